@@ -0,0 +1,70 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use vipi::{Buffer, Vec2};
+
+/// Lines of about 100 bytes making up a 100 MB file.
+const LINES: usize = 1_000_000;
+
+fn big_buffer() -> Buffer {
+    let lines = (0..LINES)
+        .map(|i| {
+            format!(
+                "{:>8} {}",
+                i,
+                "the quick brown fox jumps over the lazy dog ".repeat(2)
+            )
+        })
+        .collect();
+
+    Buffer::from_lines(lines, Vec2::default(), Vec2::new(80, 24), true)
+}
+
+fn editing(c: &mut Criterion) {
+    let mut buffer = big_buffer();
+    let middle = Vec2::new(10, LINES / 2);
+
+    c.bench_function("insert and delete a line in the middle of 100 MB", |b| {
+        b.iter(|| {
+            let end = buffer.insert_text(middle, "inserted line\n");
+            buffer.delete_text(middle, end);
+        })
+    });
+
+    c.bench_function("type a character at the end of 100 MB", |b| {
+        buffer.set_cursor(Vec2::new(0, LINES - 1));
+        b.iter(|| {
+            buffer.insert_char('x');
+            buffer.backspace();
+        })
+    });
+
+    c.bench_function("move to and read a line of 100 MB", |b| {
+        b.iter(|| {
+            buffer.goto_line(LINES / 3);
+            buffer.current_line().len()
+        })
+    });
+}
+
+fn saving(c: &mut Criterion) {
+    let mut buffer = big_buffer();
+    let path = std::env::temp_dir().join(format!("vipi-bench-{}", std::process::id()));
+    let path = path.to_str().unwrap().to_string();
+
+    c.bench_function("save 100 MB", |b| {
+        b.iter(|| buffer.write_to_file(&path).unwrap())
+    });
+
+    c.bench_function("open 100 MB", |b| {
+        b.iter(|| Buffer::from_file_path(&path, Vec2::default(), Vec2::new(80, 24)))
+    });
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = editing, saving
+}
+criterion_main!(benches);
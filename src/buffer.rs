@@ -1,30 +1,203 @@
+use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::fs::File;
-use std::io::prelude::*;
-use std::io::Write;
-
+use std::io;
+use std::io::{BufWriter, Write};
+use std::iter;
+use std::ops::{Range, RangeInclusive};
+
+use regex::Regex;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::encoding::{self, Encoding};
+use crate::fileformat::FileFormat;
+use crate::history::{end_of, Edit, History};
+use crate::lines::Lines;
+use crate::mark::Marks;
+use crate::motion::CharSearch;
+use crate::register::{Register, RegisterKind};
+use crate::search::Search;
+use crate::visual::{Selection, VisualKind};
 use crate::write_debug;
 use crate::Vec2;
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Buffer {
-    lines: Vec<String>,
+    lines: Lines,
     cursor: Vec2,
     offset: usize,
     pos: Vec2,
     size: Vec2,
     line_num: bool,
+    history: History,
+    /// File the buffer was read from or last saved as.
+    file_path: Option<String>,
+    /// Whether there are edits not written to the file yet.
+    modified: bool,
+    search: Option<Search>,
+    /// Whether matches of the last search are highlighted, until `:noh`.
+    hlsearch: bool,
+    /// Pattern being typed after `/` or `?`, highlighted instead of the last
+    /// search.
+    incsearch: Option<Regex>,
+    /// Visual mode selection, from its anchor to the cursor.
+    selection: Option<Selection>,
+    marks: Marks,
+    /// Display column `j` and `k` try to keep the cursor at, along with
+    /// where the cursor was when it was set, so that it is forgotten once
+    /// the cursor moves otherwise.
+    want: Option<(Vec2, usize)>,
+    /// Lines kept visible above and below the cursor, the `scrolloff`
+    /// option.
+    scrolloff: usize,
+    /// Lines scrolled by `Ctrl-D` and `Ctrl-U`, the `scroll` option, where 0
+    /// stands for half the window.
+    scroll: usize,
+    /// Columns between tab stops, the `tabstop` option, where 0 stands for
+    /// vim's default of 8.
+    tabstop: usize,
+    /// Encoding the file is written in, the `fileencoding` option.
+    encoding: Encoding,
+    /// Whether the file starts with a byte order mark, kept when saving.
+    bom: bool,
+    /// Whether the file looked binary when read.
+    binary: bool,
+    /// Encoding of the file whose bytes that weren't valid in it are kept
+    /// in the text as escapes, when it had any.
+    escapes: Option<Encoding>,
+    /// Line break written after each line, the `fileformat` option.
+    format: FileFormat,
+    /// Whether the last line ends with a line break, the `endofline`
+    /// option.
+    eol: bool,
+    /// Whether a missing line break at the end is added when writing, the
+    /// `fixendofline` option.
+    fixeol: bool,
+}
+
+/// Largest `tabstop`, like vim.
+pub const MAX_TABSTOP: usize = 9999;
+
+/// Pairs of brackets matched by `%`.
+const BRACKETS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
+/// Where `zt`, `zz` and `zb` put the cursor line in the window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowPosition {
+    Top,
+    Middle,
+    Bottom,
 }
 
 #[derive(PartialEq)]
-enum MoveForwardOutcome {
+enum MoveOutcome {
     Char,
     Line,
     Noop,
 }
 
+/// Kind of character, used to find where words start and end. A bigword
+/// (WORD in vim) is only delimited by blanks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CharClass {
+    Blank,
+    Punctuation,
+    Word,
+}
+
+impl CharClass {
+    pub fn of(c: char, bigword: bool) -> Self {
+        if c.is_whitespace() {
+            CharClass::Blank
+        } else if bigword || c.is_alphanumeric() || c == '_' {
+            CharClass::Word
+        } else {
+            CharClass::Punctuation
+        }
+    }
+}
+
+/// Byte offset where the grapheme cluster after the one at byte `x` of
+/// `line` starts, or the length of `line` when there is none.
+fn next_boundary(line: &str, x: usize) -> usize {
+    line.grapheme_indices(true)
+        .map(|(i, g)| i + g.len())
+        .find(|end| *end > x)
+        .unwrap_or(line.len())
+}
+
+/// Byte offset where the grapheme cluster before byte `x` of `line` starts,
+/// or 0 when there is none.
+fn prev_boundary(line: &str, x: usize) -> usize {
+    line.grapheme_indices(true)
+        .map(|(i, _)| i)
+        .take_while(|i| *i < x)
+        .last()
+        .unwrap_or(0)
+}
+
+/// Byte offset where the grapheme cluster containing byte `x` of `line`
+/// starts, or the length of `line` past its end.
+fn boundary_at(line: &str, x: usize) -> usize {
+    if x >= line.len() {
+        return line.len();
+    }
+
+    line.grapheme_indices(true)
+        .map(|(i, _)| i)
+        .take_while(|i| *i <= x)
+        .last()
+        .unwrap_or(0)
+}
+
+/// Cells showing the grapheme cluster `g` at display column `vcol`, each with
+/// the text written to it and its width, and whether they show a control
+/// character. Tabs are expanded up to the next tab stop and control
+/// characters shown like `^M`, or like `<85>` outside ASCII and for bytes
+/// of the file that aren't valid UTF-8, so that they can be split across
+/// rows.
+fn cells(
+    g: &str,
+    vcol: usize,
+    tabstop: usize,
+) -> (impl Iterator<Item = (Cow<'_, str>, usize)>, bool) {
+    let mut chars = g.chars();
+    let (cells, blanks, control) = match (chars.next(), chars.next()) {
+        (Some('\t'), None) => (vec![], tabstop - vcol % tabstop, false),
+        (Some(c), None) if c.is_control() || encoding::unescape(c).is_some() => {
+            let shown = if let Some(b) = encoding::unescape(c) {
+                format!("<{:02x}>", b)
+            } else if c.is_ascii_control() {
+                format!("^{}", (c as u8 ^ 0x40) as char)
+            } else {
+                format!("<{:02x}>", c as u32)
+            };
+
+            let cells = shown.chars().map(|c| (c.to_string().into(), 1)).collect();
+            (cells, 0, true)
+        }
+        _ => (vec![(g.into(), g.width().max(1))], 0, false),
+    };
+
+    // the blanks of a tab are made as they are drawn, as there may be
+    // thousands of them
+    let blanks = iter::repeat_n((Cow::Borrowed(" "), 1), blanks);
+    (cells.into_iter().chain(blanks), control)
+}
+
+/// Display columns taken by the grapheme cluster `g` at display column
+/// `vcol`.
+fn width(g: &str, vcol: usize, tabstop: usize) -> usize {
+    cells(g, vcol, tabstop).0.map(|(_, width)| width).sum()
+}
+
 impl Buffer {
     pub fn from_lines(lines: Vec<String>, pos: Vec2, size: Vec2, line_num: bool) -> Self {
+        Self::from_text(Lines::from(lines), pos, size, line_num)
+    }
+
+    fn from_text(lines: Lines, pos: Vec2, size: Vec2, line_num: bool) -> Self {
         Self {
             lines,
             cursor: Vec2::default(),
@@ -32,22 +205,118 @@ impl Buffer {
             size,
             pos,
             line_num,
+            history: History::default(),
+            file_path: None,
+            modified: false,
+            search: None,
+            hlsearch: false,
+            incsearch: None,
+            selection: None,
+            marks: Marks::default(),
+            want: None,
+            scrolloff: 0,
+            scroll: 0,
+            tabstop: 0,
+            encoding: Encoding::default(),
+            bom: false,
+            binary: false,
+            escapes: None,
+            format: FileFormat::default(),
+            eol: true,
+            fixeol: true,
         }
     }
 
     pub fn from_file_path(file_path: &str, pos: Vec2, size: Vec2) -> Self {
-        let lines = if let Ok(mut file) = File::open(file_path) {
-            let mut contents = String::new();
-            file.read_to_string(&mut contents)
-                .unwrap_or_else(|_| panic!("Failed to read lines from file: {}", file_path));
-            contents.split('\n').map(|s| s.to_string()).collect()
+        let decoded = if let Ok(bytes) = std::fs::read(file_path) {
+            Encoding::decode(bytes)
         } else {
             File::create(file_path)
                 .unwrap_or_else(|_| panic!("Could neither open or create file: {}", file_path));
-            vec![]
+            Encoding::decode(Vec::new())
+        };
+
+        // line breaks of binary files are kept as they are
+        let format = if decoded.binary {
+            FileFormat::Unix
+        } else {
+            FileFormat::detect(&decoded.text)
         };
+        let (text, eol) = format.strip(decoded.text);
+
+        let mut buffer = Self::from_text(Lines::from(text.as_str()), pos, size, true);
+        buffer.file_path = Some(file_path.to_string());
+        buffer.encoding = decoded.encoding;
+        buffer.bom = decoded.bom;
+        buffer.binary = decoded.binary;
+        buffer.escapes = decoded.escaped.then_some(decoded.encoding);
+        buffer.format = format;
+        // like vim, only a file whose last line has no line break is noeol,
+        // and it isn't fixed so that it's written back as it was read, like
+        // a file with nothing but a line break
+        buffer.eol = eol || text.is_empty();
+        buffer.fixeol = eol != text.is_empty();
+        buffer
+    }
+
+    pub fn file_path(&self) -> Option<&str> {
+        self.file_path.as_deref()
+    }
+
+    pub fn set_file_path(&mut self, file_path: &str) {
+        self.file_path = Some(file_path.to_string());
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Sets the encoding the file is written in, which is a change to it.
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        if encoding != self.encoding {
+            self.encoding = encoding;
+            self.modified = true;
+        }
+    }
+
+    pub fn is_binary(&self) -> bool {
+        self.binary
+    }
 
-        Self::from_lines(lines, pos, size, true)
+    pub fn file_format(&self) -> FileFormat {
+        self.format
+    }
+
+    /// Sets the line break the file is written with, which is a change to
+    /// it.
+    pub fn set_file_format(&mut self, format: FileFormat) {
+        if format != self.format {
+            self.format = format;
+            self.modified = true;
+        }
+    }
+
+    pub fn eol(&self) -> bool {
+        self.eol
+    }
+
+    pub fn set_eol(&mut self, eol: bool) {
+        if eol != self.eol {
+            self.eol = eol;
+            self.modified = true;
+        }
+    }
+
+    pub fn fixeol(&self) -> bool {
+        self.fixeol
+    }
+
+    pub fn set_fixeol(&mut self, fixeol: bool) {
+        self.fixeol = fixeol;
     }
 
     pub fn render(&self, term: &mut impl Write) {
@@ -57,13 +326,12 @@ impl Buffer {
         let mut col = 0;
         let mut cursor: Option<Vec2> = None;
 
-        for (y, line) in self
-            .lines
-            .iter()
-            .skip(self.offset)
-            .take(self.size.y)
-            .enumerate()
-        {
+        // like vim's matchparen, only brackets in view are matched
+        let matching = self.matching_bracket(self.cursor, self.offset..=self.last_visible_line());
+        let selection = self.selection.map(|selection| selection.range(self));
+
+        let visible = (self.offset..self.lines.len()).take(self.size.y);
+        for (y, line) in visible.map(|y| self.lines.line(y)).enumerate() {
             let line_num = y + self.offset + 1;
 
             let relative_line_num = match self.cursor.y.cmp(&(y + self.offset)) {
@@ -96,42 +364,81 @@ impl Buffer {
                 }
             }
 
-            for (x, c) in line.chars().enumerate() {
-                if col >= self.size.x {
-                    row += 1;
-                    col = 0;
-                }
+            let selected = selection.and_then(|range| range.columns(self, y + self.offset));
+            let highlighted: Vec<_> = self
+                .highlight()
+                .map(|regex| regex.find_iter(&line).map(|m| m.range()).collect())
+                .unwrap_or_default();
+
+            let mut vcol = 0;
+            for (i, g) in line.grapheme_indices(true) {
+                let (cells, control) = cells(g, vcol, self.tabstop());
+                vcol += width(g, vcol, self.tabstop());
+
+                let selected = selected.is_some_and(|(start, end)| start <= i && i < end);
+                let (style, reset) =
+                    if selected || highlighted.iter().any(|range| range.contains(&i)) {
+                        (
+                            termion::style::Invert.to_string(),
+                            termion::style::Reset.to_string(),
+                        )
+                    } else if matching == Some(Vec2::new(i, y + self.offset)) {
+                        (
+                            termion::color::Bg(termion::color::Cyan).to_string(),
+                            termion::style::Reset.to_string(),
+                        )
+                    } else if control {
+                        (
+                            termion::color::Fg(termion::color::Blue).to_string(),
+                            termion::style::Reset.to_string(),
+                        )
+                    } else {
+                        (String::new(), String::new())
+                    };
+
+                for (n, (cell, width)) in cells.enumerate() {
+                    // a wide character that doesn't fit in the row starts
+                    // the next one
+                    if col + width > self.size.x {
+                        row += 1;
+                        col = 0;
+                    }
 
-                if y + self.offset == self.cursor.y && x == self.cursor.x {
-                    cursor = Some(Vec2::new(col, row));
-                }
+                    if n == 0 && y + self.offset == self.cursor.y && i == self.cursor.x {
+                        cursor = Some(Vec2::new(col, row));
+                    }
+
+                    if n == 0 && i == 0 && self.line_num {
+                        write!(
+                            term,
+                            "{}{} ",
+                            termion::cursor::Goto(
+                                (self.pos.x + col + 1) as u16,
+                                (self.pos.y + row + 1) as u16
+                            ),
+                            line_num_str
+                        )
+                        .unwrap();
+                    }
 
-                if x == 0 && self.line_num {
                     write!(
                         term,
-                        "{}{} ",
+                        "{}{}{}{}",
                         termion::cursor::Goto(
-                            (self.pos.x + col + 1) as u16,
+                            ((if self.line_num { line_num_size + 1 } else { 0 })
+                                + self.pos.x
+                                + col
+                                + 1) as u16,
                             (self.pos.y + row + 1) as u16
                         ),
-                        line_num_str
+                        style,
+                        cell,
+                        reset
                     )
                     .unwrap();
-                }
-
-                write!(
-                    term,
-                    "{}{}",
-                    termion::cursor::Goto(
-                        ((if self.line_num { line_num_size + 1 } else { 0 }) + self.pos.x + col + 1)
-                            as u16,
-                        (self.pos.y + row + 1) as u16
-                    ),
-                    c
-                )
-                .unwrap();
 
-                col += 1;
+                    col += width;
+                }
             }
 
             if y + self.offset == self.cursor.y && cursor.is_none() {
@@ -162,104 +469,284 @@ impl Buffer {
         }
     }
 
-    pub fn current_line(&self) -> &String {
-        &self.lines[self.cursor.y]
+    fn highlight(&self) -> Option<&Regex> {
+        match (&self.incsearch, &self.search) {
+            (Some(regex), _) => Some(regex),
+            (None, Some(search)) if self.hlsearch => Some(&search.regex),
+            _ => None,
+        }
+    }
+
+    pub fn cursor(&self) -> Vec2 {
+        self.cursor
+    }
+
+    /// Moves the cursor to `cursor`, clamped to the buffer contents, and
+    /// scrolls to keep it visible.
+    pub fn set_cursor(&mut self, cursor: Vec2) {
+        self.cursor.y = cursor.y.min(self.lines.len() - 1);
+        self.cursor.x = cursor.x;
+        self.clamp_cursor(false);
+        self.scroll_to_cursor();
+    }
+
+    /// Like `set_cursor`, but allows the cursor one past the end of the
+    /// line, where Insert mode types.
+    pub fn set_insert_cursor(&mut self, cursor: Vec2) {
+        self.set_cursor(cursor);
+        self.cursor.x = cursor.x;
+        self.clamp_cursor(true);
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn line_len(&self, y: usize) -> usize {
+        self.lines.line_len(y)
+    }
+
+    pub fn line(&self, y: usize) -> Cow<'_, str> {
+        self.lines.line(y)
+    }
+
+    pub fn current_line(&self) -> Cow<'_, str> {
+        self.lines.line(self.cursor.y)
+    }
+
+    // Columns, like the cursor's, are byte offsets that always start a
+    // grapheme cluster, so that a letter with combining marks or an emoji
+    // made of several code points is moved over and edited as a whole.
+
+    /// Column of the grapheme cluster after the one at `pos`, or the end of
+    /// the line.
+    pub fn next_grapheme(&self, pos: Vec2) -> usize {
+        next_boundary(&self.lines.line(pos.y), pos.x)
+    }
+
+    /// Column of the grapheme cluster before `pos`, or 0 at the start of the
+    /// line.
+    pub fn prev_grapheme(&self, pos: Vec2) -> usize {
+        prev_boundary(&self.lines.line(pos.y), pos.x)
+    }
+
+    /// Column of the grapheme cluster numbered `index` on line `y`, or the
+    /// end of the line past its last one.
+    pub fn grapheme_to_byte(&self, y: usize, index: usize) -> usize {
+        let line = self.lines.line(y);
+        line.grapheme_indices(true)
+            .nth(index)
+            .map_or(line.len(), |(i, _)| i)
+    }
+
+    /// Number of grapheme clusters before column `pos.x`.
+    pub fn byte_to_grapheme(&self, pos: Vec2) -> usize {
+        self.lines
+            .line(pos.y)
+            .grapheme_indices(true)
+            .take_while(|(i, _)| *i < pos.x)
+            .count()
+    }
+
+    /// Column of the character numbered `index` on line `y`, or the end of
+    /// the line past its last one.
+    pub fn char_to_byte(&self, y: usize, index: usize) -> usize {
+        let line = self.lines.line(y);
+        line.char_indices()
+            .nth(index)
+            .map_or(line.len(), |(i, _)| i)
+    }
+
+    /// Number of characters before column `pos.x`.
+    pub fn byte_to_char(&self, pos: Vec2) -> usize {
+        self.lines
+            .line(pos.y)
+            .char_indices()
+            .take_while(|(i, _)| *i < pos.x)
+            .count()
+    }
+
+    /// Display column where the grapheme cluster at `pos` starts, counting
+    /// the columns tabs, wide and control characters are shown in.
+    pub fn byte_to_col(&self, pos: Vec2) -> usize {
+        let tabstop = self.tabstop();
+        let mut vcol = 0;
+        for (i, g) in self.lines.line(pos.y).grapheme_indices(true) {
+            if i >= pos.x {
+                break;
+            }
+            vcol += width(g, vcol, tabstop);
+        }
+        vcol
+    }
+
+    /// Column of the grapheme cluster shown at display column `col` of line
+    /// `y`, or the end of the line past its last one.
+    pub fn col_to_byte(&self, y: usize, col: usize) -> usize {
+        let line = self.lines.line(y);
+        let tabstop = self.tabstop();
+        let mut vcol = 0;
+        for (i, g) in line.grapheme_indices(true) {
+            vcol += width(g, vcol, tabstop);
+            if vcol > col {
+                return i;
+            }
+        }
+        line.len()
+    }
+
+    /// Column of line `y` where inserted text shows from display column
+    /// `col`, or right after the character covering it. A shorter line is
+    /// padded with spaces up to it.
+    pub fn pad_to_col(&mut self, y: usize, col: usize) -> usize {
+        let len = self.line_len(y);
+        let width = self.byte_to_col(Vec2::new(len, y));
+        if width < col {
+            self.insert_text(Vec2::new(len, y), &" ".repeat(col - width));
+            return len + col - width;
+        }
+
+        let x = self.col_to_byte(y, col);
+        if self.byte_to_col(Vec2::new(x, y)) < col {
+            self.next_grapheme(Vec2::new(x, y))
+        } else {
+            x
+        }
     }
 
-    fn move_forward(&mut self) -> MoveForwardOutcome {
+    fn move_forward(&mut self) -> MoveOutcome {
         let line = self.current_line();
-        if self.cursor.x + 1 < line.len() {
-            self.cursor.x += 1;
-            MoveForwardOutcome::Char
+        let next = next_boundary(&line, self.cursor.x);
+        if next < line.len() {
+            self.cursor.x = next;
+            MoveOutcome::Char
         } else if self.cursor.y + 1 < self.lines.len() {
             self.move_cursor_down();
             self.cursor.x = 0;
-            MoveForwardOutcome::Line
+            MoveOutcome::Line
         } else {
-            MoveForwardOutcome::Noop
+            MoveOutcome::Noop
         }
     }
 
     pub fn clamp_cursor(&mut self, allow_one_off: bool) {
         let line = self.current_line();
 
-        let mut max = line.len();
-        if !allow_one_off && max > 0 {
-            max -= 1;
-        }
+        let max = if allow_one_off {
+            line.len()
+        } else {
+            prev_boundary(&line, line.len())
+        };
 
-        self.cursor.x = self.cursor.x.clamp(0, max);
+        self.cursor.x = boundary_at(&line, self.cursor.x.min(max));
     }
 
     pub fn move_cursor_left(&mut self) {
-        if self.cursor.x > 0 {
-            self.cursor.x -= 1;
-        }
+        self.cursor.x = self.prev_grapheme(self.cursor);
     }
 
     pub fn move_cursor_down(&mut self) {
-        let is_edge = self.cursor.y - self.offset == self.size.y - 1;
+        let want = self.wanted_col();
         if self.cursor.y + 1 < self.lines.len() {
             self.cursor.y += 1;
-
-            if is_edge {
-                self.offset += 1;
-            }
+            self.scroll_to_cursor();
         }
 
-        self.clamp_cursor(false);
+        self.move_cursor_to_col(want);
     }
 
     pub fn move_cursor_up(&mut self) {
-        let is_edge = self.cursor.y - self.offset == 0;
+        let want = self.wanted_col();
         if self.cursor.y > 0 {
             self.cursor.y -= 1;
+            self.scroll_to_cursor();
+        }
 
-            if is_edge {
-                self.offset -= 1;
-            }
+        self.move_cursor_to_col(want);
+    }
+
+    /// Display column `j` and `k` keep the cursor at, which is the one it
+    /// is shown at unless they moved it there.
+    fn wanted_col(&self) -> usize {
+        match self.want {
+            Some((pos, col)) if pos == self.cursor => col,
+            _ => self.byte_to_col(self.cursor),
         }
+    }
 
+    fn move_cursor_to_col(&mut self, col: usize) {
+        self.cursor.x = self.col_to_byte(self.cursor.y, col);
         self.clamp_cursor(false);
+        self.want = Some((self.cursor, col));
     }
 
     pub fn move_cursor_right(&mut self, allow_one_off: bool) {
-        self.cursor.x += 1;
+        self.cursor.x = self.next_grapheme(self.cursor);
         self.clamp_cursor(allow_one_off);
     }
 
-    pub fn delete_char(&mut self) {
-        let line = &mut self.lines[self.cursor.y];
-        if line.len() > self.cursor.x {
-            line.remove(self.cursor.x);
-            self.clamp_cursor(false);
+    pub fn move_cursor_first_character(&mut self) {
+        self.cursor.x = 0;
+    }
+
+    /// Moves to the last character, where `j` and `k` keep the cursor on
+    /// the lines they move to.
+    pub fn move_cursor_last_character(&mut self) {
+        self.move_cursor_to_col(usize::MAX);
+    }
+
+    pub fn move_cursor_first_non_blank(&mut self) {
+        self.cursor.x = self.first_non_blank(self.cursor.y);
+        self.clamp_cursor(false);
+    }
+
+    /// Column of the first non-blank character of line `y`, or its length
+    /// when it only has blanks.
+    pub fn first_non_blank(&self, y: usize) -> usize {
+        let line = self.lines.line(y);
+        line.find(|c: char| !c.is_whitespace())
+            .unwrap_or(line.len())
+    }
+
+    pub fn char_at(&self, pos: Vec2) -> Option<char> {
+        if pos.y >= self.lines.len() {
+            return None;
         }
+        self.lines.line(pos.y).get(pos.x..)?.chars().next()
     }
 
-    pub fn move_cursor_first_character(&mut self) {
-        self.cursor.x = 0;
+    fn move_backward(&mut self) -> MoveOutcome {
+        if self.cursor.x > 0 {
+            self.cursor.x = self.prev_grapheme(self.cursor);
+            MoveOutcome::Char
+        } else if self.cursor.y > 0 {
+            self.move_cursor_up();
+            self.move_cursor_last_character();
+            MoveOutcome::Line
+        } else {
+            MoveOutcome::Noop
+        }
     }
 
-    fn is_at_whitespace(&self) -> bool {
-        self.current_line()
-            .chars()
-            .nth(self.cursor.x)
-            .map(|c| c.is_whitespace())
-            .unwrap_or(false)
+    /// Class of the character at `pos`, where the end of a line counts as a
+    /// blank.
+    pub fn class_at(&self, pos: Vec2, bigword: bool) -> CharClass {
+        self.char_at(pos)
+            .map_or(CharClass::Blank, |c| CharClass::of(c, bigword))
+    }
+
+    fn class_at_cursor(&self, bigword: bool) -> CharClass {
+        self.class_at(self.cursor, bigword)
     }
 
-    fn is_at_alphanumeric(&self) -> bool {
-        self.current_line()
-            .chars()
-            .nth(self.cursor.x)
-            .map(|c| c.is_alphanumeric())
-            .unwrap_or(false)
+    fn is_at_whitespace(&self) -> bool {
+        self.char_at(self.cursor).is_some_and(char::is_whitespace)
     }
 
-    pub fn word_forward(&mut self) {
+    pub fn word_forward(&mut self, bigword: bool) {
         let mut moved_from_empty_line = false;
         if self.current_line().is_empty() {
-            if self.move_forward() == MoveForwardOutcome::Noop {
+            if self.move_forward() == MoveOutcome::Noop {
                 return;
             }
 
@@ -267,660 +754,2812 @@ impl Buffer {
         }
 
         if !moved_from_empty_line {
-            if self.is_at_whitespace() {
-                while self.is_at_whitespace() {
-                    if self.move_forward() != MoveForwardOutcome::Char {
-                        break;
-                    }
-                }
-            } else if self.is_at_alphanumeric() {
-                while self.is_at_alphanumeric() {
-                    if self.move_forward() != MoveForwardOutcome::Char {
-                        break;
-                    }
-                }
-            } else {
-                while !self.is_at_alphanumeric() {
-                    if self.move_forward() != MoveForwardOutcome::Char {
-                        break;
-                    }
+            let class = self.class_at_cursor(bigword);
+            while self.class_at_cursor(bigword) == class {
+                if self.move_forward() != MoveOutcome::Char {
+                    break;
                 }
             }
         }
 
         if !self.current_line().is_empty() {
             while self.is_at_whitespace() {
-                if self.move_forward() == MoveForwardOutcome::Noop {
+                if self.move_forward() == MoveOutcome::Noop {
                     break;
                 }
             }
         }
     }
 
-    pub fn join_line(&mut self) {
-        if let Some(next_line) = self.lines.get(self.cursor.y + 1) {
-            if self.current_line().is_empty() {
-                self.lines.remove(self.cursor.y);
-                self.cursor.x = self.current_line().len() - 1;
-            } else {
-                let current_line = self.current_line().clone();
-                self.lines[self.cursor.y] = format!("{} {}", current_line, next_line);
-                self.lines.remove(self.cursor.y + 1);
-                self.cursor.x = current_line.len();
+    pub fn word_backward(&mut self, bigword: bool) {
+        if self.move_backward() == MoveOutcome::Noop {
+            return;
+        }
+
+        while self.class_at_cursor(bigword) == CharClass::Blank {
+            if self.current_line().is_empty() || self.move_backward() == MoveOutcome::Noop {
+                return;
+            }
+        }
+
+        let class = self.class_at_cursor(bigword);
+        while self.cursor.x > 0 {
+            let x = self.prev_grapheme(self.cursor);
+            if self.class_at(Vec2::new(x, self.cursor.y), bigword) != class {
+                break;
             }
+            self.cursor.x = x;
         }
     }
 
-    pub fn insert_char(&mut self, c: char) {
-        let line = &mut self.lines[self.cursor.y];
-        if self.cursor.x < line.len() {
-            line.insert(self.cursor.x, c);
-        } else {
-            line.push(c);
+    pub fn word_end(&mut self, bigword: bool) {
+        if self.move_forward() == MoveOutcome::Noop {
+            return;
         }
 
-        self.cursor.x += 1;
-    }
+        while self.class_at_cursor(bigword) == CharClass::Blank {
+            if self.move_forward() == MoveOutcome::Noop {
+                return;
+            }
+        }
 
-    pub fn insert_line_after_cursor(&mut self, line: String) {
-        if self.cursor.y + 1 < self.lines.len() {
-            self.lines.insert(self.cursor.y + 1, line);
-        } else {
-            self.lines.push(line);
+        let class = self.class_at_cursor(bigword);
+        loop {
+            let x = self.next_grapheme(self.cursor);
+            if self.class_at(Vec2::new(x, self.cursor.y), bigword) != class {
+                break;
+            }
+            self.cursor.x = x;
         }
     }
 
-    // TODO: improve performance
-    pub fn write_to_file(&self, file_path: &str) {
-        let mut file_contents = String::new();
-        for (i, line) in self.lines.iter().enumerate() {
-            if i != 0 {
-                file_contents.push('\n');
+    pub fn word_end_backward(&mut self, bigword: bool) {
+        let class = self.class_at_cursor(bigword);
+
+        let mut outcome = self.move_backward();
+        if class != CharClass::Blank {
+            while outcome == MoveOutcome::Char && self.class_at_cursor(bigword) == class {
+                outcome = self.move_backward();
             }
-            file_contents.push_str(line);
         }
 
-        // TODO: report error to user instead of panic
-        std::fs::write(file_path, file_contents).unwrap();
+        if outcome == MoveOutcome::Noop {
+            return;
+        }
+
+        while self.class_at_cursor(bigword) == CharClass::Blank {
+            if self.current_line().is_empty() || self.move_backward() == MoveOutcome::Noop {
+                return;
+            }
+        }
     }
 
-    pub fn backspace(&mut self) {
-        let line = self.current_line().clone();
+    /// Moves to the `count`th occurrence of the searched character in the
+    /// current line. The cursor doesn't move when there aren't enough
+    /// occurrences, in which case `false` is returned.
+    pub fn find_char(&mut self, search: CharSearch, count: usize, repeat: bool) -> bool {
+        let line = self.current_line();
         let x = self.cursor.x;
-        let y = self.cursor.y;
 
-        if x == 0 && y > 0 {
-            self.move_cursor_up();
-            self.join_line();
+        // a repeated `t` would stay put when right before its character,
+        // so it looks past that character
+        let (after, before) = if search.till && repeat {
+            (next_boundary(&line, x), prev_boundary(&line, x))
         } else {
-            self.move_cursor_left();
-        }
+            (x, x)
+        };
 
-        self.delete_char();
+        let matches = line
+            .char_indices()
+            .filter(|(_, c)| *c == search.c)
+            .map(|(i, _)| i);
+
+        let found = if search.forward {
+            matches.filter(|i| *i > after).nth(count - 1).map(|i| {
+                if search.till {
+                    prev_boundary(&line, i)
+                } else {
+                    i
+                }
+            })
+        } else {
+            matches
+                .rev()
+                .filter(|i| *i < before)
+                .nth(count - 1)
+                .map(|i| {
+                    if search.till {
+                        next_boundary(&line, i)
+                    } else {
+                        i
+                    }
+                })
+        };
 
-        if x >= line.len() {
-            self.move_cursor_right(true);
+        match found {
+            Some(x) => {
+                self.cursor.x = x;
+                true
+            }
+            None => false,
         }
     }
 
-    pub fn insert_new_line(&mut self) {
-        let line = self.current_line().clone();
-        let (before_cursor, from_cursor) = line.split_at(self.cursor.x);
-
-        self.lines[self.cursor.y] = before_cursor.to_string();
-        self.insert_line_after_cursor(from_cursor.to_string());
-        self.cursor.y += 1;
-        self.cursor.x = 0;
+    pub fn selection(&self) -> Option<Selection> {
+        self.selection
     }
 
-    pub fn write_debug(&self) {
-        write_debug("##########################");
-        write_debug(&format!("offset {:?}\n", self.offset));
-        write_debug(&format!("cursor {:?}\n", self.cursor));
-        write_debug(&format!("current_line {:?}\n", self.current_line()));
-        write_debug("##########################");
+    /// Selects from the cursor, or keeps the current anchor when already
+    /// selecting.
+    pub fn start_selection(&mut self, kind: VisualKind) {
+        let anchor = self
+            .selection
+            .map_or(self.cursor, |selection| selection.anchor);
+        self.selection = Some(Selection { anchor, kind });
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 
-    #[test]
-    fn move_cursor_down() {
-        let mut buffer = Buffer {
-            lines: vec!["line1".to_string(), "line2".to_string()],
-            cursor: Vec2::default(),
-            offset: 0,
-            pos: Vec2::default(),
-            size: Vec2::new(100, 100),
-            line_num: true,
-        };
+    pub fn marks(&self) -> &Marks {
+        &self.marks
+    }
 
-        buffer.move_cursor_down();
+    pub fn marks_mut(&mut self) -> &mut Marks {
+        &mut self.marks
+    }
 
-        assert_eq!(buffer.cursor, Vec2::new(0, 1));
+    /// Moves to mark `name`, or to the first non-blank character of its line
+    /// when `linewise`. Returns `false` when the mark isn't set.
+    pub fn goto_mark(&mut self, name: char, linewise: bool) -> bool {
+        match self.marks.get(name) {
+            Some(pos) if linewise => self.goto_line(pos.y),
+            Some(pos) => self.set_cursor(pos),
+            None => return false,
+        }
+        true
     }
 
-    #[test]
-    fn move_cursor_down_offset() {
-        let mut buffer = Buffer {
-            lines: vec!["line1".to_string(), "line2".to_string()],
-            cursor: Vec2::default(),
-            size: Vec2::new(100, 1),
-            pos: Vec2::default(),
-            offset: 0,
-            line_num: true,
+    /// Moves `count` entries back in the jump list, or forward when
+    /// `forward` is set. Returns `false` when there are no such entries.
+    pub fn walk_jumps(&mut self, forward: bool, count: usize) -> bool {
+        let pos = if forward {
+            self.marks.newer_jump(count)
+        } else {
+            self.marks.older_jump(self.cursor, count)
         };
 
-        buffer.move_cursor_down();
-
-        assert_eq!(buffer.cursor, Vec2::new(0, 1));
-        assert_eq!(buffer.offset, 1);
+        match pos {
+            Some(pos) => {
+                self.set_cursor(pos);
+                true
+            }
+            None => false,
+        }
     }
 
-    #[test]
-    fn move_cursor_down_clamp_x() {
-        let mut buffer = Buffer {
-            lines: vec!["big line".to_string(), "small".to_string()],
-            cursor: Vec2::new(7, 0),
-            size: Vec2::new(100, 100),
-            pos: Vec2::default(),
-            offset: 0,
-            line_num: true,
+    /// Moves `count` entries back in the change list, or forward when
+    /// `forward` is set.
+    pub fn walk_changes(&mut self, forward: bool, count: usize) -> Result<(), String> {
+        let pos = if forward {
+            self.marks.newer_change(count)?
+        } else {
+            self.marks.older_change(count)?
         };
+        self.set_cursor(pos);
+        Ok(())
+    }
 
-        buffer.move_cursor_down();
+    pub fn set_selection(&mut self, selection: Selection) {
+        self.selection = Some(selection);
+    }
 
-        assert_eq!(buffer.cursor, Vec2::new(4, 1));
+    pub fn clear_selection(&mut self) {
+        self.selection = None;
     }
 
-    #[test]
+    /// Moves the cursor to the other end of the selection, or only to its
+    /// other display column when `columns_only` is set.
+    pub fn swap_selection_ends(&mut self, columns_only: bool) {
+        if let Some(mut selection) = self.selection {
+            let cursor = self.cursor;
+            if columns_only {
+                let (anchor_col, cursor_col) =
+                    (self.byte_to_col(selection.anchor), self.byte_to_col(cursor));
+                self.cursor.x = self.col_to_byte(cursor.y, anchor_col);
+                selection.anchor.x = self.col_to_byte(selection.anchor.y, cursor_col);
+            } else {
+                self.cursor = selection.anchor;
+                selection.anchor = cursor;
+            }
+            self.selection = Some(selection);
+            self.scroll_to_cursor();
+        }
+    }
+
+    pub fn search(&self) -> Option<&Search> {
+        self.search.as_ref()
+    }
+
+    /// Makes `search` the pattern repeated by `n` and `N`, and highlights its
+    /// matches.
+    pub fn set_search(&mut self, search: Search) {
+        self.search = Some(search);
+        self.hlsearch = true;
+    }
+
+    pub fn clear_hlsearch(&mut self) {
+        self.hlsearch = false;
+    }
+
+    pub fn set_incsearch(&mut self, regex: Option<Regex>) {
+        self.incsearch = regex;
+    }
+
+    /// Position of the first match after `from`, or before it when searching
+    /// backward, wrapping around the end of the buffer.
+    pub fn find_match(&self, regex: &Regex, from: Vec2, forward: bool) -> Option<Vec2> {
+        let len = self.lines.len();
+
+        for i in 0..=len {
+            let y = if forward {
+                (from.y + i) % len
+            } else {
+                (from.y + len * 2 - i) % len
+            };
+
+            let line = self.lines.line(y);
+            let mut starts = regex.find_iter(&line).map(|m| m.start());
+            let x = match (forward, i) {
+                (true, 0) => starts.find(|x| *x > from.x),
+                (true, i) if i == len => starts.find(|x| *x <= from.x),
+                (true, _) => starts.next(),
+                (false, 0) => starts.filter(|x| *x < from.x).last(),
+                (false, i) if i == len => starts.filter(|x| *x >= from.x).last(),
+                (false, _) => starts.last(),
+            };
+
+            if let Some(x) = x {
+                return Some(Vec2::new(x, y));
+            }
+        }
+
+        None
+    }
+
+    /// Moves to the next match of the last search, in the opposite direction
+    /// when `reverse` is set. Returns `false` when there is no match.
+    pub fn search_next(&mut self, reverse: bool) -> bool {
+        let found = self.search.as_ref().and_then(|search| {
+            self.find_match(&search.regex, self.cursor, search.forward != reverse)
+        });
+
+        match found {
+            Some(pos) => {
+                self.hlsearch = true;
+                self.set_cursor(pos);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Keyword under or after the cursor, and the column it starts at.
+    pub fn word_under_cursor(&self) -> Option<(usize, String)> {
+        let line = self.current_line();
+        let chars: Vec<(usize, char)> = line.char_indices().collect();
+        let is_word = |i: &usize| CharClass::of(chars[*i].1, false) == CharClass::Word;
+
+        let from = chars.iter().position(|(x, _)| *x >= self.cursor.x)?;
+        let start = (from..chars.len()).find(is_word)?;
+        let start = (0..start).rev().take_while(is_word).last().unwrap_or(start);
+        let end = (start..chars.len())
+            .find(|i| !is_word(i))
+            .map_or(line.len(), |i| chars[i].0);
+
+        let start = chars[start].0;
+        Some((start, line[start..end].to_string()))
+    }
+
+    /// Position of the bracket matching the one at `pos`, looking no
+    /// further than `lines`. Brackets of the same kind in between must be
+    /// balanced.
+    pub fn matching_bracket(&self, pos: Vec2, lines: RangeInclusive<usize>) -> Option<Vec2> {
+        let c = self.lines.line(pos.y).get(pos.x..)?.chars().next()?;
+        let (bracket, other, forward) = BRACKETS.iter().find_map(|&(open, close)| {
+            if c == open {
+                Some((open, close, true))
+            } else if c == close {
+                Some((close, open, false))
+            } else {
+                None
+            }
+        })?;
+
+        let mut depth = 0;
+        for (pos, c) in self.chars_around(pos, forward, lines) {
+            if c == bracket {
+                depth += 1;
+            } else if c == other {
+                if depth == 0 {
+                    return Some(pos);
+                }
+                depth -= 1;
+            }
+        }
+
+        None
+    }
+
+    /// Position `%` jumps to: the bracket matching the one under the
+    /// cursor, or the first one after it on its line.
+    pub fn match_pair(&self) -> Option<Vec2> {
+        let line = self.current_line();
+        let from = self.cursor.x.min(line.len());
+        let x = from
+            + line[from..].find(|c| {
+                BRACKETS
+                    .iter()
+                    .any(|&(open, close)| c == open || c == close)
+            })?;
+
+        self.matching_bracket(Vec2::new(x, self.cursor.y), 0..=self.lines.len() - 1)
+    }
+
+    /// Characters after `pos` up to the end of `lines`, or before it down to
+    /// their start when not `forward`, with their positions.
+    pub fn chars_around(
+        &self,
+        pos: Vec2,
+        forward: bool,
+        lines: RangeInclusive<usize>,
+    ) -> impl Iterator<Item = (Vec2, char)> + '_ {
+        self.lines
+            .chars_around(pos, forward)
+            .take_while(move |(pos, _)| lines.contains(&pos.y))
+    }
+
+    /// Moves to the first non-blank character of line `y`, or of the last
+    /// line when `y` is past it.
+    pub fn goto_line(&mut self, y: usize) {
+        self.cursor.y = y.min(self.lines.len() - 1);
+        self.move_cursor_first_non_blank();
+        self.scroll_to_cursor();
+    }
+
+    pub fn join_line(&mut self) {
+        let y = self.cursor.y;
+        if y + 1 >= self.lines.len() {
+            return;
+        }
+
+        let len = self.current_line().len();
+        if len == 0 {
+            self.delete_text(Vec2::new(0, y), Vec2::new(0, y + 1));
+            self.move_cursor_last_character();
+        } else {
+            self.delete_text(Vec2::new(len, y), Vec2::new(0, y + 1));
+            self.insert_text(Vec2::new(len, y), " ");
+            self.cursor.x = len;
+        }
+    }
+
+    pub fn insert_char(&mut self, c: char) {
+        self.cursor.x = self.cursor.x.min(self.current_line().len());
+        self.insert_text(self.cursor, &c.to_string());
+
+        self.cursor.x += c.len_utf8();
+    }
+
+    /// Inserts `text` at the cursor and moves past it.
+    pub fn insert_str(&mut self, text: &str) {
+        self.cursor.x = self.cursor.x.min(self.current_line().len());
+        let end = self.insert_text(self.cursor, text);
+        self.set_insert_cursor(end);
+    }
+
+    /// Deletes the character under the cursor, or the line break at the end
+    /// of the line, as done by Delete in Insert mode.
+    pub fn delete_char(&mut self) {
+        let x = self.cursor.x.min(self.current_line().len());
+        let y = self.cursor.y;
+
+        match self.next_grapheme(Vec2::new(x, y)) {
+            end if end > x => {
+                self.delete_text(Vec2::new(x, y), Vec2::new(end, y));
+            }
+            _ if y + 1 < self.lines.len() => {
+                self.delete_text(Vec2::new(x, y), Vec2::new(0, y + 1));
+            }
+            _ => {}
+        }
+    }
+
+    /// Deletes the word before the cursor, along with the blanks after it,
+    /// as done by `Ctrl-W` in Insert mode. Joins the line with the previous
+    /// one when at its start.
+    pub fn delete_word_before_cursor(&mut self) {
+        let x = self.cursor.x.min(self.current_line().len());
+        let y = self.cursor.y;
+        if x == 0 {
+            return self.backspace();
+        }
+
+        let line = self.lines.line(y);
+        let mut chars = line[..x].char_indices().rev().peekable();
+        let mut start = x;
+        while let Some((i, _)) = chars.next_if(|(_, c)| c.is_whitespace()) {
+            start = i;
+        }
+        if let Some(&(_, c)) = chars.peek() {
+            let class = CharClass::of(c, false);
+            while let Some((i, _)) = chars.next_if(|(_, c)| CharClass::of(*c, false) == class) {
+                start = i;
+            }
+        }
+        drop(chars);
+
+        self.delete_text(Vec2::new(start, y), Vec2::new(x, y));
+        self.cursor.x = start;
+    }
+
+    /// Deletes the text before the cursor while keeping the indent, or the
+    /// indent itself when there is nothing else, as done by `Ctrl-U` in
+    /// Insert mode. Joins the line with the previous one when at its start.
+    pub fn delete_line_before_cursor(&mut self) {
+        let x = self.cursor.x.min(self.current_line().len());
+        let y = self.cursor.y;
+        if x == 0 {
+            return self.backspace();
+        }
+
+        let indent = self.first_non_blank(y);
+        let start = if x > indent { indent } else { 0 };
+        self.delete_text(Vec2::new(start, y), Vec2::new(x, y));
+        self.cursor.x = start;
+    }
+
+    /// Types `c` over the character under the cursor, as done in Replace
+    /// mode, or after the end of the line. Returns the overwritten grapheme
+    /// cluster.
+    pub fn overwrite_char(&mut self, c: char) -> Option<String> {
+        let x = self.cursor.x.min(self.current_line().len());
+        let y = self.cursor.y;
+
+        let end = self.next_grapheme(Vec2::new(x, y));
+        let original = if end > x {
+            Some(self.delete_text(Vec2::new(x, y), Vec2::new(end, y)))
+        } else {
+            None
+        };
+
+        self.cursor.x = x;
+        self.insert_char(c);
+        original
+    }
+
+    /// Replaces `count` characters from the cursor with `c`, or with a
+    /// single line break for `'\n'`, as done by `r`. Returns `false` when
+    /// there aren't enough characters.
+    pub fn replace_chars(&mut self, c: char, count: usize) -> bool {
+        let Vec2 { x, y } = self.cursor;
+        let line = self.lines.line(y);
+        let rest = &line[x.min(line.len())..];
+        if rest.graphemes(true).count() < count {
+            return false;
+        }
+
+        let end = x + rest
+            .grapheme_indices(true)
+            .nth(count)
+            .map_or(rest.len(), |(i, _)| i);
+        drop(line);
+        self.delete_text(self.cursor, Vec2::new(end, y));
+
+        if c == '\n' {
+            self.insert_text(self.cursor, "\n");
+            self.set_cursor(Vec2::new(0, y + 1));
+        } else {
+            self.insert_text(self.cursor, &c.to_string().repeat(count));
+            self.cursor.x = x + (count - 1) * c.len_utf8();
+        }
+        true
+    }
+
+    pub fn insert_line_after_cursor(&mut self, line: String) {
+        self.insert_lines(self.cursor.y + 1, &line);
+    }
+
+    pub fn insert_line_before_cursor(&mut self, line: String) {
+        self.insert_lines(self.cursor.y, &line);
+    }
+
+    /// Inserts `text` as whole lines right before line `y`, which may be one
+    /// past the last line.
+    fn insert_lines(&mut self, y: usize, text: &str) {
+        if y < self.lines.len() {
+            self.insert_text(Vec2::new(0, y), &format!("{}\n", text));
+        } else {
+            let len = self.lines.line_len(y - 1);
+            self.insert_text(Vec2::new(len, y - 1), &format!("\n{}", text));
+        }
+    }
+
+    /// Text of `count` lines starting at line `y`, joined with `'\n'`.
+    pub fn lines_text(&self, y: usize, count: usize) -> String {
+        self.lines.lines_text(y, count)
+    }
+
+    /// Deletes `count` whole lines starting at line `y`, leaving the cursor
+    /// on the first non-blank character of the line that took their place.
+    /// Returns the deleted lines joined with `'\n'`.
+    pub fn delete_lines(&mut self, y: usize, count: usize) -> String {
+        let end = y.saturating_add(count).min(self.lines.len());
+        let text = self.lines_text(y, count);
+
+        if end < self.lines.len() {
+            self.delete_text(Vec2::new(0, y), Vec2::new(0, end));
+        } else {
+            let last_len = self.lines.line_len(end - 1);
+            let start = if y > 0 {
+                Vec2::new(self.lines.line_len(y - 1), y - 1)
+            } else {
+                Vec2::new(0, 0)
+            };
+            self.delete_text(start, Vec2::new(last_len, end - 1));
+        }
+
+        self.cursor.y = y.min(self.lines.len() - 1);
+        self.move_cursor_first_non_blank();
+        self.scroll_to_cursor();
+
+        text
+    }
+
+    /// Puts the register contents after the cursor, or before it when
+    /// `before` is set. Linewise contents go below or above the current line.
+    pub fn put(&mut self, register: &Register, before: bool) {
+        match register.kind {
+            RegisterKind::Charwise => {
+                let len = self.current_line().len();
+                let x = if before || len == 0 {
+                    self.cursor.x.min(len)
+                } else {
+                    self.next_grapheme(self.cursor)
+                };
+
+                let start = Vec2::new(x, self.cursor.y);
+                let end = self.insert_text(start, &register.text);
+
+                self.cursor = if register.text.contains('\n') {
+                    start
+                } else {
+                    Vec2::new(self.prev_grapheme(end), end.y)
+                };
+                self.clamp_cursor(false);
+            }
+            RegisterKind::Linewise => {
+                let y = if before {
+                    self.cursor.y
+                } else {
+                    self.cursor.y + 1
+                };
+
+                self.insert_lines(y, &register.text);
+                self.cursor.y = y;
+                self.move_cursor_first_non_blank();
+            }
+            RegisterKind::Blockwise => {
+                let len = self.current_line().len();
+                let x = if before || len == 0 {
+                    self.cursor.x.min(len)
+                } else {
+                    self.next_grapheme(self.cursor)
+                };
+
+                // the block is put at the same display column on every line
+                let col = self.byte_to_col(Vec2::new(x, self.cursor.y));
+                for (i, text) in register.text.split('\n').enumerate() {
+                    let y = self.cursor.y + i;
+                    if y == self.lines.len() {
+                        self.insert_text(Vec2::new(self.line_len(y - 1), y - 1), "\n");
+                    }
+
+                    let x = self.pad_to_col(y, col);
+                    self.insert_text(Vec2::new(x, y), text);
+                }
+
+                self.cursor.x = x;
+                self.clamp_cursor(false);
+            }
+        }
+
+        self.scroll_to_cursor();
+    }
+
+    /// Inserts `text` at `pos`, splitting lines on `'\n'`, and records the
+    /// edit in the undo history. Returns the position right after the text.
+    pub fn insert_text(&mut self, pos: Vec2, text: &str) -> Vec2 {
+        let edit = Edit::Insert {
+            pos,
+            text: text.to_string(),
+        };
+        self.apply(&edit);
+        self.history.record(edit, self.cursor);
+
+        let end = end_of(pos, text);
+        self.mark_change(pos, end);
+        end
+    }
+
+    /// Deletes the text from `start` up to, but not including, `end` and
+    /// records the edit in the undo history. Returns the deleted text.
+    pub fn delete_text(&mut self, start: Vec2, end: Vec2) -> String {
+        let text = self.text_between(start, end);
+        let edit = Edit::Delete {
+            pos: start,
+            text: text.clone(),
+        };
+        self.apply(&edit);
+        self.history.record(edit, self.cursor);
+
+        self.mark_change(start, start);
+        text
+    }
+
+    /// Sets the `.`, `[` and `]` marks around a change, where text typed
+    /// right after the last change extends it.
+    fn mark_change(&mut self, start: Vec2, end: Vec2) {
+        if self.marks.get(']') != Some(start) || self.marks.get('[').is_none() {
+            self.marks.set('[', start);
+        }
+        self.marks.set(']', end);
+        self.marks.set('.', start);
+    }
+
+    /// Text from `start` up to, but not including, `end`.
+    pub fn text_between(&self, start: Vec2, end: Vec2) -> String {
+        self.lines.slice(start, end)
+    }
+
+    /// Lines removed whole, along with a line break, when deleting the text
+    /// from `pos` up to `end`: those in between, and the first or last one
+    /// when the text starts at the start of it or ends at the end of it, as
+    /// done by `dd`.
+    fn deleted_lines(&self, pos: Vec2, end: Vec2) -> Range<usize> {
+        if end.y == pos.y {
+            pos.y..pos.y
+        } else if pos.x == 0 && end.x == 0 {
+            pos.y..end.y
+        } else if pos.x == self.lines.line_len(pos.y) && end.x == self.lines.line_len(end.y) {
+            pos.y + 1..end.y + 1
+        } else {
+            pos.y + 1..end.y
+        }
+    }
+
+    fn apply(&mut self, edit: &Edit) {
+        self.modified = true;
+
+        match edit {
+            Edit::Insert { pos, text } => {
+                let end = end_of(*pos, text);
+                self.lines.insert(*pos, text);
+                self.marks.adjust_insert(*pos, end);
+
+                // lines added above the window keep the same text in view
+                if pos.y < self.offset {
+                    self.offset += end.y - pos.y;
+                }
+            }
+            Edit::Delete { pos, text } => {
+                let end = end_of(*pos, text);
+                self.marks.delete_lines(self.deleted_lines(*pos, end));
+                self.lines.remove(*pos, end);
+                self.marks.adjust_delete(*pos, end);
+
+                // same for lines removed above the window, where the top
+                // line may be one of them
+                if pos.y < self.offset {
+                    self.offset = self.offset.saturating_sub(end.y - pos.y).max(pos.y);
+                }
+                self.offset = self.offset.min(self.lines.len() - 1);
+            }
+        }
+    }
+
+    /// Closes the current undo step, if there is one. Returns whether the
+    /// buffer was changed since the last step.
+    pub fn commit_undo_step(&mut self) -> bool {
+        let committed = self.history.commit(self.cursor);
+        if let (true, Some(pos)) = (committed, self.marks.get('.')) {
+            self.marks.push_change(pos);
+        }
+        committed
+    }
+
+    pub fn undo(&mut self) -> bool {
+        self.commit_undo_step();
+
+        let step = match self.history.undo() {
+            Some(step) => step.clone(),
+            None => return false,
+        };
+
+        for edit in step.edits.iter().rev() {
+            self.apply(&edit.inverse());
+        }
+        self.set_cursor(step.cursor_before);
+
+        true
+    }
+
+    pub fn redo(&mut self) -> bool {
+        self.commit_undo_step();
+
+        let step = match self.history.redo() {
+            Some(step) => step.clone(),
+            None => return false,
+        };
+
+        for edit in &step.edits {
+            self.apply(edit);
+        }
+        self.set_cursor(step.cursor_after);
+
+        true
+    }
+
+    fn scroll_to_cursor(&mut self) {
+        let context = self.context_lines();
+        let top = self.cursor.y.saturating_sub(context);
+        let bottom = (self.cursor.y + context).min(self.lines.len() - 1);
+
+        if top < self.offset {
+            self.offset = top;
+        } else if bottom >= self.offset + self.size.y {
+            self.offset = (bottom + 1).saturating_sub(self.size.y);
+        }
+    }
+
+    pub fn scrolloff(&self) -> usize {
+        self.scrolloff
+    }
+
+    pub fn set_scrolloff(&mut self, lines: usize) {
+        self.scrolloff = lines;
+        self.scroll_to_cursor();
+    }
+
+    /// Lines scrolled by `Ctrl-D` and `Ctrl-U`.
+    pub fn scroll(&self) -> usize {
+        if self.scroll == 0 {
+            (self.size.y / 2).max(1)
+        } else {
+            self.scroll
+        }
+    }
+
+    pub fn set_scroll(&mut self, lines: usize) {
+        self.scroll = lines;
+    }
+
+    /// Columns between tab stops.
+    pub fn tabstop(&self) -> usize {
+        if self.tabstop == 0 {
+            8
+        } else {
+            self.tabstop
+        }
+    }
+
+    /// Sets `tabstop`, which is at most `MAX_TABSTOP`.
+    pub fn set_tabstop(&mut self, columns: usize) {
+        self.tabstop = columns.min(MAX_TABSTOP);
+    }
+
+    /// Lines actually kept around the cursor, as `scrolloff` can't keep
+    /// more than half the window.
+    fn context_lines(&self) -> usize {
+        self.scrolloff.min(self.size.y.saturating_sub(1) / 2)
+    }
+
+    fn last_visible_line(&self) -> usize {
+        (self.offset + self.size.y.max(1) - 1).min(self.lines.len() - 1)
+    }
+
+    /// Moves the cursor into the window after it was scrolled, keeping
+    /// `scrolloff` lines around it unless the window shows the start or end
+    /// of the buffer.
+    fn keep_cursor_in_window(&mut self) {
+        let context = self.context_lines();
+        let last = self.last_visible_line();
+
+        let bottom = if last + 1 == self.lines.len() {
+            last
+        } else {
+            last.saturating_sub(context)
+        };
+        let top = if self.offset == 0 {
+            0
+        } else {
+            (self.offset + context).min(bottom)
+        };
+
+        let y = self.cursor.y.clamp(top, bottom);
+        if y != self.cursor.y {
+            self.cursor.y = y;
+            self.clamp_cursor(false);
+        }
+    }
+
+    /// Scrolls the window `count` lines down or up without moving the
+    /// cursor, unless it would leave the window, as done by `Ctrl-E` and
+    /// `Ctrl-Y`. Returns whether the window could scroll.
+    pub fn scroll_lines(&mut self, down: bool, count: usize) -> bool {
+        let offset = if down {
+            self.offset.saturating_add(count).min(self.lines.len() - 1)
+        } else {
+            self.offset.saturating_sub(count)
+        };
+        if offset == self.offset {
+            return false;
+        }
+
+        self.offset = offset;
+        self.keep_cursor_in_window();
+        true
+    }
+
+    /// Scrolls the window and moves the cursor `lines` lines down or up, as
+    /// done by `Ctrl-D` and `Ctrl-U`. Once the window shows the end or start
+    /// of the buffer, only the cursor moves. Returns whether it could.
+    pub fn scroll_half_page(&mut self, down: bool, lines: usize) -> bool {
+        let last = self.lines.len() - 1;
+
+        if down {
+            if self.cursor.y == last {
+                return false;
+            }
+            let max_offset = (last + 1).saturating_sub(self.size.y).max(self.offset);
+            self.offset = self.offset.saturating_add(lines).min(max_offset);
+            self.cursor.y = self.cursor.y.saturating_add(lines).min(last);
+        } else {
+            if self.cursor.y == 0 {
+                return false;
+            }
+            self.offset = self.offset.saturating_sub(lines);
+            self.cursor.y = self.cursor.y.saturating_sub(lines);
+        }
+
+        self.move_cursor_first_non_blank();
+        self.scroll_to_cursor();
+        true
+    }
+
+    /// Scrolls the window `count` pages down or up, keeping two lines of the
+    /// previous page in view, as done by `Ctrl-F` and `Ctrl-B`. Returns
+    /// whether the window could scroll.
+    pub fn scroll_page(&mut self, down: bool, count: usize) -> bool {
+        let lines = self.size.y.saturating_sub(2).max(1).saturating_mul(count);
+        if !self.scroll_lines(down, lines) {
+            return false;
+        }
+
+        self.move_cursor_first_non_blank();
+        true
+    }
+
+    /// Scrolls the window to show the cursor line at `position`, as done by
+    /// `zt`, `zz` and `zb`.
+    pub fn scroll_cursor_to(&mut self, position: WindowPosition) {
+        let context = self.context_lines();
+        let y = self.cursor.y;
+
+        self.offset = match position {
+            WindowPosition::Top => y.saturating_sub(context),
+            WindowPosition::Middle => y.saturating_sub(self.size.y.saturating_sub(1) / 2),
+            WindowPosition::Bottom => {
+                let bottom = (y + context).min(self.lines.len() - 1);
+                (bottom + 1).saturating_sub(self.size.y)
+            }
+        };
+    }
+
+    /// Line `H`, `M` or `L` moves to, where `count` counts lines from the
+    /// top or bottom of the window. The `scrolloff` lines at either end are
+    /// skipped unless they show the start or end of the buffer.
+    pub fn window_line(&self, position: WindowPosition, count: usize) -> usize {
+        let context = self.context_lines();
+        let last = self.last_visible_line();
+
+        match position {
+            WindowPosition::Top => {
+                let y = self.offset + count.saturating_sub(1);
+                let y = if self.offset > 0 {
+                    y.max(self.offset + context)
+                } else {
+                    y
+                };
+                y.min(last)
+            }
+            WindowPosition::Middle => self.offset + (last - self.offset) / 2,
+            WindowPosition::Bottom => {
+                let y = last.saturating_sub(count.saturating_sub(1));
+                let y = if last + 1 < self.lines.len() {
+                    y.min(last.saturating_sub(context))
+                } else {
+                    y
+                };
+                y.max(self.offset)
+            }
+        }
+    }
+
+    /// Writes the buffer to `file_path` in its encoding and format and
+    /// returns the number of bytes written. A buffer without a file takes
+    /// `file_path` as its file, and writing to its own file clears the
+    /// modified flag.
+    ///
+    /// Fails with `InvalidData`, leaving the file as it was, when the text
+    /// can't be written in the encoding.
+    pub fn write_to_file(&mut self, file_path: &str) -> io::Result<usize> {
+        if !self.encoding.can_encode(self.lines.chunks(), self.escapes) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "conversion failed",
+            ));
+        }
+
+        // like vim, an empty buffer is written as an empty file, unless
+        // it was read from a file with nothing but a line break
+        let empty = self.lines.len() == 1 && self.lines.line_len(0) == 0;
+        let eol = if empty {
+            self.eol && !self.fixeol
+        } else {
+            self.eol || self.fixeol
+        };
+
+        let format = self.format;
+        let chunks = self
+            .lines
+            .chunks()
+            .map(|chunk| format.convert(chunk))
+            .chain(eol.then(|| format.line_break().into()));
+
+        let mut writer = BufWriter::new(File::create(file_path)?);
+        let written = self
+            .encoding
+            .write(chunks, self.bom, self.escapes.is_some(), &mut writer)?;
+        writer.flush()?;
+
+        if self.file_path.is_none() {
+            self.file_path = Some(file_path.to_string());
+        }
+        if self.file_path.as_deref() == Some(file_path) {
+            self.modified = false;
+        }
+
+        Ok(written)
+    }
+
+    pub fn backspace(&mut self) {
+        let x = self.cursor.x.min(self.current_line().len());
+        let y = self.cursor.y;
+
+        if x > 0 {
+            let start = self.prev_grapheme(Vec2::new(x, y));
+            self.delete_text(Vec2::new(start, y), Vec2::new(x, y));
+            self.cursor.x = start;
+        } else if y > 0 {
+            let len = self.lines.line_len(y - 1);
+            self.delete_text(Vec2::new(len, y - 1), Vec2::new(0, y));
+            self.cursor = Vec2::new(len, y - 1);
+            self.scroll_to_cursor();
+        }
+    }
+
+    pub fn insert_new_line(&mut self) {
+        self.cursor.x = self.cursor.x.min(self.current_line().len());
+        self.cursor = self.insert_text(self.cursor, "\n");
+        self.scroll_to_cursor();
+    }
+
+    pub fn write_debug(&self) {
+        write_debug("##########################");
+        write_debug(&format!("offset {:?}\n", self.offset));
+        write_debug(&format!("cursor {:?}\n", self.cursor));
+        write_debug(&format!("current_line {:?}\n", self.current_line()));
+        write_debug("##########################");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn move_cursor_down() {
+        let mut buffer = Buffer {
+            lines: vec!["line1".to_string(), "line2".to_string()].into(),
+            cursor: Vec2::default(),
+            offset: 0,
+            pos: Vec2::default(),
+            size: Vec2::new(100, 100),
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.move_cursor_down();
+
+        assert_eq!(buffer.cursor, Vec2::new(0, 1));
+    }
+
+    #[test]
+    fn move_cursor_down_offset() {
+        let mut buffer = Buffer {
+            lines: vec!["line1".to_string(), "line2".to_string()].into(),
+            cursor: Vec2::default(),
+            size: Vec2::new(100, 1),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.move_cursor_down();
+
+        assert_eq!(buffer.cursor, Vec2::new(0, 1));
+        assert_eq!(buffer.offset, 1);
+    }
+
+    #[test]
+    fn move_cursor_down_clamp_x() {
+        let mut buffer = Buffer {
+            lines: vec!["big line".to_string(), "small".to_string()].into(),
+            cursor: Vec2::new(7, 0),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.move_cursor_down();
+
+        assert_eq!(buffer.cursor, Vec2::new(4, 1));
+    }
+
+    #[test]
     fn move_cursor_up_clamp_x() {
         let mut buffer = Buffer {
-            lines: vec!["small".to_string(), "big line".to_string()],
-            cursor: Vec2::new(7, 1),
+            lines: vec!["small".to_string(), "big line".to_string()].into(),
+            cursor: Vec2::new(7, 1),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.move_cursor_up();
+
+        assert_eq!(buffer.cursor, Vec2::new(4, 0));
+    }
+
+    #[test]
+    fn move_cursor_up_and_down_keep_display_column() {
+        let mut buffer = Buffer {
+            lines: vec!["hello", "héllo", "中文字", "a\tb", "hi", "hello"]
+                .into_iter()
+                .map(String::from)
+                .collect::<Vec<_>>()
+                .into(),
+            cursor: Vec2::new(4, 0),
+            size: Vec2::new(100, 100),
+            ..Default::default()
+        };
+
+        let mut moves = vec![];
+        for _ in 0..5 {
+            buffer.move_cursor_down();
+            moves.push(buffer.cursor);
+        }
+        assert_eq!(
+            moves,
+            vec![
+                Vec2::new(5, 1),
+                Vec2::new(6, 2),
+                Vec2::new(1, 3),
+                Vec2::new(1, 4),
+                Vec2::new(4, 5)
+            ]
+        );
+        assert_eq!(buffer.byte_to_col(Vec2::new(6, 2)), 4);
+        assert_eq!(buffer.col_to_byte(2, 3), 3);
+
+        buffer.move_cursor_left();
+        buffer.move_cursor_up();
+        assert_eq!(buffer.cursor, Vec2::new(1, 4));
+        buffer.move_cursor_up();
+        assert_eq!(buffer.cursor, Vec2::new(1, 3));
+
+        buffer.move_cursor_last_character();
+        buffer.move_cursor_up();
+        assert_eq!(buffer.cursor, Vec2::new(6, 2));
+        buffer.move_cursor_up();
+        assert_eq!(buffer.cursor, Vec2::new(5, 1));
+    }
+
+    #[test]
+    fn move_forward() {
+        let mut buffer = Buffer {
+            lines: vec!["abc".to_string()].into(),
+            cursor: Vec2::default(),
+            offset: 0,
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.move_forward();
+
+        assert_eq!(buffer.cursor, Vec2::new(1, 0));
+    }
+
+    #[test]
+    fn move_forward_no_op_when_end_of_buffer() {
+        let mut buffer = Buffer {
+            lines: vec!["abc".to_string()].into(),
+            cursor: Vec2::new(2, 0),
+            offset: 0,
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.move_forward();
+
+        assert_eq!(buffer.cursor, Vec2::new(2, 0));
+    }
+
+    #[test]
+    fn move_forward_wrap_line() {
+        let mut buffer = Buffer {
+            lines: vec!["line!".to_string(), "line2".to_string()].into(),
+            cursor: Vec2::new(4, 0),
+            offset: 0,
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.move_forward();
+
+        assert_eq!(buffer.cursor, Vec2::new(0, 1));
+    }
+
+    #[test]
+    fn move_forward_wrap_line_offset() {
+        let mut buffer = Buffer {
+            lines: vec!["line1".to_string(), "line2".to_string()].into(),
+            cursor: Vec2::new(4, 0),
+            offset: 0,
+            size: Vec2::new(100, 1),
+            pos: Vec2::default(),
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.move_forward();
+
+        assert_eq!(buffer.cursor, Vec2::new(0, 1));
+        assert_eq!(buffer.offset, 1);
+    }
+
+    #[test]
+    fn word_forward() {
+        let mut buffer = Buffer {
+            lines: vec!["Word Forward".to_string()].into(),
+            cursor: Vec2::default(),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.word_forward(false);
+
+        assert_eq!(buffer.cursor, Vec2::new(5, 0));
+    }
+
+    #[test]
+    fn word_forward_space_character() {
+        let mut buffer = Buffer {
+            lines: vec![" Word Forward".to_string()].into(),
+            cursor: Vec2::default(),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.word_forward(false);
+        assert_eq!(buffer.cursor, Vec2::new(1, 0));
+    }
+
+    #[test]
+    fn word_forward_multiple_space_character() {
+        let mut buffer = Buffer {
+            lines: vec!["  Word Forward".to_string()].into(),
+            cursor: Vec2::default(),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.word_forward(false);
+        assert_eq!(buffer.cursor, Vec2::new(2, 0));
+    }
+
+    #[test]
+    fn word_forward_non_alpha_numeric_character() {
+        let mut buffer = Buffer {
+            lines: vec![";Word Forward".to_string()].into(),
+            cursor: Vec2::default(),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.word_forward(false);
+        assert_eq!(buffer.cursor, Vec2::new(1, 0));
+    }
+
+    #[test]
+    fn word_forward_multiple_non_alpha_numeric_character() {
+        let mut buffer = Buffer {
+            lines: vec![";;Word Forward".to_string()].into(),
+            cursor: Vec2::default(),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.word_forward(false);
+        assert_eq!(buffer.cursor, Vec2::new(2, 0));
+    }
+
+    #[test]
+    fn word_forward_wrap_line() {
+        let mut buffer = Buffer {
+            lines: vec!["word1".to_string(), "word2".to_string()].into(),
+            cursor: Vec2::default(),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.word_forward(false);
+        assert_eq!(buffer.cursor, Vec2::new(0, 1));
+
+        // next line starts with space
+        buffer = Buffer {
+            lines: vec!["word1".to_string(), " word2".to_string()].into(),
+            cursor: Vec2::default(),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.word_forward(false);
+        assert_eq!(buffer.cursor, Vec2::new(1, 1));
+
+        // next line is empty
+        buffer = Buffer {
+            lines: vec!["word1".to_string(), "".to_string()].into(),
+            cursor: Vec2::default(),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.word_forward(false);
+        assert_eq!(buffer.cursor, Vec2::new(0, 1));
+
+        // when current line has trailing space
+        buffer = Buffer {
+            lines: vec!["word1 ".to_string(), "word2".to_string()].into(),
+            cursor: Vec2::default(),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.word_forward(false);
+        assert_eq!(buffer.cursor, Vec2::new(0, 1));
+
+        // when current line has trailing space and next starts with space
+        buffer = Buffer {
+            lines: vec!["; ".to_string(), " word2".to_string()].into(),
+            cursor: Vec2::default(),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.word_forward(false);
+        assert_eq!(buffer.cursor, Vec2::new(1, 1));
+
+        buffer = Buffer {
+            lines: vec![
+                "weird ".to_string(),
+                " ".to_string(),
+                " scenario".to_string(),
+            ]
+            .into(),
+            cursor: Vec2::default(),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.word_forward(false);
+        assert_eq!(buffer.cursor, Vec2::new(1, 2));
+    }
+
+    #[test]
+    fn word_forward_wrap_line_current_line_is_empty() {
+        let mut buffer = Buffer {
+            lines: vec!["".to_string(), "word".to_string()].into(),
+            cursor: Vec2::default(),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.word_forward(false);
+        assert_eq!(buffer.cursor, Vec2::new(0, 1));
+    }
+
+    #[test]
+    fn word_forward_starts_white_space() {
+        let mut buffer = Buffer {
+            lines: vec!["    }".to_string(), "}".to_string()].into(),
+            cursor: Vec2::default(),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.word_forward(false);
+        assert_eq!(buffer.cursor, Vec2::new(4, 0));
+    }
+
+    #[test]
+    fn word_forward_wrap_line_offset() {
+        let mut buffer = Buffer {
+            lines: vec![
+                "word1".to_string(),
+                "word2".to_string(),
+                "word3".to_string(),
+            ]
+            .into(),
+            cursor: Vec2::default(),
+            size: Vec2::new(100, 1),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.word_forward(false);
+        assert_eq!(buffer.cursor, Vec2::new(0, 1));
+        assert_eq!(buffer.offset, 1);
+    }
+
+    #[test]
+    fn clamp_cursor() {
+        let mut buffer = Buffer {
+            lines: vec!["".to_string()].into(),
+            cursor: Vec2::default(),
+            size: Vec2::new(100, 1),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.insert_char('a');
+        buffer.clamp_cursor(false);
+
+        assert_eq!(buffer.cursor, Vec2::new(0, 0));
+    }
+
+    #[test]
+    fn insert_char() {
+        let mut buffer = Buffer {
+            lines: vec!["".to_string()].into(),
+            cursor: Vec2::default(),
+            size: Vec2::new(100, 1),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.insert_char('a');
+
+        assert_eq!(buffer.current_line(), "a");
+        assert_eq!(buffer.cursor, Vec2::new(1, 0));
+    }
+
+    #[test]
+    fn insert_line_after_cursor_last_line() {
+        let mut buffer = Buffer {
+            lines: vec!["".to_string()].into(),
+            cursor: Vec2::default(),
+            size: Vec2::new(100, 1),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.insert_line_after_cursor("hello".to_string());
+
+        assert_eq!(buffer.lines, vec!["", "hello"]);
+        assert_eq!(buffer.cursor, Vec2::default());
+    }
+
+    #[test]
+    fn insert_line_after_cursor_middle_line() {
+        let mut buffer = Buffer {
+            lines: vec![
+                "line1".to_string(),
+                "line2".to_string(),
+                "line3".to_string(),
+            ]
+            .into(),
+            cursor: Vec2::new(2, 1),
+            size: Vec2::new(100, 1),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.insert_line_after_cursor("inserted".to_string());
+
+        assert_eq!(buffer.lines, vec!["line1", "line2", "inserted", "line3"]);
+        assert_eq!(buffer.cursor, Vec2::new(2, 1));
+    }
+
+    #[test]
+    fn insert_new_line_start_of_current_line() {
+        let mut buffer = Buffer {
+            lines: vec![
+                "before".to_string(),
+                "cursor_line".to_string(),
+                "after".to_string(),
+            ]
+            .into(),
+            cursor: Vec2::new(0, 1),
+            size: Vec2::new(100, 1),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.insert_new_line();
+
+        assert_eq!(buffer.lines, vec!["before", "", "cursor_line", "after"]);
+        assert_eq!(buffer.cursor, Vec2::new(0, 2));
+    }
+
+    #[test]
+    fn insert_new_line_end_of_current_line() {
+        let mut buffer = Buffer {
+            lines: vec!["before".to_string(), "1".to_string(), "after".to_string()].into(),
+            cursor: Vec2::new(1, 1),
+            size: Vec2::new(100, 1),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.insert_new_line();
+
+        assert_eq!(buffer.lines, vec!["before", "1", "", "after"]);
+        assert_eq!(buffer.cursor, Vec2::new(0, 2));
+    }
+
+    #[test]
+    fn insert_new_line_middle_of_current_line() {
+        let mut buffer = Buffer {
+            lines: vec![
+                "before".to_string(),
+                "cursor_line".to_string(),
+                "after".to_string(),
+            ]
+            .into(),
+            cursor: Vec2::new(6, 1),
+            size: Vec2::new(100, 1),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.insert_new_line();
+
+        assert_eq!(buffer.lines, vec!["before", "cursor", "_line", "after"]);
+        assert_eq!(buffer.cursor, Vec2::new(0, 2));
+    }
+
+    #[test]
+    fn join_line() {
+        let mut buffer = Buffer {
+            lines: vec!["line1".to_string(), "line2".to_string()].into(),
+            cursor: Vec2::new(0, 0),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.join_line();
+
+        assert_eq!(buffer.lines, vec!["line1 line2"]);
+        assert_eq!(buffer.cursor, Vec2::new(5, 0));
+    }
+
+    #[test]
+    fn backspace_cursor_one_off() {
+        let mut buffer = Buffer {
+            lines: vec!["0123456".to_string()].into(),
+            cursor: Vec2::new(7, 0),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.backspace();
+
+        assert_eq!(buffer.lines, vec!["012345"]);
+        assert_eq!(buffer.cursor, Vec2::new(6, 0));
+    }
+
+    #[test]
+    fn backspace_cursor_middle() {
+        let mut buffer = Buffer {
+            lines: vec!["0123456".to_string()].into(),
+            cursor: Vec2::new(5, 0),
             size: Vec2::new(100, 100),
             pos: Vec2::default(),
             offset: 0,
             line_num: true,
+            ..Default::default()
         };
 
-        buffer.move_cursor_up();
+        buffer.backspace();
 
+        assert_eq!(buffer.lines, vec!["012356"]);
         assert_eq!(buffer.cursor, Vec2::new(4, 0));
     }
 
     #[test]
-    fn move_forward() {
+    fn backspace_cursor_start() {
         let mut buffer = Buffer {
-            lines: vec!["abc".to_string()],
-            cursor: Vec2::default(),
+            lines: vec!["0123".to_string(), "4567".to_string()].into(),
+            cursor: Vec2::new(0, 1),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
             offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.backspace();
+
+        assert_eq!(buffer.lines, vec!["01234567"]);
+        assert_eq!(buffer.cursor, Vec2::new(4, 0));
+    }
+
+    #[test]
+    fn undo_groups_edits_until_commit() {
+        let mut buffer = Buffer {
+            lines: vec!["line1".to_string(), "line2".to_string()].into(),
+            cursor: Vec2::new(5, 0),
             size: Vec2::new(100, 100),
             pos: Vec2::default(),
+            offset: 0,
             line_num: true,
+            ..Default::default()
         };
 
-        buffer.move_forward();
+        buffer.insert_char('!');
+        buffer.insert_new_line();
+        buffer.insert_char('a');
+        buffer.commit_undo_step();
 
-        assert_eq!(buffer.cursor, Vec2::new(1, 0));
+        assert_eq!(buffer.lines, vec!["line1!", "a", "line2"]);
+
+        assert!(buffer.undo());
+        assert_eq!(buffer.lines, vec!["line1", "line2"]);
+        assert_eq!(buffer.cursor, Vec2::new(4, 0));
+
+        assert!(!buffer.undo());
     }
 
     #[test]
-    fn move_forward_no_op_when_end_of_buffer() {
+    fn redo() {
         let mut buffer = Buffer {
-            lines: vec!["abc".to_string()],
+            lines: vec!["line1".to_string(), "line2".to_string()].into(),
             cursor: Vec2::new(2, 0),
-            offset: 0,
             size: Vec2::new(100, 100),
             pos: Vec2::default(),
+            offset: 0,
             line_num: true,
+            ..Default::default()
         };
 
-        buffer.move_forward();
+        buffer.join_line();
+        buffer.commit_undo_step();
+        buffer.delete_text(Vec2::new(5, 0), Vec2::new(6, 0));
+        buffer.commit_undo_step();
 
+        buffer.undo();
+        buffer.undo();
+        assert_eq!(buffer.lines, vec!["line1", "line2"]);
         assert_eq!(buffer.cursor, Vec2::new(2, 0));
+
+        assert!(buffer.redo());
+        assert_eq!(buffer.lines, vec!["line1 line2"]);
+        assert_eq!(buffer.cursor, Vec2::new(5, 0));
+
+        assert!(buffer.redo());
+        assert_eq!(buffer.lines, vec!["line1line2"]);
+
+        assert!(!buffer.redo());
     }
 
     #[test]
-    fn move_forward_wrap_line() {
+    fn undo_backspace_joining_lines() {
+        let mut buffer = Buffer {
+            lines: vec!["0123".to_string(), "4567".to_string()].into(),
+            cursor: Vec2::new(0, 1),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.backspace();
+        buffer.commit_undo_step();
+        buffer.undo();
+
+        assert_eq!(buffer.lines, vec!["0123", "4567"]);
+        assert_eq!(buffer.cursor, Vec2::new(0, 1));
+    }
+
+    #[test]
+    fn undo_scrolls_to_cursor() {
+        let mut buffer = Buffer {
+            lines: vec!["line1".to_string(), "line2".to_string()].into(),
+            cursor: Vec2::default(),
+            size: Vec2::new(100, 1),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.delete_text(Vec2::new(0, 0), Vec2::new(1, 0));
+        buffer.commit_undo_step();
+        buffer.move_cursor_down();
+        buffer.undo();
+
+        assert_eq!(buffer.cursor, Vec2::new(0, 0));
+        assert_eq!(buffer.offset, 0);
+    }
+
+    fn numbered_buffer(count: usize, height: usize) -> Buffer {
+        Buffer {
+            lines: (0..count).map(|i| i.to_string()).collect::<Vec<_>>().into(),
+            size: Vec2::new(100, height),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn scrolloff_keeps_lines_around_cursor() {
+        let mut buffer = numbered_buffer(100, 10);
+        buffer.set_scrolloff(3);
+
+        buffer.set_cursor(Vec2::new(0, 6));
+        assert_eq!(buffer.offset, 0);
+        buffer.move_cursor_down();
+        assert_eq!(buffer.offset, 1);
+
+        buffer.set_cursor(Vec2::new(0, 99));
+        assert_eq!(buffer.offset, 90);
+        buffer.set_cursor(Vec2::new(0, 92));
+        assert_eq!(buffer.offset, 89);
+
+        // no more than half the window is kept
+        buffer.set_scrolloff(100);
+        buffer.set_cursor(Vec2::new(0, 50));
+        assert_eq!(buffer.offset, 46);
+    }
+
+    #[test]
+    fn scroll_lines_moves_cursor_only_when_leaving_window() {
+        let mut buffer = numbered_buffer(100, 10);
+
+        assert!(buffer.scroll_lines(true, 3));
+        assert_eq!(buffer.offset, 3);
+        assert_eq!(buffer.cursor, Vec2::new(0, 3));
+
+        assert!(buffer.scroll_lines(false, 5));
+        assert_eq!(buffer.offset, 0);
+        assert_eq!(buffer.cursor, Vec2::new(0, 3));
+        assert!(!buffer.scroll_lines(false, 1));
+
+        buffer.set_scrolloff(2);
+        buffer.scroll_lines(true, 2);
+        assert_eq!(buffer.cursor, Vec2::new(0, 4));
+
+        assert!(buffer.scroll_lines(true, 1000));
+        assert_eq!(buffer.offset, 99);
+        assert_eq!(buffer.cursor, Vec2::new(0, 99));
+        assert!(!buffer.scroll_lines(true, 1));
+    }
+
+    #[test]
+    fn scroll_half_page() {
+        let mut buffer = numbered_buffer(100, 10);
+
+        assert!(buffer.scroll_half_page(true, 5));
+        assert_eq!(buffer.offset, 5);
+        assert_eq!(buffer.cursor, Vec2::new(0, 5));
+
+        // the window stops at the end of the buffer while the cursor goes on
+        buffer.set_cursor(Vec2::new(0, 95));
+        assert!(buffer.scroll_half_page(true, 5));
+        assert_eq!(buffer.offset, 90);
+        assert_eq!(buffer.cursor, Vec2::new(0, 99));
+        assert!(!buffer.scroll_half_page(true, 5));
+
+        assert!(buffer.scroll_half_page(false, 5));
+        assert_eq!(buffer.offset, 85);
+        assert_eq!(buffer.cursor, Vec2::new(0, 94));
+    }
+
+    #[test]
+    fn scroll_page() {
+        let mut buffer = numbered_buffer(100, 10);
+        assert!(!buffer.scroll_page(false, 1));
+
+        assert!(buffer.scroll_page(true, 1));
+        assert_eq!(buffer.offset, 8);
+        assert_eq!(buffer.cursor, Vec2::new(0, 8));
+
+        assert!(buffer.scroll_page(true, 2));
+        assert_eq!(buffer.offset, 24);
+
+        assert!(buffer.scroll_page(false, 1));
+        assert_eq!(buffer.offset, 16);
+        assert_eq!(buffer.cursor, Vec2::new(0, 24));
+
+        assert!(buffer.scroll_page(true, 100));
+        assert_eq!(buffer.offset, 99);
+        assert_eq!(buffer.cursor, Vec2::new(0, 99));
+        assert!(!buffer.scroll_page(true, 1));
+    }
+
+    #[test]
+    fn scroll_cursor_to_window_position() {
+        let mut buffer = numbered_buffer(100, 10);
+        buffer.set_cursor(Vec2::new(0, 50));
+
+        buffer.scroll_cursor_to(WindowPosition::Top);
+        assert_eq!(buffer.offset, 50);
+        buffer.scroll_cursor_to(WindowPosition::Middle);
+        assert_eq!(buffer.offset, 46);
+        buffer.scroll_cursor_to(WindowPosition::Bottom);
+        assert_eq!(buffer.offset, 41);
+
+        buffer.set_scrolloff(2);
+        buffer.scroll_cursor_to(WindowPosition::Top);
+        assert_eq!(buffer.offset, 48);
+        buffer.scroll_cursor_to(WindowPosition::Bottom);
+        assert_eq!(buffer.offset, 43);
+
+        buffer.set_cursor(Vec2::new(0, 99));
+        buffer.scroll_cursor_to(WindowPosition::Bottom);
+        assert_eq!(buffer.offset, 90);
+    }
+
+    #[test]
+    fn window_line() {
+        let mut buffer = numbered_buffer(100, 10);
+        buffer.offset = 20;
+
+        assert_eq!(buffer.window_line(WindowPosition::Top, 1), 20);
+        assert_eq!(buffer.window_line(WindowPosition::Top, 3), 22);
+        assert_eq!(buffer.window_line(WindowPosition::Middle, 1), 24);
+        assert_eq!(buffer.window_line(WindowPosition::Bottom, 1), 29);
+        assert_eq!(buffer.window_line(WindowPosition::Bottom, 3), 27);
+
+        buffer.scrolloff = 2;
+        assert_eq!(buffer.window_line(WindowPosition::Top, 1), 22);
+        assert_eq!(buffer.window_line(WindowPosition::Bottom, 1), 27);
+
+        buffer.offset = 0;
+        assert_eq!(buffer.window_line(WindowPosition::Top, 1), 0);
+
+        let buffer = numbered_buffer(5, 10);
+        assert_eq!(buffer.window_line(WindowPosition::Middle, 1), 2);
+        assert_eq!(buffer.window_line(WindowPosition::Bottom, 1), 4);
+    }
+
+    #[test]
+    fn matching_bracket() {
+        let buffer = Buffer {
+            lines: vec![
+                "fn f(a: [u8; 2]) {".to_string(),
+                "    g(a[0], (1));".to_string(),
+                "}".to_string(),
+            ]
+            .into(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            buffer.matching_bracket(Vec2::new(4, 0), 0..=2),
+            Some(Vec2::new(15, 0))
+        );
+        assert_eq!(
+            buffer.matching_bracket(Vec2::new(15, 0), 0..=2),
+            Some(Vec2::new(4, 0))
+        );
+        assert_eq!(
+            buffer.matching_bracket(Vec2::new(17, 0), 0..=2),
+            Some(Vec2::new(0, 2))
+        );
+        assert_eq!(
+            buffer.matching_bracket(Vec2::new(0, 2), 0..=2),
+            Some(Vec2::new(17, 0))
+        );
+        assert_eq!(
+            buffer.matching_bracket(Vec2::new(15, 1), 0..=2),
+            Some(Vec2::new(5, 1))
+        );
+
+        assert_eq!(buffer.matching_bracket(Vec2::new(17, 0), 0..=1), None);
+        assert_eq!(buffer.matching_bracket(Vec2::new(0, 0), 0..=2), None);
+    }
+
+    #[test]
+    fn match_pair_searches_forward_on_line() {
+        let mut buffer = Buffer {
+            lines: vec!["let x = f(a) + [b];".to_string(), "(".to_string()].into(),
+            ..Default::default()
+        };
+
+        assert_eq!(buffer.match_pair(), Some(Vec2::new(11, 0)));
+
+        buffer.cursor = Vec2::new(12, 0);
+        assert_eq!(buffer.match_pair(), Some(Vec2::new(17, 0)));
+
+        buffer.cursor = Vec2::new(18, 0);
+        assert_eq!(buffer.match_pair(), None);
+
+        buffer.cursor = Vec2::new(0, 1);
+        assert_eq!(buffer.match_pair(), None);
+    }
+
+    #[test]
+    fn offset_follows_lines_above_window() {
+        let mut buffer = Buffer {
+            lines: (0..10).map(|i| i.to_string()).collect::<Vec<_>>().into(),
+            cursor: Vec2::new(0, 6),
+            size: Vec2::new(100, 3),
+            offset: 5,
+            ..Default::default()
+        };
+
+        buffer.insert_text(Vec2::new(1, 2), "\na\nb");
+        assert_eq!(buffer.offset, 7);
+
+        buffer.delete_text(Vec2::new(0, 0), Vec2::new(0, 3));
+        assert_eq!(buffer.offset, 4);
+        assert_eq!(buffer.lines.line(buffer.offset), "5");
+
+        buffer.delete_text(Vec2::new(0, 3), Vec2::new(0, 6));
+        assert_eq!(buffer.offset, 3);
+        assert_eq!(buffer.lines.line(buffer.offset), "7");
+    }
+
+    #[test]
+    fn delete_lines_last_line() {
+        let mut buffer = Buffer {
+            lines: vec!["line1".to_string(), "  line2".to_string()].into(),
+            cursor: Vec2::new(0, 1),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        assert_eq!(buffer.delete_lines(1, 1), "  line2");
+        assert_eq!(buffer.lines, vec!["line1"]);
+        assert_eq!(buffer.cursor, Vec2::new(0, 0));
+
+        assert_eq!(buffer.delete_lines(0, 1), "line1");
+        assert_eq!(buffer.lines, vec![""]);
+    }
+
+    #[test]
+    fn put_linewise_after_last_line() {
+        let mut buffer = Buffer {
+            lines: vec!["line1".to_string()].into(),
+            cursor: Vec2::new(3, 0),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.put(
+            &Register::new("  a\nb".to_string(), RegisterKind::Linewise),
+            false,
+        );
+
+        assert_eq!(buffer.lines, vec!["line1", "  a", "b"]);
+        assert_eq!(buffer.cursor, Vec2::new(2, 1));
+    }
+
+    #[test]
+    fn put_characterwise_before() {
         let mut buffer = Buffer {
-            lines: vec!["line!".to_string(), "line2".to_string()],
+            lines: vec!["line1".to_string()].into(),
             cursor: Vec2::new(4, 0),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.put(
+            &Register::new("abc".to_string(), RegisterKind::Charwise),
+            true,
+        );
+
+        assert_eq!(buffer.lines, vec!["lineabc1"]);
+        assert_eq!(buffer.cursor, Vec2::new(6, 0));
+    }
+
+    #[test]
+    fn word_forward_underscore() {
+        let mut buffer = Buffer {
+            lines: vec!["word_forward next".to_string()].into(),
+            cursor: Vec2::new(0, 0),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
             offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.word_forward(false);
+        assert_eq!(buffer.cursor, Vec2::new(13, 0));
+    }
+
+    #[test]
+    fn bigword_forward() {
+        let mut buffer = Buffer {
+            lines: vec!["foo.bar(baz) next".to_string()].into(),
+            cursor: Vec2::new(0, 0),
             size: Vec2::new(100, 100),
             pos: Vec2::default(),
+            offset: 0,
             line_num: true,
+            ..Default::default()
         };
 
-        buffer.move_forward();
+        buffer.word_forward(true);
+        assert_eq!(buffer.cursor, Vec2::new(13, 0));
+    }
 
-        assert_eq!(buffer.cursor, Vec2::new(0, 1));
+    #[test]
+    fn bigword_forward_wrap_line() {
+        let mut buffer = Buffer {
+            lines: vec!["foo.bar".to_string(), "  baz".to_string()].into(),
+            cursor: Vec2::new(0, 0),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.word_forward(true);
+        assert_eq!(buffer.cursor, Vec2::new(2, 1));
+    }
+
+    #[test]
+    fn word_backward() {
+        let mut buffer = Buffer {
+            lines: vec!["Word Backward".to_string()].into(),
+            cursor: Vec2::new(8, 0),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.word_backward(false);
+        assert_eq!(buffer.cursor, Vec2::new(5, 0));
+    }
+
+    #[test]
+    fn word_backward_start_of_word() {
+        let mut buffer = Buffer {
+            lines: vec!["Word Backward".to_string()].into(),
+            cursor: Vec2::new(5, 0),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.word_backward(false);
+        assert_eq!(buffer.cursor, Vec2::new(0, 0));
+    }
+
+    #[test]
+    fn word_backward_non_alpha_numeric_character() {
+        let mut buffer = Buffer {
+            lines: vec!["foo.bar".to_string()].into(),
+            cursor: Vec2::new(4, 0),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.word_backward(false);
+        assert_eq!(buffer.cursor, Vec2::new(3, 0));
+    }
+
+    #[test]
+    fn word_backward_multiple_space_character() {
+        let mut buffer = Buffer {
+            lines: vec!["foo   bar".to_string()].into(),
+            cursor: Vec2::new(6, 0),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.word_backward(false);
+        assert_eq!(buffer.cursor, Vec2::new(0, 0));
+    }
+
+    #[test]
+    fn word_backward_start_of_buffer() {
+        let mut buffer = Buffer {
+            lines: vec!["  foo".to_string()].into(),
+            cursor: Vec2::new(2, 0),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.word_backward(false);
+        assert_eq!(buffer.cursor, Vec2::new(0, 0));
+    }
+
+    #[test]
+    fn word_backward_wrap_line() {
+        let mut buffer = Buffer {
+            lines: vec!["word1".to_string(), "word2".to_string()].into(),
+            cursor: Vec2::new(0, 1),
+            size: Vec2::new(100, 100),
+            pos: Vec2::default(),
+            offset: 0,
+            line_num: true,
+            ..Default::default()
+        };
+
+        buffer.word_backward(false);
+        assert_eq!(buffer.cursor, Vec2::new(0, 0));
     }
 
     #[test]
-    fn move_forward_wrap_line_offset() {
+    fn word_backward_wrap_line_trailing_space() {
         let mut buffer = Buffer {
-            lines: vec!["line1".to_string(), "line2".to_string()],
-            cursor: Vec2::new(4, 0),
-            offset: 0,
-            size: Vec2::new(100, 1),
+            lines: vec!["word1  ".to_string(), "  word2".to_string()].into(),
+            cursor: Vec2::new(2, 1),
+            size: Vec2::new(100, 100),
             pos: Vec2::default(),
+            offset: 0,
             line_num: true,
+            ..Default::default()
         };
 
-        buffer.move_forward();
-
-        assert_eq!(buffer.cursor, Vec2::new(0, 1));
-        assert_eq!(buffer.offset, 1);
+        buffer.word_backward(false);
+        assert_eq!(buffer.cursor, Vec2::new(0, 0));
     }
 
     #[test]
-    fn word_forward() {
+    fn word_backward_stops_at_empty_line() {
         let mut buffer = Buffer {
-            lines: vec!["Word Forward".to_string()],
-            cursor: Vec2::default(),
+            lines: vec!["word1".to_string(), "".to_string(), "word2".to_string()].into(),
+            cursor: Vec2::new(0, 2),
             size: Vec2::new(100, 100),
             pos: Vec2::default(),
             offset: 0,
             line_num: true,
+            ..Default::default()
         };
 
-        buffer.word_forward();
-
-        assert_eq!(buffer.cursor, Vec2::new(5, 0));
+        buffer.word_backward(false);
+        assert_eq!(buffer.cursor, Vec2::new(0, 1));
     }
 
     #[test]
-    fn word_forward_space_character() {
+    fn bigword_backward() {
         let mut buffer = Buffer {
-            lines: vec![" Word Forward".to_string()],
-            cursor: Vec2::default(),
+            lines: vec!["x foo.bar(baz)".to_string()].into(),
+            cursor: Vec2::new(11, 0),
             size: Vec2::new(100, 100),
             pos: Vec2::default(),
             offset: 0,
             line_num: true,
+            ..Default::default()
         };
 
-        buffer.word_forward();
-        assert_eq!(buffer.cursor, Vec2::new(1, 0));
+        buffer.word_backward(true);
+        assert_eq!(buffer.cursor, Vec2::new(2, 0));
     }
 
     #[test]
-    fn word_forward_multiple_space_character() {
+    fn word_end() {
         let mut buffer = Buffer {
-            lines: vec!["  Word Forward".to_string()],
-            cursor: Vec2::default(),
+            lines: vec!["Word End".to_string()].into(),
+            cursor: Vec2::new(0, 0),
             size: Vec2::new(100, 100),
             pos: Vec2::default(),
             offset: 0,
             line_num: true,
+            ..Default::default()
         };
 
-        buffer.word_forward();
-        assert_eq!(buffer.cursor, Vec2::new(2, 0));
+        buffer.word_end(false);
+        assert_eq!(buffer.cursor, Vec2::new(3, 0));
     }
 
     #[test]
-    fn word_forward_non_alpha_numeric_character() {
+    fn word_end_at_end_of_word() {
         let mut buffer = Buffer {
-            lines: vec![";Word Forward".to_string()],
-            cursor: Vec2::default(),
+            lines: vec!["Word End".to_string()].into(),
+            cursor: Vec2::new(3, 0),
             size: Vec2::new(100, 100),
             pos: Vec2::default(),
             offset: 0,
             line_num: true,
+            ..Default::default()
         };
 
-        buffer.word_forward();
-        assert_eq!(buffer.cursor, Vec2::new(1, 0));
+        buffer.word_end(false);
+        assert_eq!(buffer.cursor, Vec2::new(7, 0));
     }
 
     #[test]
-    fn word_forward_multiple_non_alpha_numeric_character() {
+    fn word_end_non_alpha_numeric_character() {
         let mut buffer = Buffer {
-            lines: vec![";;Word Forward".to_string()],
-            cursor: Vec2::default(),
+            lines: vec!["foo.,bar".to_string()].into(),
+            cursor: Vec2::new(2, 0),
             size: Vec2::new(100, 100),
             pos: Vec2::default(),
             offset: 0,
             line_num: true,
+            ..Default::default()
         };
 
-        buffer.word_forward();
-        assert_eq!(buffer.cursor, Vec2::new(2, 0));
+        buffer.word_end(false);
+        assert_eq!(buffer.cursor, Vec2::new(4, 0));
     }
 
     #[test]
-    fn word_forward_wrap_line() {
+    fn word_end_wrap_line() {
         let mut buffer = Buffer {
-            lines: vec!["word1".to_string(), "word2".to_string()],
-            cursor: Vec2::default(),
+            lines: vec!["word1".to_string(), "".to_string(), "  word2".to_string()].into(),
+            cursor: Vec2::new(4, 0),
             size: Vec2::new(100, 100),
             pos: Vec2::default(),
             offset: 0,
             line_num: true,
+            ..Default::default()
         };
 
-        buffer.word_forward();
-        assert_eq!(buffer.cursor, Vec2::new(0, 1));
+        buffer.word_end(false);
+        assert_eq!(buffer.cursor, Vec2::new(6, 2));
+    }
 
-        // next line starts with space
-        buffer = Buffer {
-            lines: vec!["word1".to_string(), " word2".to_string()],
-            cursor: Vec2::default(),
+    #[test]
+    fn word_end_end_of_buffer() {
+        let mut buffer = Buffer {
+            lines: vec!["word".to_string()].into(),
+            cursor: Vec2::new(3, 0),
             size: Vec2::new(100, 100),
             pos: Vec2::default(),
             offset: 0,
             line_num: true,
+            ..Default::default()
         };
 
-        buffer.word_forward();
-        assert_eq!(buffer.cursor, Vec2::new(1, 1));
+        buffer.word_end(false);
+        assert_eq!(buffer.cursor, Vec2::new(3, 0));
+    }
 
-        // next line is empty
-        buffer = Buffer {
-            lines: vec!["word1".to_string(), "".to_string()],
-            cursor: Vec2::default(),
+    #[test]
+    fn bigword_end() {
+        let mut buffer = Buffer {
+            lines: vec!["foo.bar baz".to_string()].into(),
+            cursor: Vec2::new(0, 0),
             size: Vec2::new(100, 100),
             pos: Vec2::default(),
             offset: 0,
             line_num: true,
+            ..Default::default()
         };
 
-        buffer.word_forward();
-        assert_eq!(buffer.cursor, Vec2::new(0, 1));
+        buffer.word_end(true);
+        assert_eq!(buffer.cursor, Vec2::new(6, 0));
+    }
 
-        // when current line has trailing space
-        buffer = Buffer {
-            lines: vec!["word1 ".to_string(), "word2".to_string()],
-            cursor: Vec2::default(),
+    #[test]
+    fn word_end_backward() {
+        let mut buffer = Buffer {
+            lines: vec!["Word End".to_string()].into(),
+            cursor: Vec2::new(6, 0),
             size: Vec2::new(100, 100),
             pos: Vec2::default(),
             offset: 0,
             line_num: true,
+            ..Default::default()
         };
 
-        buffer.word_forward();
-        assert_eq!(buffer.cursor, Vec2::new(0, 1));
+        buffer.word_end_backward(false);
+        assert_eq!(buffer.cursor, Vec2::new(3, 0));
+    }
 
-        // when current line has trailing space and next starts with space
-        buffer = Buffer {
-            lines: vec!["; ".to_string(), " word2".to_string()],
-            cursor: Vec2::default(),
+    #[test]
+    fn word_end_backward_non_alpha_numeric_character() {
+        let mut buffer = Buffer {
+            lines: vec!["foo.bar".to_string()].into(),
+            cursor: Vec2::new(5, 0),
             size: Vec2::new(100, 100),
             pos: Vec2::default(),
             offset: 0,
             line_num: true,
+            ..Default::default()
         };
 
-        buffer.word_forward();
-        assert_eq!(buffer.cursor, Vec2::new(1, 1));
+        buffer.word_end_backward(false);
+        assert_eq!(buffer.cursor, Vec2::new(3, 0));
+    }
 
-        buffer = Buffer {
-            lines: vec![
-                "weird ".to_string(),
-                " ".to_string(),
-                " scenario".to_string(),
-            ],
-            cursor: Vec2::default(),
+    #[test]
+    fn word_end_backward_wrap_line() {
+        let mut buffer = Buffer {
+            lines: vec!["word1  ".to_string(), "  word2".to_string()].into(),
+            cursor: Vec2::new(3, 1),
             size: Vec2::new(100, 100),
             pos: Vec2::default(),
             offset: 0,
             line_num: true,
+            ..Default::default()
         };
 
-        buffer.word_forward();
-        assert_eq!(buffer.cursor, Vec2::new(1, 2));
+        buffer.word_end_backward(false);
+        assert_eq!(buffer.cursor, Vec2::new(4, 0));
     }
 
     #[test]
-    fn word_forward_wrap_line_current_line_is_empty() {
+    fn word_end_backward_stops_at_empty_line() {
         let mut buffer = Buffer {
-            lines: vec!["".to_string(), "word".to_string()],
-            cursor: Vec2::default(),
+            lines: vec!["word1".to_string(), "".to_string(), "word2".to_string()].into(),
+            cursor: Vec2::new(2, 2),
             size: Vec2::new(100, 100),
             pos: Vec2::default(),
             offset: 0,
             line_num: true,
+            ..Default::default()
         };
 
-        buffer.word_forward();
+        buffer.word_end_backward(false);
         assert_eq!(buffer.cursor, Vec2::new(0, 1));
     }
 
     #[test]
-    fn word_forward_starts_white_space() {
+    fn word_end_backward_start_of_buffer() {
         let mut buffer = Buffer {
-            lines: vec!["    }".to_string(), "}".to_string()],
-            cursor: Vec2::default(),
+            lines: vec!["word".to_string()].into(),
+            cursor: Vec2::new(2, 0),
             size: Vec2::new(100, 100),
             pos: Vec2::default(),
             offset: 0,
             line_num: true,
+            ..Default::default()
         };
 
-        buffer.word_forward();
-        assert_eq!(buffer.cursor, Vec2::new(4, 0));
+        buffer.word_end_backward(false);
+        assert_eq!(buffer.cursor, Vec2::new(0, 0));
     }
 
     #[test]
-    fn word_forward_wrap_line_offset() {
+    fn bigword_end_backward() {
         let mut buffer = Buffer {
-            lines: vec![
-                "word1".to_string(),
-                "word2".to_string(),
-                "word3".to_string(),
-            ],
-            cursor: Vec2::default(),
-            size: Vec2::new(100, 1),
+            lines: vec!["foo.bar baz".to_string()].into(),
+            cursor: Vec2::new(9, 0),
+            size: Vec2::new(100, 100),
             pos: Vec2::default(),
             offset: 0,
             line_num: true,
+            ..Default::default()
         };
 
-        buffer.word_forward();
-        assert_eq!(buffer.cursor, Vec2::new(0, 1));
-        assert_eq!(buffer.offset, 1);
+        buffer.word_end_backward(true);
+        assert_eq!(buffer.cursor, Vec2::new(6, 0));
     }
 
     #[test]
-    fn clamp_cursor() {
+    fn move_cursor_last_character() {
         let mut buffer = Buffer {
-            lines: vec!["".to_string()],
-            cursor: Vec2::default(),
-            size: Vec2::new(100, 1),
+            lines: vec!["line".to_string()].into(),
+            cursor: Vec2::new(0, 0),
+            size: Vec2::new(100, 100),
             pos: Vec2::default(),
             offset: 0,
             line_num: true,
+            ..Default::default()
         };
 
-        buffer.insert_char('a');
-        buffer.clamp_cursor(false);
-
-        assert_eq!(buffer.cursor, Vec2::new(0, 0));
+        buffer.move_cursor_last_character();
+        assert_eq!(buffer.cursor, Vec2::new(3, 0));
     }
 
     #[test]
-    fn insert_char() {
+    fn move_cursor_first_non_blank() {
         let mut buffer = Buffer {
-            lines: vec!["".to_string()],
-            cursor: Vec2::default(),
-            size: Vec2::new(100, 1),
+            lines: vec!["   line".to_string()].into(),
+            cursor: Vec2::new(6, 0),
+            size: Vec2::new(100, 100),
             pos: Vec2::default(),
             offset: 0,
             line_num: true,
+            ..Default::default()
         };
 
-        buffer.insert_char('a');
-
-        assert_eq!(buffer.current_line(), "a");
-        assert_eq!(buffer.cursor, Vec2::new(1, 0));
+        buffer.move_cursor_first_non_blank();
+        assert_eq!(buffer.cursor, Vec2::new(3, 0));
     }
 
     #[test]
-    fn insert_line_after_cursor_last_line() {
+    fn move_cursor_first_non_blank_blank_line() {
         let mut buffer = Buffer {
-            lines: vec!["".to_string()],
-            cursor: Vec2::default(),
-            size: Vec2::new(100, 1),
+            lines: vec!["   ".to_string()].into(),
+            cursor: Vec2::new(0, 0),
+            size: Vec2::new(100, 100),
             pos: Vec2::default(),
             offset: 0,
             line_num: true,
+            ..Default::default()
         };
 
-        buffer.insert_line_after_cursor("hello".to_string());
-
-        assert_eq!(buffer.lines, vec!["", "hello"]);
-        assert_eq!(buffer.cursor, Vec2::default());
+        buffer.move_cursor_first_non_blank();
+        assert_eq!(buffer.cursor, Vec2::new(2, 0));
     }
 
     #[test]
-    fn insert_line_after_cursor_middle_line() {
+    fn goto_line() {
         let mut buffer = Buffer {
             lines: vec![
                 "line1".to_string(),
-                "line2".to_string(),
+                "  line2".to_string(),
                 "line3".to_string(),
-            ],
-            cursor: Vec2::new(2, 1),
+            ]
+            .into(),
+            cursor: Vec2::new(4, 0),
             size: Vec2::new(100, 1),
             pos: Vec2::default(),
             offset: 0,
             line_num: true,
+            ..Default::default()
         };
 
-        buffer.insert_line_after_cursor("inserted".to_string());
-
-        assert_eq!(buffer.lines, vec!["line1", "line2", "inserted", "line3"]);
+        buffer.goto_line(1);
         assert_eq!(buffer.cursor, Vec2::new(2, 1));
+        assert_eq!(buffer.offset, 1);
+
+        buffer.goto_line(usize::MAX);
+        assert_eq!(buffer.cursor, Vec2::new(0, 2));
+        assert_eq!(buffer.offset, 2);
+
+        buffer.goto_line(0);
+        assert_eq!(buffer.cursor, Vec2::new(0, 0));
+        assert_eq!(buffer.offset, 0);
+    }
+
+    fn find(c: char, forward: bool, till: bool) -> CharSearch {
+        CharSearch { c, forward, till }
     }
 
     #[test]
-    fn insert_new_line_start_of_current_line() {
+    fn find_char_forward() {
         let mut buffer = Buffer {
-            lines: vec![
-                "before".to_string(),
-                "cursor_line".to_string(),
-                "after".to_string(),
-            ],
-            cursor: Vec2::new(0, 1),
-            size: Vec2::new(100, 1),
-            pos: Vec2::default(),
-            offset: 0,
-            line_num: true,
+            lines: vec!["a,b,c,d".to_string()].into(),
+            ..Default::default()
         };
 
-        buffer.insert_new_line();
+        assert!(buffer.find_char(find(',', true, false), 1, false));
+        assert_eq!(buffer.cursor, Vec2::new(1, 0));
 
-        assert_eq!(buffer.lines, vec!["before", "", "cursor_line", "after"]);
-        assert_eq!(buffer.cursor, Vec2::new(0, 2));
+        assert!(buffer.find_char(find(',', true, false), 2, false));
+        assert_eq!(buffer.cursor, Vec2::new(5, 0));
+
+        assert!(!buffer.find_char(find(',', true, false), 1, false));
+        assert_eq!(buffer.cursor, Vec2::new(5, 0));
     }
 
     #[test]
-    fn insert_new_line_end_of_current_line() {
+    fn find_char_backward() {
         let mut buffer = Buffer {
-            lines: vec!["before".to_string(), "1".to_string(), "after".to_string()],
-            cursor: Vec2::new(1, 1),
-            size: Vec2::new(100, 1),
-            pos: Vec2::default(),
-            offset: 0,
-            line_num: true,
+            lines: vec!["a,b,c,d".to_string()].into(),
+            cursor: Vec2::new(6, 0),
+            ..Default::default()
         };
 
-        buffer.insert_new_line();
+        assert!(buffer.find_char(find(',', false, false), 2, false));
+        assert_eq!(buffer.cursor, Vec2::new(3, 0));
 
-        assert_eq!(buffer.lines, vec!["before", "1", "", "after"]);
-        assert_eq!(buffer.cursor, Vec2::new(0, 2));
+        assert!(!buffer.find_char(find(',', false, false), 2, false));
+        assert_eq!(buffer.cursor, Vec2::new(3, 0));
     }
 
     #[test]
-    fn insert_new_line_middle_of_current_line() {
+    fn find_char_till() {
         let mut buffer = Buffer {
-            lines: vec![
-                "before".to_string(),
-                "cursor_line".to_string(),
-                "after".to_string(),
-            ],
-            cursor: Vec2::new(6, 1),
-            size: Vec2::new(100, 1),
-            pos: Vec2::default(),
-            offset: 0,
-            line_num: true,
+            lines: vec!["a,b,c,d".to_string()].into(),
+            cursor: Vec2::new(2, 0),
+            ..Default::default()
         };
 
-        buffer.insert_new_line();
+        assert!(buffer.find_char(find(',', true, true), 1, false));
+        assert_eq!(buffer.cursor, Vec2::new(2, 0));
 
-        assert_eq!(buffer.lines, vec!["before", "cursor", "_line", "after"]);
-        assert_eq!(buffer.cursor, Vec2::new(0, 2));
+        assert!(buffer.find_char(find(',', true, true), 1, true));
+        assert_eq!(buffer.cursor, Vec2::new(4, 0));
+
+        assert!(buffer.find_char(find(',', false, true), 1, false));
+        assert_eq!(buffer.cursor, Vec2::new(4, 0));
+
+        assert!(buffer.find_char(find(',', false, true), 1, true));
+        assert_eq!(buffer.cursor, Vec2::new(2, 0));
     }
 
     #[test]
-    fn join_line() {
+    fn find_match_wraps_around() {
+        let buffer = Buffer {
+            lines: vec!["foo bar".to_string(), "bar foo".to_string()].into(),
+            ..Default::default()
+        };
+        let regex = Regex::new("foo").unwrap();
+
+        assert_eq!(
+            buffer.find_match(&regex, Vec2::new(0, 0), true),
+            Some(Vec2::new(4, 1))
+        );
+        assert_eq!(
+            buffer.find_match(&regex, Vec2::new(4, 1), true),
+            Some(Vec2::new(0, 0))
+        );
+        assert_eq!(
+            buffer.find_match(&regex, Vec2::new(0, 0), false),
+            Some(Vec2::new(4, 1))
+        );
+        assert_eq!(
+            buffer.find_match(&regex, Vec2::new(4, 1), false),
+            Some(Vec2::new(0, 0))
+        );
+    }
+
+    #[test]
+    fn find_match_only_match_is_under_cursor() {
+        let buffer = Buffer {
+            lines: vec!["a foo".to_string(), "b".to_string()].into(),
+            ..Default::default()
+        };
+        let regex = Regex::new("foo").unwrap();
+
+        assert_eq!(
+            buffer.find_match(&regex, Vec2::new(2, 0), true),
+            Some(Vec2::new(2, 0))
+        );
+        assert_eq!(
+            buffer.find_match(&regex, Vec2::new(2, 0), false),
+            Some(Vec2::new(2, 0))
+        );
+        assert_eq!(
+            buffer.find_match(&Regex::new("baz").unwrap(), Vec2::new(2, 0), true),
+            None
+        );
+    }
+
+    #[test]
+    fn word_under_cursor() {
         let mut buffer = Buffer {
-            lines: vec!["line1".to_string(), "line2".to_string()],
-            cursor: Vec2::new(0, 0),
-            size: Vec2::new(100, 100),
-            pos: Vec2::default(),
-            offset: 0,
-            line_num: true,
+            lines: vec!["(foo_bar) baz".to_string()].into(),
+            ..Default::default()
         };
 
-        buffer.join_line();
+        assert_eq!(buffer.word_under_cursor(), Some((1, "foo_bar".to_string())));
 
-        assert_eq!(buffer.lines, vec!["line1 line2"]);
-        assert_eq!(buffer.cursor, Vec2::new(5, 0));
+        buffer.cursor.x = 5;
+        assert_eq!(buffer.word_under_cursor(), Some((1, "foo_bar".to_string())));
+
+        buffer.cursor.x = 8;
+        assert_eq!(buffer.word_under_cursor(), Some((10, "baz".to_string())));
+
+        buffer.lines = vec!["a ()".to_string()].into();
+        buffer.cursor.x = 1;
+        assert_eq!(buffer.word_under_cursor(), None);
     }
 
     #[test]
-    fn backspace_cursor_one_off() {
+    fn edits_set_modified() {
         let mut buffer = Buffer {
-            lines: vec!["0123456".to_string()],
-            cursor: Vec2::new(7, 0),
-            size: Vec2::new(100, 100),
-            pos: Vec2::default(),
-            offset: 0,
-            line_num: true,
+            lines: vec!["a".to_string()].into(),
+            ..Default::default()
         };
+        assert!(!buffer.is_modified());
 
-        buffer.backspace();
+        buffer.insert_char('b');
+        assert!(buffer.is_modified());
 
-        assert_eq!(buffer.lines, vec!["012345"]);
-        assert_eq!(buffer.cursor, Vec2::new(6, 0));
+        let path = std::env::temp_dir().join(format!("vipi-modified-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        assert_eq!(buffer.write_to_file(path).unwrap(), 2);
+        assert!(!buffer.is_modified());
+        assert_eq!(buffer.file_path(), Some(path));
+
+        buffer.commit_undo_step();
+        buffer.undo();
+        assert!(buffer.is_modified());
+
+        std::fs::remove_file(path).unwrap();
     }
 
+    /// A letter with a combining mark, a family emoji joined with ZWJs and
+    /// two CJK characters, starting at columns 0, 3, 4, 22 and 25.
+    const CLUSTERS: &str = "e\u{301}t\u{1f469}\u{200d}\u{1f469}\u{200d}\u{1f467}\u{4e2d}\u{6587}";
+
     #[test]
-    fn backspace_cursor_middle() {
+    fn grapheme_offsets() {
+        let buffer = Buffer {
+            lines: vec![CLUSTERS.to_string()].into(),
+            ..Default::default()
+        };
+
+        assert_eq!(buffer.grapheme_to_byte(0, 1), 3);
+        assert_eq!(buffer.grapheme_to_byte(0, 3), 22);
+        assert_eq!(buffer.grapheme_to_byte(0, 5), 28);
+        assert_eq!(buffer.byte_to_grapheme(Vec2::new(22, 0)), 3);
+        assert_eq!(buffer.byte_to_grapheme(Vec2::new(28, 0)), 5);
+
+        assert_eq!(buffer.char_to_byte(0, 2), 3);
+        assert_eq!(buffer.char_to_byte(0, 8), 22);
+        assert_eq!(buffer.byte_to_char(Vec2::new(22, 0)), 8);
+        assert_eq!(buffer.byte_to_char(Vec2::new(28, 0)), 10);
+
+        assert_eq!(buffer.next_grapheme(Vec2::new(4, 0)), 22);
+        assert_eq!(buffer.next_grapheme(Vec2::new(25, 0)), 28);
+        assert_eq!(buffer.prev_grapheme(Vec2::new(22, 0)), 4);
+        assert_eq!(buffer.prev_grapheme(Vec2::new(3, 0)), 0);
+    }
+
+    #[test]
+    fn cursor_moves_over_grapheme_clusters() {
         let mut buffer = Buffer {
-            lines: vec!["0123456".to_string()],
-            cursor: Vec2::new(5, 0),
-            size: Vec2::new(100, 100),
-            pos: Vec2::default(),
-            offset: 0,
-            line_num: true,
+            lines: vec![CLUSTERS.to_string()].into(),
+            ..Default::default()
         };
 
-        buffer.backspace();
+        let mut columns = vec![buffer.cursor.x];
+        for _ in 0..5 {
+            buffer.move_cursor_right(false);
+            columns.push(buffer.cursor.x);
+        }
+        assert_eq!(columns, vec![0, 3, 4, 22, 25, 25]);
 
-        assert_eq!(buffer.lines, vec!["012356"]);
-        assert_eq!(buffer.cursor, Vec2::new(4, 0));
+        buffer.move_cursor_right(true);
+        assert_eq!(buffer.cursor.x, 28);
+
+        buffer.move_cursor_last_character();
+        buffer.move_cursor_left();
+        buffer.move_cursor_left();
+        assert_eq!(buffer.cursor.x, 4);
+
+        // a column inside a cluster is moved to its start
+        buffer.set_cursor(Vec2::new(10, 0));
+        assert_eq!(buffer.cursor.x, 4);
+        buffer.set_cursor(Vec2::new(1, 0));
+        assert_eq!(buffer.cursor.x, 0);
     }
 
     #[test]
-    fn backspace_cursor_start() {
+    fn edit_grapheme_clusters() {
         let mut buffer = Buffer {
-            lines: vec!["0123".to_string(), "4567".to_string()],
-            cursor: Vec2::new(0, 1),
-            size: Vec2::new(100, 100),
-            pos: Vec2::default(),
-            offset: 0,
-            line_num: true,
+            lines: vec![CLUSTERS.to_string()].into(),
+            ..Default::default()
         };
 
+        buffer.cursor.x = 22;
         buffer.backspace();
+        assert_eq!(buffer.lines, vec!["e\u{301}t\u{4e2d}\u{6587}"]);
+        assert_eq!(buffer.cursor.x, 4);
+
+        buffer.cursor.x = 0;
+        buffer.delete_char();
+        assert_eq!(buffer.lines, vec!["t\u{4e2d}\u{6587}"]);
+
+        buffer.cursor.x = 4;
+        buffer.insert_char('\u{5b57}');
+        assert_eq!(buffer.lines, vec!["t\u{4e2d}\u{5b57}\u{6587}"]);
+        assert_eq!(buffer.cursor.x, 7);
+
+        buffer.cursor.x = 1;
+        assert!(buffer.replace_chars('x', 3));
+        assert_eq!(buffer.lines, vec!["txxx"]);
+        assert!(!buffer.replace_chars('x', 4));
+
+        buffer.lines = vec!["a\u{301}b".to_string()].into();
+        buffer.cursor.x = 0;
+        assert_eq!(buffer.overwrite_char('c'), Some("a\u{301}".to_string()));
+        assert_eq!(buffer.lines, vec!["cb"]);
+    }
 
-        assert_eq!(buffer.lines, vec!["01234567"]);
-        assert_eq!(buffer.cursor, Vec2::new(4, 0));
+    #[test]
+    fn word_motions_over_multibyte_text() {
+        let mut buffer = Buffer {
+            lines: vec!["cafe\u{301} \u{4e2d}\u{6587} \u{1f44d}\u{1f3fd}!".to_string()].into(),
+            ..Default::default()
+        };
+
+        buffer.word_end(false);
+        assert_eq!(buffer.cursor.x, 3);
+        buffer.word_forward(false);
+        assert_eq!(buffer.cursor.x, 7);
+        buffer.word_end(false);
+        assert_eq!(buffer.cursor.x, 10);
+        buffer.word_forward(false);
+        assert_eq!(buffer.cursor.x, 14);
+        buffer.word_backward(false);
+        assert_eq!(buffer.cursor.x, 7);
+
+        let search = |c, till| CharSearch {
+            c,
+            forward: true,
+            till,
+        };
+        buffer.cursor.x = 0;
+        assert!(buffer.find_char(search('!', true), 1, false));
+        assert_eq!(buffer.cursor.x, 14);
+
+        buffer.cursor.x = 0;
+        assert!(buffer.find_char(search('\u{6587}', false), 1, false));
+        assert_eq!(buffer.cursor.x, 10);
+        assert!(!buffer.find_char(search('\u{6587}', false), 1, false));
+    }
+
+    /// Output of rendering `buffer`, with the cursor put at the end.
+    fn rendered(buffer: &Buffer) -> String {
+        let mut out = Vec::new();
+        buffer.render(&mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    fn ends_with_cursor_at(out: &str, x: u16, y: u16) -> bool {
+        out.ends_with(&termion::cursor::Goto(x, y).to_string())
+    }
+
+    #[test]
+    fn render_tabs_and_control_characters() {
+        let mut buffer = Buffer::from_lines(
+            vec!["a\tb\r\u{4e2d}x\u{85}\u{10ffff}".to_string()],
+            Vec2::default(),
+            Vec2::new(80, 5),
+            false,
+        );
+        buffer.set_cursor(Vec2::new(7, 0));
+
+        // the tab takes 7 cells, `^M` 2 and the CJK character 2
+        let out = rendered(&buffer);
+        assert!(ends_with_cursor_at(&out, 14, 1));
+        assert!(out.contains(&format!("{}^", termion::color::Fg(termion::color::Blue))));
+
+        let escapes = Regex::new("\x1b\\[[0-9;]*[A-Za-z]").unwrap();
+        let text = escapes.replace_all(&out, "");
+        assert_eq!(text, "a       b^M\u{4e2d}x<85><ff>");
+
+        buffer.set_tabstop(4);
+        assert!(ends_with_cursor_at(&rendered(&buffer), 10, 1));
+
+        buffer.set_cursor(Vec2::new(2, 0));
+        assert!(ends_with_cursor_at(&rendered(&buffer), 5, 1));
+    }
+
+    #[test]
+    fn render_wraps_before_wide_characters() {
+        let mut buffer = Buffer::from_lines(
+            vec!["abc\u{4e2d}d".to_string(), "\t\te".to_string()],
+            Vec2::default(),
+            Vec2::new(4, 5),
+            false,
+        );
+
+        buffer.set_cursor(Vec2::new(3, 0));
+        assert!(ends_with_cursor_at(&rendered(&buffer), 1, 2));
+        buffer.set_cursor(Vec2::new(6, 0));
+        assert!(ends_with_cursor_at(&rendered(&buffer), 3, 2));
+
+        // tabs are split across rows
+        buffer.set_cursor(Vec2::new(2, 1));
+        assert!(ends_with_cursor_at(&rendered(&buffer), 1, 7));
     }
 }
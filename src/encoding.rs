@@ -0,0 +1,385 @@
+use std::convert::TryFrom;
+use std::io::{self, Write};
+
+/// Private use character standing for byte 0 of a file, where the bytes
+/// that aren't valid in its encoding map to the last 256 characters from
+/// here on, so that they are written back as they were.
+const ESCAPES: u32 = 0x10ff00;
+
+/// Bytes looked at to tell whether a file is binary, like git does.
+const BINARY_CHECK_LEN: usize = 8000;
+
+/// Encoding of a file, the `fileencoding` option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+}
+
+/// Contents of a file turned into text.
+#[derive(Debug)]
+pub struct Decoded {
+    pub text: String,
+    pub encoding: Encoding,
+    /// Whether the file starts with a byte order mark.
+    pub bom: bool,
+    /// Whether the file has a NUL byte, which text files don't.
+    pub binary: bool,
+    /// Whether bytes that aren't valid in `encoding` are kept in `text` as
+    /// escapes, which only then stand for anything but themselves.
+    pub escaped: bool,
+}
+
+impl Encoding {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "utf-16le" => Some(Encoding::Utf16Le),
+            "utf-16" | "utf-16be" => Some(Encoding::Utf16Be),
+            "latin1" | "iso-8859-1" => Some(Encoding::Latin1),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+            Encoding::Latin1 => "latin1",
+        }
+    }
+
+    fn bom(self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8 => b"\xef\xbb\xbf",
+            Encoding::Utf16Le => b"\xff\xfe",
+            Encoding::Utf16Be => b"\xfe\xff",
+            Encoding::Latin1 => b"",
+        }
+    }
+
+    fn is_utf16(self) -> bool {
+        self == Encoding::Utf16Le || self == Encoding::Utf16Be
+    }
+
+    /// Turns the contents of a file into text, telling their encoding from
+    /// a byte order mark, or else from whether they look like UTF-8. Bytes
+    /// that aren't valid in a file that otherwise is are kept as escapes,
+    /// unless it also has the characters they'd be told apart from, when
+    /// it's read as Latin-1 to keep every byte.
+    pub fn decode(bytes: Vec<u8>) -> Decoded {
+        for encoding in [Encoding::Utf8, Encoding::Utf16Le, Encoding::Utf16Be] {
+            if let Some(rest) = bytes.strip_prefix(encoding.bom()) {
+                if let Some((text, escaped)) = encoding.decode_without_bom(rest) {
+                    return Decoded {
+                        text,
+                        encoding,
+                        bom: true,
+                        binary: false,
+                        escaped,
+                    };
+                }
+            }
+        }
+
+        let binary = bytes.iter().take(BINARY_CHECK_LEN).any(|b| *b == 0);
+        let bytes = match String::from_utf8(bytes) {
+            Ok(text) => {
+                return Decoded {
+                    text,
+                    encoding: Encoding::Utf8,
+                    bom: false,
+                    binary,
+                    escaped: false,
+                }
+            }
+            Err(err) => err.into_bytes(),
+        };
+
+        // without any valid multibyte sequence, the file is more likely to
+        // be in Latin-1 than UTF-8 with stray bytes
+        let multibyte = bytes.utf8_chunks().any(|chunk| !chunk.valid().is_ascii());
+        let encoding = if multibyte {
+            Encoding::Utf8
+        } else {
+            Encoding::Latin1
+        };
+        let (encoding, (text, escaped)) = match encoding.decode_without_bom(&bytes) {
+            Some(decoded) => (encoding, decoded),
+            None => (
+                Encoding::Latin1,
+                Encoding::Latin1.decode_without_bom(&bytes).unwrap(),
+            ),
+        };
+
+        Decoded {
+            text,
+            encoding,
+            bom: false,
+            binary,
+            escaped,
+        }
+    }
+
+    /// Turns `bytes` into text, with the bytes that aren't valid in this
+    /// encoding as escapes, and whether there were any. Returns `None` when
+    /// the text also has characters that escapes stand for.
+    fn decode_without_bom(self, bytes: &[u8]) -> Option<(String, bool)> {
+        let mut text = String::with_capacity(bytes.len());
+        let mut escaped = false;
+        let mut collides = false;
+        match self {
+            Encoding::Utf8 => {
+                for chunk in bytes.utf8_chunks() {
+                    collides |= chunk.valid().chars().any(|c| unescape(c).is_some());
+                    text.push_str(chunk.valid());
+                    text.extend(chunk.invalid().iter().map(|b| escape(*b)));
+                    escaped |= !chunk.invalid().is_empty();
+                }
+            }
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let pairs = bytes.chunks_exact(2);
+                let odd = pairs.remainder();
+                let units = pairs.map(|pair| match self {
+                    Encoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+                    _ => u16::from_be_bytes([pair[0], pair[1]]),
+                });
+                for c in char::decode_utf16(units) {
+                    match c {
+                        Ok(c) => {
+                            collides |= unescape(c).is_some();
+                            text.push(c);
+                        }
+                        Err(err) => {
+                            let unit = err.unpaired_surrogate();
+                            let bytes = match self {
+                                Encoding::Utf16Le => unit.to_le_bytes(),
+                                _ => unit.to_be_bytes(),
+                            };
+                            text.extend(bytes.iter().map(|b| escape(*b)));
+                            escaped = true;
+                        }
+                    }
+                }
+                text.extend(odd.iter().map(|b| escape(*b)));
+                escaped |= !odd.is_empty();
+            }
+            Encoding::Latin1 => text.extend(bytes.iter().map(|b| char::from(*b))),
+        }
+
+        (!(escaped && collides)).then_some((text, escaped))
+    }
+
+    /// Whether every character of `chunks` can be written in this
+    /// encoding, checked before the file is overwritten. `escapes` is the
+    /// encoding of the file whose bytes the text has as escapes, which are
+    /// only written back in encodings that keep them as they were.
+    pub fn can_encode<'a>(
+        self,
+        mut chunks: impl Iterator<Item = &'a str>,
+        escapes: Option<Encoding>,
+    ) -> bool {
+        let keeps_escapes =
+            escapes.is_some_and(|from| from == self || !(from.is_utf16() || self.is_utf16()));
+        chunks.all(|chunk| {
+            chunk.chars().all(|c| match (escapes, unescape(c)) {
+                (Some(_), Some(_)) => keeps_escapes,
+                _ => self != Encoding::Latin1 || (c as u32) <= 0xff,
+            })
+        })
+    }
+
+    /// Writes `chunks` in this encoding, after a byte order mark when `bom`
+    /// is set or the encoding needs one to be told apart, with escapes as
+    /// the bytes they stand for when `escaped` is set. Returns the number
+    /// of bytes written.
+    pub fn write(
+        self,
+        chunks: impl Iterator<Item = impl AsRef<str>>,
+        bom: bool,
+        escaped: bool,
+        mut writer: impl Write,
+    ) -> io::Result<usize> {
+        let mut written = 0;
+        if bom || self.is_utf16() {
+            writer.write_all(self.bom())?;
+            written += self.bom().len();
+        }
+
+        let mut bytes = Vec::new();
+        for chunk in chunks {
+            let chunk = chunk.as_ref();
+            // escapes are encoded in UTF-8 as 4 bytes starting with 0xf4
+            if self == Encoding::Utf8 && !(escaped && chunk.as_bytes().contains(&0xf4)) {
+                writer.write_all(chunk.as_bytes())?;
+                written += chunk.len();
+                continue;
+            }
+
+            bytes.clear();
+            for c in chunk.chars() {
+                match (self, unescape(c).filter(|_| escaped)) {
+                    (_, Some(b)) => bytes.push(b),
+                    (Encoding::Utf8, None) => {
+                        bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes())
+                    }
+                    (Encoding::Utf16Le, None) => c
+                        .encode_utf16(&mut [0; 2])
+                        .iter()
+                        .for_each(|unit| bytes.extend_from_slice(&unit.to_le_bytes())),
+                    (Encoding::Utf16Be, None) => c
+                        .encode_utf16(&mut [0; 2])
+                        .iter()
+                        .for_each(|unit| bytes.extend_from_slice(&unit.to_be_bytes())),
+                    (Encoding::Latin1, None) => bytes.push(c as u8),
+                }
+            }
+            writer.write_all(&bytes)?;
+            written += bytes.len();
+        }
+
+        Ok(written)
+    }
+}
+
+fn escape(b: u8) -> char {
+    char::from_u32(ESCAPES + u32::from(b)).unwrap()
+}
+
+/// Byte of a file that isn't valid in its encoding which `c` stands for,
+/// if the file had any.
+pub fn unescape(c: char) -> Option<u8> {
+    (c as u32)
+        .checked_sub(ESCAPES)
+        .and_then(|b| u8::try_from(b).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(bytes: &[u8]) -> (Decoded, Vec<u8>) {
+        let decoded = Encoding::decode(bytes.to_vec());
+        let mut written = Vec::new();
+        let chunks = decoded.text.split_inclusive('\n');
+        let len = decoded
+            .encoding
+            .write(chunks, decoded.bom, decoded.escaped, &mut written)
+            .unwrap();
+        assert_eq!(len, written.len());
+        (decoded, written)
+    }
+
+    #[test]
+    fn detect_encodings() {
+        let cases: &[(&[u8], &str, Encoding, bool)] = &[
+            (b"caf\xc3\xa9\n", "café\n", Encoding::Utf8, false),
+            (b"\xef\xbb\xbfcaf\xc3\xa9", "café", Encoding::Utf8, true),
+            (
+                b"\xff\xfec\x00\xe9\x00\n\x00",
+                "cé\n",
+                Encoding::Utf16Le,
+                true,
+            ),
+            (
+                b"\xfe\xff\x00c\xd8\x3d\xdc\x4d",
+                "c👍",
+                Encoding::Utf16Be,
+                true,
+            ),
+            (b"caf\xe9 na\xefve", "café naïve", Encoding::Latin1, false),
+        ];
+
+        for (bytes, text, encoding, bom) in cases {
+            let (decoded, written) = round_trip(bytes);
+            assert_eq!(decoded.text, *text);
+            assert_eq!(decoded.encoding, *encoding);
+            assert_eq!(decoded.bom, *bom);
+            assert_eq!(written, *bytes);
+        }
+    }
+
+    #[test]
+    fn keep_invalid_utf8_bytes() {
+        let bytes = b"caf\xc3\xa9 \xff\xc3 \xe2\x82\n";
+        let (decoded, written) = round_trip(bytes);
+
+        assert_eq!(decoded.encoding, Encoding::Utf8);
+        assert!(decoded.text.starts_with("café "));
+        assert_eq!(
+            decoded
+                .text
+                .chars()
+                .filter(|c| unescape(*c).is_some())
+                .count(),
+            4
+        );
+        assert_eq!(written, bytes);
+    }
+
+    #[test]
+    fn keep_characters_escapes_stand_for() {
+        // U+10FF85 is only a byte in files that need escapes
+        let bytes = "a\u{10ff85}b\n".as_bytes();
+        let (decoded, written) = round_trip(bytes);
+        assert_eq!(decoded.encoding, Encoding::Utf8);
+        assert!(!decoded.escaped);
+        assert_eq!(written, bytes);
+
+        // together with invalid bytes they couldn't be told apart
+        let bytes = [bytes, b"\xff"].concat();
+        let (decoded, written) = round_trip(&bytes);
+        assert_eq!(decoded.encoding, Encoding::Latin1);
+        assert_eq!(written, bytes);
+    }
+
+    #[test]
+    fn keep_invalid_utf16_units() {
+        let cases: &[&[u8]] = &[
+            b"\xff\xfea\x00\x00\xd8b\x00",
+            b"\xfe\xff\x00a\xdc\x00\x00b",
+            b"\xff\xfea\x00b",
+            b"\xfe\xff\xd8\x3d\xdc\x4d\x00",
+        ];
+
+        for bytes in cases {
+            let (decoded, written) = round_trip(bytes);
+            assert!(decoded.escaped);
+            assert!(!decoded.text.contains(char::REPLACEMENT_CHARACTER));
+            assert_eq!(written, *bytes);
+        }
+    }
+
+    #[test]
+    fn detect_binary_files() {
+        assert!(Encoding::decode(b"\x7fELF\x02\x00\x00".to_vec()).binary);
+        assert!(!Encoding::decode(b"text".to_vec()).binary);
+        assert!(!Encoding::decode(b"\xff\xfea\x00".to_vec()).binary);
+    }
+
+    #[test]
+    fn convert_between_encodings() {
+        let mut written = Vec::new();
+        let chunks = || ["café ", "👍"].iter().copied();
+
+        assert!(!Encoding::Latin1.can_encode(chunks(), None));
+        assert!(Encoding::Utf16Le.can_encode(chunks(), None));
+        Encoding::Utf16Le
+            .write(chunks(), false, false, &mut written)
+            .unwrap();
+        assert_eq!(&written[..4], b"\xff\xfec\x00");
+
+        let escaped = escape(0xe9).to_string();
+        let text = ["é", escaped.as_str()];
+        let chunks = || text.iter().copied();
+        let utf8 = Some(Encoding::Utf8);
+        assert!(Encoding::Latin1.can_encode(chunks(), utf8));
+        assert!(!Encoding::Utf16Be.can_encode(chunks(), utf8));
+        assert!(Encoding::Utf16Be.can_encode(chunks(), None));
+        assert!(!Encoding::Utf16Be.can_encode(chunks(), Some(Encoding::Utf16Le)));
+    }
+}
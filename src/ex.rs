@@ -0,0 +1,548 @@
+use std::io;
+use std::path::Path;
+
+use crate::buffer::Buffer;
+use crate::mark;
+use crate::search::Search;
+use crate::substitute::Substitute;
+use crate::{Mode, State};
+
+/// Lines an Ex command applies to, zero-based and inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl LineRange {
+    pub fn line(y: usize) -> Self {
+        Self { start: y, end: y }
+    }
+}
+
+/// An Ex command as typed, like `:2,$s/a/b/g` or `:w! path`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExCommand<'a> {
+    pub range: Option<LineRange>,
+    pub name: &'a str,
+    pub bang: bool,
+    pub args: &'a str,
+}
+
+/// Handler of an Ex command. Returns `Ok(true)` when vipi should quit.
+type Run = fn(&mut State, &ExCommand) -> Result<bool, String>;
+
+struct CommandSpec {
+    name: &'static str,
+    /// Length of the shortest abbreviation, e.g. 1 for `w[rite]`.
+    min_len: usize,
+    range: bool,
+    bang: bool,
+    args: bool,
+    run: Run,
+}
+
+const COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "nohlsearch",
+        min_len: 3,
+        range: false,
+        bang: false,
+        args: false,
+        run: nohlsearch,
+    },
+    CommandSpec {
+        name: "qall",
+        min_len: 2,
+        range: false,
+        bang: true,
+        args: false,
+        run: quit,
+    },
+    CommandSpec {
+        name: "quit",
+        min_len: 1,
+        range: false,
+        bang: true,
+        args: false,
+        run: quit,
+    },
+    CommandSpec {
+        name: "saveas",
+        min_len: 3,
+        range: false,
+        bang: true,
+        args: true,
+        run: saveas,
+    },
+    CommandSpec {
+        name: "set",
+        min_len: 2,
+        range: false,
+        bang: false,
+        args: true,
+        run: set,
+    },
+    CommandSpec {
+        name: "substitute",
+        min_len: 1,
+        range: true,
+        bang: false,
+        args: true,
+        run: substitute,
+    },
+    CommandSpec {
+        name: "wq",
+        min_len: 2,
+        range: false,
+        bang: true,
+        args: true,
+        run: write_quit,
+    },
+    CommandSpec {
+        name: "write",
+        min_len: 1,
+        range: false,
+        bang: true,
+        args: true,
+        run: write,
+    },
+    CommandSpec {
+        name: "xit",
+        min_len: 1,
+        range: false,
+        bang: true,
+        args: true,
+        run: exit,
+    },
+];
+
+fn lookup(name: &str) -> Option<&'static CommandSpec> {
+    COMMANDS
+        .iter()
+        .find(|command| name.len() >= command.min_len && command.name.starts_with(name))
+}
+
+/// Splits a command line into the commands chained with `|`. A `\|` is
+/// kept as a plain `|`.
+pub fn split(input: &str) -> Vec<String> {
+    let mut commands = vec![String::new()];
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'|') => {
+                commands.last_mut().unwrap().push('|');
+                chars.next();
+            }
+            '|' => commands.push(String::new()),
+            c => commands.last_mut().unwrap().push(c),
+        }
+    }
+
+    commands
+}
+
+/// Parses a single command, resolving its range against the buffer.
+pub fn parse<'a>(input: &'a str, buffer: &Buffer) -> Result<ExCommand<'a>, String> {
+    let input = input.trim_start_matches(|c: char| c == ':' || c.is_whitespace());
+    let (range, rest) = parse_range(input, buffer)?;
+    let rest = rest.trim_start();
+
+    let name_len = rest
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(rest.len());
+    let (name, rest) = rest.split_at(name_len);
+    let (bang, args) = match rest.strip_prefix('!') {
+        Some(args) => (true, args),
+        None => (false, rest),
+    };
+
+    Ok(ExCommand {
+        range,
+        name,
+        bang,
+        args: args.trim(),
+    })
+}
+
+impl State {
+    /// Runs the commands typed after `:`, stopping at the first error,
+    /// which is shown in the message area. Returns `true` when vipi should
+    /// quit.
+    pub fn execute(&mut self, input: &str) -> bool {
+        for command in split(input) {
+            match self.execute_one(&command) {
+                Ok(true) => return true,
+                Ok(false) => {}
+                Err(error) => {
+                    self.message = Some(error);
+                    self.failed = true;
+                    return false;
+                }
+            }
+        }
+
+        false
+    }
+
+    fn execute_one(&mut self, input: &str) -> Result<bool, String> {
+        let command = parse(input, &self.buffer)?;
+
+        if command.name.is_empty() {
+            if !command.args.is_empty() || command.bang {
+                return Err(format!("E492: Not an editor command: {}", input.trim()));
+            }
+
+            // a range alone jumps to its last line
+            if let Some(range) = command.range {
+                let from = self.buffer.cursor();
+                self.buffer.goto_line(range.end);
+                self.buffer.marks_mut().push_jump(from);
+            }
+            return Ok(false);
+        }
+
+        let spec = lookup(command.name)
+            .ok_or_else(|| format!("E492: Not an editor command: {}", input.trim()))?;
+
+        if command.range.is_some() && !spec.range {
+            return Err("E481: No range allowed".to_string());
+        }
+        if command.bang && !spec.bang {
+            return Err("E477: No ! allowed".to_string());
+        }
+        if !command.args.is_empty() && !spec.args {
+            return Err(format!("E488: Trailing characters: {}", command.args));
+        }
+
+        (spec.run)(self, &command)
+    }
+}
+
+fn nohlsearch(state: &mut State, _: &ExCommand) -> Result<bool, String> {
+    state.buffer.clear_hlsearch();
+    Ok(false)
+}
+
+fn quit(state: &mut State, command: &ExCommand) -> Result<bool, String> {
+    if state.buffer.is_modified() && !command.bang {
+        return Err("E37: No write since last change (add ! to override)".to_string());
+    }
+
+    Ok(true)
+}
+
+fn set(state: &mut State, command: &ExCommand) -> Result<bool, String> {
+    state.set_options(command.args)?;
+    Ok(false)
+}
+
+fn substitute(state: &mut State, command: &ExCommand) -> Result<bool, String> {
+    let range = command
+        .range
+        .unwrap_or_else(|| LineRange::line(state.buffer.cursor().y));
+    let last_search = state.buffer.search().map(|search| &search.regex);
+    let mut substitute = Substitute::parse(command.args, range, last_search)?;
+
+    state.buffer.set_search(Search {
+        regex: substitute.regex.clone(),
+        forward: true,
+    });
+
+    if !substitute.confirm {
+        substitute.run(&mut state.buffer);
+    } else if let Some(pos) = substitute.next(&state.buffer) {
+        state.buffer.set_cursor(pos);
+        state.substitute = Some(substitute);
+        state.mode = Mode::Confirm;
+        return Ok(false);
+    }
+
+    state.finish_substitute(substitute);
+    Ok(false)
+}
+
+fn write(state: &mut State, command: &ExCommand) -> Result<bool, String> {
+    write_buffer(state, command.args, command.bang)?;
+    Ok(false)
+}
+
+fn write_quit(state: &mut State, command: &ExCommand) -> Result<bool, String> {
+    write_buffer(state, command.args, command.bang)?;
+    Ok(true)
+}
+
+/// Like `:wq`, but only writes when there are changes.
+fn exit(state: &mut State, command: &ExCommand) -> Result<bool, String> {
+    if state.buffer.is_modified() {
+        write_buffer(state, command.args, command.bang)?;
+    }
+    Ok(true)
+}
+
+fn saveas(state: &mut State, command: &ExCommand) -> Result<bool, String> {
+    if command.args.is_empty() {
+        return Err("E471: Argument required".to_string());
+    }
+
+    if !command.bang && Path::new(command.args).exists() {
+        return Err("E13: File exists (add ! to override)".to_string());
+    }
+
+    state.buffer.set_file_path(command.args);
+    write_buffer(state, "", true)?;
+    Ok(false)
+}
+
+/// Writes the buffer to `path`, or to its own file when `path` is empty.
+/// Overwriting another existing file requires `force`.
+fn write_buffer(state: &mut State, path: &str, force: bool) -> Result<(), String> {
+    let path = match (path, state.buffer.file_path()) {
+        ("", Some(file_path)) => file_path.to_string(),
+        ("", None) => return Err("E32: No file name".to_string()),
+        (path, _) => path.to_string(),
+    };
+
+    if !force && state.buffer.file_path() != Some(&path) && Path::new(&path).exists() {
+        return Err("E13: File exists (add ! to override)".to_string());
+    }
+
+    let bytes = state
+        .buffer
+        .write_to_file(&path)
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::InvalidData => {
+                format!("\"{}\" E513: Write error, conversion failed", path)
+            }
+            _ => format!("\"{}\" E212: Can't open file for writing", path),
+        })?;
+
+    state.message = Some(format!(
+        "\"{}\" {}L, {}B written",
+        path,
+        state.buffer.line_count(),
+        bytes
+    ));
+    Ok(())
+}
+
+/// Parses the range at the start of an Ex command, returning it with the
+/// rest of the command. Addresses are line numbers, `.`, `$` or marks, each
+/// followed by any number of `+N` and `-N` offsets. `%` is the whole buffer.
+pub fn parse_range<'a>(
+    input: &'a str,
+    buffer: &Buffer,
+) -> Result<(Option<LineRange>, &'a str), String> {
+    if let Some(rest) = input.strip_prefix('%') {
+        let range = LineRange {
+            start: 0,
+            end: buffer.line_count() - 1,
+        };
+        return Ok((Some(range), rest));
+    }
+
+    let cursor = buffer.cursor().y as i64 + 1;
+
+    let (start, rest) = parse_address(input, buffer)?;
+    let (end, rest) = match rest.strip_prefix(|c| c == ',' || c == ';') {
+        Some(rest) => {
+            let (end, rest) = parse_address(rest, buffer)?;
+            (Some(end.unwrap_or(cursor)), rest)
+        }
+        None => (start, rest),
+    };
+
+    let (start, end) = match (start, end) {
+        (None, None) => return Ok((None, rest)),
+        (start, end) => (
+            to_line(start.unwrap_or(cursor), buffer)?,
+            to_line(end.unwrap_or(cursor), buffer)?,
+        ),
+    };
+
+    let range = LineRange {
+        start: start.min(end),
+        end: start.max(end),
+    };
+    Ok((Some(range), rest))
+}
+
+/// Parses a single address into a one-based line number.
+fn parse_address<'a>(input: &'a str, buffer: &Buffer) -> Result<(Option<i64>, &'a str), String> {
+    let cursor = buffer.cursor().y as i64 + 1;
+
+    let (mut line, mut rest) = match input.chars().next() {
+        Some('.') => (Some(cursor), &input[1..]),
+        Some('$') => (Some(buffer.line_count() as i64), &input[1..]),
+        Some('\'') => {
+            let name = input[1..]
+                .chars()
+                .next()
+                .ok_or_else(|| "E20: Mark not set".to_string())?;
+            let pos = buffer.marks().get(name).ok_or_else(|| {
+                if mark::is_valid(name) {
+                    "E20: Mark not set".to_string()
+                } else {
+                    "E78: Unknown mark".to_string()
+                }
+            })?;
+            (Some(pos.y as i64 + 1), &input[1 + name.len_utf8()..])
+        }
+        Some(c) if c.is_ascii_digit() => {
+            let (number, rest) = parse_number(input)?;
+            (Some(number), rest)
+        }
+        _ => (None, input),
+    };
+
+    while let Some(sign) = rest.chars().next().filter(|c| *c == '+' || *c == '-') {
+        let (offset, after) = match rest[1..].chars().next() {
+            Some(c) if c.is_ascii_digit() => parse_number(&rest[1..])?,
+            _ => (1, &rest[1..]),
+        };
+
+        let base = line.unwrap_or(cursor);
+        line = Some(if sign == '+' {
+            base.saturating_add(offset)
+        } else {
+            base.saturating_sub(offset)
+        });
+        rest = after;
+    }
+
+    Ok((line, rest))
+}
+
+fn parse_number(input: &str) -> Result<(i64, &str), String> {
+    let len = input
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(input.len());
+    let number = input[..len]
+        .parse()
+        .map_err(|_| "E16: Invalid range".to_string())?;
+
+    Ok((number, &input[len..]))
+}
+
+/// Turns a one-based line number into a line index. Like vim, line 0 is
+/// taken as the first line.
+fn to_line(line: i64, buffer: &Buffer) -> Result<usize, String> {
+    if line < 0 || line > buffer.line_count() as i64 {
+        return Err("E16: Invalid range".to_string());
+    }
+
+    Ok(line.max(1) as usize - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Vec2;
+
+    fn buffer(line_count: usize, cursor_y: usize) -> Buffer {
+        let lines = (0..line_count).map(|i| format!("line{}", i)).collect();
+        let mut buffer = Buffer::from_lines(lines, Vec2::default(), Vec2::new(100, 100), true);
+        buffer.set_cursor(Vec2::new(0, cursor_y));
+        buffer
+    }
+
+    fn range(start: usize, end: usize) -> Option<LineRange> {
+        Some(LineRange { start, end })
+    }
+
+    #[test]
+    fn parse_command() {
+        let buffer = buffer(10, 4);
+
+        assert_eq!(
+            parse("2,3s/a/b/g", &buffer),
+            Ok(ExCommand {
+                range: range(1, 2),
+                name: "s",
+                bang: false,
+                args: "/a/b/g",
+            })
+        );
+        assert_eq!(
+            parse(": w! path ", &buffer),
+            Ok(ExCommand {
+                range: None,
+                name: "w",
+                bang: true,
+                args: "path",
+            })
+        );
+    }
+
+    #[test]
+    fn lookup_abbreviations() {
+        assert_eq!(lookup("w").map(|spec| spec.name), Some("write"));
+        assert_eq!(lookup("wri").map(|spec| spec.name), Some("write"));
+        assert_eq!(lookup("write").map(|spec| spec.name), Some("write"));
+        assert_eq!(lookup("noh").map(|spec| spec.name), Some("nohlsearch"));
+        assert!(lookup("no").is_none());
+        assert!(lookup("writes").is_none());
+        assert_eq!(lookup("x").map(|spec| spec.name), Some("xit"));
+        assert_eq!(lookup("qa").map(|spec| spec.name), Some("qall"));
+        assert!(lookup("sa").is_none());
+    }
+
+    #[test]
+    fn split_chained_commands() {
+        assert_eq!(split("noh | s/a/b/"), vec!["noh ", " s/a/b/"]);
+        assert_eq!(split(r"s/a\|b/c/"), vec!["s/a|b/c/"]);
+    }
+
+    #[test]
+    fn no_range() {
+        let buffer = buffer(10, 4);
+
+        assert_eq!(parse_range("s/a/b/", &buffer), Ok((None, "s/a/b/")));
+    }
+
+    #[test]
+    fn whole_buffer() {
+        let buffer = buffer(10, 4);
+
+        assert_eq!(parse_range("%s", &buffer), Ok((range(0, 9), "s")));
+    }
+
+    #[test]
+    fn single_addresses() {
+        let buffer = buffer(10, 4);
+
+        assert_eq!(parse_range("3s", &buffer), Ok((range(2, 2), "s")));
+        assert_eq!(parse_range(".s", &buffer), Ok((range(4, 4), "s")));
+        assert_eq!(parse_range("$s", &buffer), Ok((range(9, 9), "s")));
+    }
+
+    #[test]
+    fn address_pairs() {
+        let buffer = buffer(10, 4);
+
+        assert_eq!(parse_range("2,$s", &buffer), Ok((range(1, 9), "s")));
+        assert_eq!(parse_range(".,7s", &buffer), Ok((range(4, 6), "s")));
+        assert_eq!(parse_range(",7s", &buffer), Ok((range(4, 6), "s")));
+        assert_eq!(parse_range("7,2s", &buffer), Ok((range(1, 6), "s")));
+    }
+
+    #[test]
+    fn offsets() {
+        let buffer = buffer(10, 4);
+
+        assert_eq!(parse_range(".+2s", &buffer), Ok((range(6, 6), "s")));
+        assert_eq!(parse_range("-,+s", &buffer), Ok((range(3, 5), "s")));
+        assert_eq!(parse_range("$-3,$s", &buffer), Ok((range(6, 9), "s")));
+        assert_eq!(parse_range("2+1+1s", &buffer), Ok((range(3, 3), "s")));
+    }
+
+    #[test]
+    fn invalid_ranges() {
+        let buffer = buffer(10, 4);
+
+        assert!(parse_range("11s", &buffer).is_err());
+        assert!(parse_range(".-6s", &buffer).is_err());
+        assert!(parse_range("'as", &buffer).is_err());
+    }
+}
@@ -0,0 +1,119 @@
+use std::borrow::Cow;
+
+/// Line break used by a file, the `fileformat` option. Lines are always
+/// broken with `'\n'` in the buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileFormat {
+    /// `"\n"`
+    #[default]
+    Unix,
+    /// `"\r\n"`
+    Dos,
+    /// `"\r"`
+    Mac,
+}
+
+impl FileFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "unix" => Some(FileFormat::Unix),
+            "dos" => Some(FileFormat::Dos),
+            "mac" => Some(FileFormat::Mac),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FileFormat::Unix => "unix",
+            FileFormat::Dos => "dos",
+            FileFormat::Mac => "mac",
+        }
+    }
+
+    pub fn line_break(self) -> &'static str {
+        match self {
+            FileFormat::Unix => "\n",
+            FileFormat::Dos => "\r\n",
+            FileFormat::Mac => "\r",
+        }
+    }
+
+    /// Tells the format of `text` from its line breaks, like vim: DOS when
+    /// every `'\n'` follows a `'\r'`, Mac when there are only `'\r'`s, and
+    /// Unix otherwise.
+    pub fn detect(text: &str) -> Self {
+        let bytes = text.as_bytes();
+        let lf = bytes.iter().filter(|b| **b == b'\n').count();
+
+        if lf == 0 {
+            if bytes.contains(&b'\r') {
+                FileFormat::Mac
+            } else {
+                FileFormat::Unix
+            }
+        } else if bytes.windows(2).filter(|pair| *pair == b"\r\n").count() == lf {
+            FileFormat::Dos
+        } else {
+            FileFormat::Unix
+        }
+    }
+
+    /// Turns the line breaks of `text` into `'\n'` and removes the one at
+    /// the end, if any, returning whether there was one.
+    pub fn strip(self, text: String) -> (String, bool) {
+        let mut text = match self {
+            FileFormat::Unix => text,
+            FileFormat::Dos => text.replace("\r\n", "\n"),
+            FileFormat::Mac => text.replace('\r', "\n"),
+        };
+
+        let eol = text.ends_with('\n');
+        if eol {
+            text.pop();
+        }
+        (text, eol)
+    }
+
+    /// `chunk` of the buffer with its line breaks turned into this format's.
+    pub fn convert(self, chunk: &str) -> Cow<'_, str> {
+        match self {
+            FileFormat::Unix => chunk.into(),
+            _ => chunk.replace('\n', self.line_break()).into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_formats() {
+        assert_eq!(FileFormat::detect("a\nb\n"), FileFormat::Unix);
+        assert_eq!(FileFormat::detect("a\r\nb\r\n"), FileFormat::Dos);
+        assert_eq!(FileFormat::detect("a\r\nb\n"), FileFormat::Unix);
+        assert_eq!(FileFormat::detect("a\rb\r"), FileFormat::Mac);
+        assert_eq!(FileFormat::detect("a"), FileFormat::Unix);
+    }
+
+    #[test]
+    fn strip_and_restore_line_breaks() {
+        let cases = [
+            ("a\r\nb\rc\r\n", FileFormat::Dos, "a\nb\rc", true),
+            ("a\rb", FileFormat::Mac, "a\nb", false),
+            ("a\n\n", FileFormat::Unix, "a\n", true),
+            ("", FileFormat::Unix, "", false),
+        ];
+
+        for (file, format, text, eol) in cases.iter() {
+            assert_eq!(format.strip(file.to_string()), (text.to_string(), *eol));
+
+            let mut restored = format.convert(text).into_owned();
+            if *eol {
+                restored.push_str(format.line_break());
+            }
+            assert_eq!(restored, *file);
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use crate::Vec2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edit {
+    Insert { pos: Vec2, text: String },
+    Delete { pos: Vec2, text: String },
+}
+
+impl Edit {
+    pub fn inverse(&self) -> Edit {
+        match self {
+            Edit::Insert { pos, text } => Edit::Delete {
+                pos: *pos,
+                text: text.clone(),
+            },
+            Edit::Delete { pos, text } => Edit::Insert {
+                pos: *pos,
+                text: text.clone(),
+            },
+        }
+    }
+}
+
+/// Position right after `text` once it has been inserted at `pos`.
+pub fn end_of(pos: Vec2, text: &str) -> Vec2 {
+    match text.rfind('\n') {
+        Some(i) => Vec2::new(text.len() - i - 1, pos.y + text.matches('\n').count()),
+        None => Vec2::new(pos.x + text.len(), pos.y),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub edits: Vec<Edit>,
+    pub cursor_before: Vec2,
+    pub cursor_after: Vec2,
+}
+
+/// Records the edits applied to a buffer, grouped into undo steps.
+///
+/// Edits are accumulated into the current step until `commit` is called, so
+/// a whole Insert-mode session can be undone at once.
+#[derive(Debug, Default)]
+pub struct History {
+    undo: Vec<Step>,
+    redo: Vec<Step>,
+    current: Option<Step>,
+}
+
+impl History {
+    pub fn record(&mut self, edit: Edit, cursor: Vec2) {
+        self.redo.clear();
+
+        let step = self.current.get_or_insert_with(|| Step {
+            edits: vec![],
+            cursor_before: cursor,
+            cursor_after: cursor,
+        });
+
+        // merge consecutive typed characters into a single edit
+        if let (
+            Some(Edit::Insert {
+                pos: last_pos,
+                text: last_text,
+            }),
+            Edit::Insert { pos, text },
+        ) = (step.edits.last_mut(), &edit)
+        {
+            if !last_text.contains('\n') && end_of(*last_pos, last_text) == *pos {
+                last_text.push_str(text);
+                return;
+            }
+        }
+
+        step.edits.push(edit);
+    }
+
+    /// Closes the current step. Returns whether there was one.
+    pub fn commit(&mut self, cursor: Vec2) -> bool {
+        match self.current.take() {
+            Some(mut step) => {
+                step.cursor_after = cursor;
+                self.undo.push(step);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn undo(&mut self) -> Option<&Step> {
+        let step = self.undo.pop()?;
+        self.redo.push(step);
+        self.redo.last()
+    }
+
+    pub fn redo(&mut self) -> Option<&Step> {
+        let step = self.redo.pop()?;
+        self.undo.push(step);
+        self.undo.last()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn end_of_single_line() {
+        assert_eq!(end_of(Vec2::new(2, 3), "abc"), Vec2::new(5, 3));
+    }
+
+    #[test]
+    fn end_of_multi_line() {
+        assert_eq!(end_of(Vec2::new(2, 3), "abc\nde\nf"), Vec2::new(1, 5));
+        assert_eq!(end_of(Vec2::new(2, 3), "abc\n"), Vec2::new(0, 4));
+    }
+
+    #[test]
+    fn record_merges_typed_characters() {
+        let mut history = History::default();
+
+        for (x, c) in "abc".chars().enumerate() {
+            history.record(
+                Edit::Insert {
+                    pos: Vec2::new(x, 0),
+                    text: c.to_string(),
+                },
+                Vec2::new(x, 0),
+            );
+        }
+        history.commit(Vec2::new(3, 0));
+
+        let step = history.undo().unwrap();
+        assert_eq!(
+            step.edits,
+            vec![Edit::Insert {
+                pos: Vec2::new(0, 0),
+                text: "abc".to_string()
+            }]
+        );
+        assert_eq!(step.cursor_before, Vec2::new(0, 0));
+        assert_eq!(step.cursor_after, Vec2::new(3, 0));
+    }
+
+    #[test]
+    fn record_clears_redo() {
+        let mut history = History::default();
+
+        history.record(
+            Edit::Insert {
+                pos: Vec2::default(),
+                text: "a".to_string(),
+            },
+            Vec2::default(),
+        );
+        history.commit(Vec2::default());
+        history.undo();
+
+        history.record(
+            Edit::Insert {
+                pos: Vec2::default(),
+                text: "b".to_string(),
+            },
+            Vec2::default(),
+        );
+
+        assert!(history.redo().is_none());
+    }
+}
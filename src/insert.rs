@@ -0,0 +1,199 @@
+use termion::event::{Event, Key};
+
+use crate::macros;
+use crate::operator::SHIFT_WIDTH;
+use crate::register::RegisterKind;
+use crate::{Mode, Pending, State, Vec2};
+
+/// Insert mode session started with a count, whose text is typed
+/// `count - 1` more times when it is left, as in `3ifoo<Esc>`.
+#[derive(Debug)]
+pub struct RepeatedInsert {
+    start: Vec2,
+    count: usize,
+    /// Set for `o` and `O`, where every copy goes on a line of its own.
+    lines: bool,
+}
+
+impl State {
+    /// Enters Insert mode at the cursor, where the text typed will be
+    /// repeated `count` times.
+    pub fn start_insert(&mut self, count: Option<usize>, lines: bool) {
+        self.mode = Mode::Insert;
+        self.repeated_insert = match count {
+            Some(count) if count > 1 => Some(RepeatedInsert {
+                start: self.buffer.cursor(),
+                count,
+                lines,
+            }),
+            _ => None,
+        };
+    }
+
+    fn finish_repeated_insert(&mut self) {
+        let insert = match self.repeated_insert.take() {
+            Some(insert) => insert,
+            None => return,
+        };
+
+        let end = self.buffer.cursor();
+        if end <= insert.start {
+            return;
+        }
+
+        let mut text = self.buffer.text_between(insert.start, end);
+        if insert.lines {
+            text.insert(0, '\n');
+        }
+        if !self.check_repeated_len(text.len(), insert.count - 1) {
+            return;
+        }
+        self.buffer.insert_str(&text.repeat(insert.count - 1));
+    }
+
+    pub fn update_insert(&mut self, evt: Event) {
+        let key = match evt {
+            Event::Key(key) => key,
+            _ => return,
+        };
+
+        // like vim, the change repeated by `.` ends where `Ctrl-O` is typed
+        if key == Key::Ctrl('o') {
+            self.record_key(Key::Esc);
+        } else {
+            self.record_key(key);
+        }
+
+        match self.pending.take() {
+            Some(Pending::Register) => {
+                if let Key::Char(name) = key {
+                    self.insert_register(name);
+                }
+                return;
+            }
+            Some(Pending::Literal) => {
+                let text = match key {
+                    Key::Char('\n') => "\r".to_string(),
+                    key => macros::encode(&[Event::Key(key)]),
+                };
+                self.buffer.insert_str(&text);
+                return;
+            }
+            _ => {}
+        }
+
+        match key {
+            Key::Esc => {
+                self.finish_repeated_insert();
+                self.finish_block_insert();
+                self.buffer.clamp_cursor(false);
+                self.mode = Mode::Normal;
+            }
+            Key::Char('\n') => self.buffer.insert_new_line(),
+            Key::Char(c) => self.buffer.insert_char(c),
+            Key::Backspace => self.buffer.backspace(),
+            Key::Delete => self.buffer.delete_char(),
+            Key::Ctrl('w') => self.buffer.delete_word_before_cursor(),
+            Key::Ctrl('u') => self.buffer.delete_line_before_cursor(),
+            Key::Ctrl('t') => self.shift_insert_line(true),
+            Key::Ctrl('d') => self.shift_insert_line(false),
+            Key::Ctrl('r') => self.pending = Some(Pending::Register),
+            Key::Ctrl('v') => self.pending = Some(Pending::Literal),
+            Key::Ctrl('o') => {
+                self.repeated_insert = None;
+                let cursor = self.buffer.cursor();
+                self.buffer.clamp_cursor(false);
+                self.insert_once = Some((cursor, self.buffer.cursor()));
+                self.mode = Mode::Normal;
+            }
+            Key::Left
+            | Key::Right
+            | Key::Up
+            | Key::Down
+            | Key::Home
+            | Key::End
+            | Key::PageUp
+            | Key::PageDown => {
+                // moving the cursor starts a new undo step, like in vim
+                self.buffer.commit_undo_step();
+                self.repeated_insert = None;
+                self.move_insert_cursor(key);
+            }
+            _ => {}
+        }
+    }
+
+    fn move_insert_cursor(&mut self, key: Key) {
+        let cursor = self.buffer.cursor();
+        let page = self.size.y.saturating_sub(3).max(1);
+
+        let target = match key {
+            Key::Left => Vec2::new(self.buffer.prev_grapheme(cursor), cursor.y),
+            Key::Right => Vec2::new(self.buffer.next_grapheme(cursor), cursor.y),
+            Key::Up => Vec2::new(cursor.x, cursor.y.saturating_sub(1)),
+            Key::Down => Vec2::new(cursor.x, cursor.y + 1),
+            Key::PageUp => Vec2::new(cursor.x, cursor.y.saturating_sub(page)),
+            Key::PageDown => Vec2::new(cursor.x, cursor.y + page),
+            Key::Home => Vec2::new(0, cursor.y),
+            Key::End => Vec2::new(usize::MAX, cursor.y),
+            _ => return,
+        };
+
+        self.buffer.set_insert_cursor(target);
+    }
+
+    /// Inserts the text of a register, after `Ctrl-R`, where lines of a
+    /// linewise register end with a line break.
+    fn insert_register(&mut self, name: char) {
+        let register = match self.registers.get(Some(name)) {
+            Some(register) => register,
+            None => return,
+        };
+
+        let mut text = register.text.clone();
+        if register.kind == RegisterKind::Linewise {
+            text.push('\n');
+        }
+        self.buffer.insert_str(&text);
+    }
+
+    /// Indents or unindents the current line by one shift width, as done by
+    /// `Ctrl-T` and `Ctrl-D`, keeping the cursor on the same character.
+    fn shift_insert_line(&mut self, right: bool) {
+        let cursor = self.buffer.cursor();
+        let len = self.buffer.line_len(cursor.y);
+
+        // unlike `>>`, an empty line is indented too, since text is about
+        // to be typed in it
+        if right {
+            self.buffer
+                .insert_text(Vec2::new(0, cursor.y), &" ".repeat(SHIFT_WIDTH));
+        } else {
+            self.shift_lines(cursor.y, cursor.y, false, 1);
+        }
+
+        let x = (cursor.x + self.buffer.line_len(cursor.y)).saturating_sub(len);
+        self.buffer.set_insert_cursor(Vec2::new(x, cursor.y));
+    }
+
+    /// Goes back to Insert mode once the Normal mode command typed after
+    /// `Ctrl-O` is done. The cursor goes back past the end of the line when
+    /// it was only moved from there by leaving Insert mode.
+    pub fn resume_insert(&mut self) {
+        match self.mode {
+            Mode::Insert | Mode::Replace => self.insert_once = None,
+            Mode::Normal
+                if self.pending.is_none() && self.count.is_none() && self.register.is_none() =>
+            {
+                if let Some((original, clamped)) = self.insert_once.take() {
+                    if self.buffer.cursor() == clamped {
+                        self.buffer.set_insert_cursor(original);
+                    }
+                    self.mode = Mode::Insert;
+                    self.record_key(Key::Char('i'));
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,2689 @@
+extern crate termion;
+
+use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::io::Write;
+use termion::event::{Event, Key};
+
+mod buffer;
+mod encoding;
+mod ex;
+mod fileformat;
+mod history;
+mod insert;
+mod lines;
+mod macros;
+mod mark;
+mod motion;
+mod operator;
+mod option;
+mod register;
+mod repeat;
+mod search;
+mod substitute;
+mod textobject;
+mod vec2;
+mod visual;
+
+pub use buffer::Buffer;
+use buffer::WindowPosition;
+use insert::RepeatedInsert;
+use macros::Recording;
+use motion::{CharSearch, Motion};
+use operator::Operator;
+use register::{Register, RegisterKind, Registers};
+use repeat::Recorder;
+use search::Search;
+use substitute::Substitute;
+use textobject::TextObject;
+pub use vec2::Vec2;
+use visual::{BlockInsert, VisualKind};
+
+/// Most text a count may repeat, as in `99999999p`, past which the command
+/// fails instead of running out of memory.
+const MAX_REPEATED_LEN: usize = 1 << 26;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Normal,
+    Insert,
+    Command,
+    OperatorPending(Operator),
+    /// Asking whether to replace a match of `:s` with the `c` flag.
+    Confirm,
+    Visual(VisualKind),
+    /// Entered with `R`, where typed characters overwrite existing ones.
+    Replace,
+}
+
+/// Normal mode command waiting for its next key.
+#[derive(Debug, PartialEq)]
+enum Pending {
+    Register,
+    G,
+    Z,
+    /// Scroll command after `z`, like `zt`.
+    Scroll,
+    Replace,
+    Find {
+        forward: bool,
+        till: bool,
+    },
+    TextObject {
+        inner: bool,
+    },
+    /// Register to record a macro into, after `q`.
+    Record,
+    /// Register to run as a macro, after `@`.
+    Execute,
+    /// Mark to set, after `m`.
+    SetMark,
+    /// Mark to jump to, after `'` or `` ` ``.
+    Mark {
+        linewise: bool,
+    },
+    /// Key to insert as is, after `Ctrl-V` in Insert mode.
+    Literal,
+}
+
+#[derive(Debug)]
+pub struct State {
+    mode: Mode,
+    pub buffer: Buffer,
+    command_line: Buffer,
+    registers: Registers,
+    register: Option<char>,
+    pending: Option<Pending>,
+    count: Option<usize>,
+    operator_count: Option<usize>,
+    /// Last `f`, `F`, `t` or `T`, repeated by `;` and `,`.
+    last_char_search: Option<CharSearch>,
+    /// Block being inserted into with `I`, `A` or `c` in Visual mode.
+    block_insert: Option<BlockInsert>,
+    /// Substitution waiting for confirmation.
+    substitute: Option<Substitute>,
+    /// Shown in the bottom line until the next key.
+    message: Option<String>,
+    /// Cursor when `/` or `?` was typed, where the search starts from.
+    search_origin: Vec2,
+    /// Keys of the Normal mode command being typed.
+    showcmd: String,
+    recorder: Recorder,
+    /// Characters overwritten in Replace mode, put back by Backspace, or
+    /// `None` for characters typed past the end of the line.
+    replaced: Vec<Option<String>>,
+    /// Macro being recorded with `q`.
+    recording: Option<Recording>,
+    /// Keys of the macros being run, handled before the next typed key.
+    typeahead: VecDeque<Event>,
+    /// Register run last, run again by `@@`.
+    last_macro: Option<char>,
+    /// Set when a command can't be done, which stops the macros being run.
+    failed: bool,
+    /// Cursor when `Ctrl-O` was typed in Insert mode, and where leaving
+    /// Insert mode put it, until the Normal mode command is done.
+    insert_once: Option<(Vec2, Vec2)>,
+    /// Insert mode session started with a count.
+    repeated_insert: Option<RepeatedInsert>,
+    size: Vec2,
+    /// Set by `ZZ` and `ZQ` to exit vipi.
+    quit: bool,
+}
+
+impl State {
+    pub fn new(buffer: Buffer, size: Vec2) -> Self {
+        let message = match (buffer.is_binary(), buffer.file_path()) {
+            (true, Some(path)) => Some(format!("\"{}\" Warning: looks like a binary file", path)),
+            _ => None,
+        };
+
+        Self {
+            buffer,
+            mode: Mode::Normal,
+            command_line: Buffer::from_lines(
+                vec![],
+                Vec2::new(0, size.y - 1),
+                Vec2::new(size.x, 1),
+                false,
+            ),
+            registers: Registers::default(),
+            register: None,
+            pending: None,
+            count: None,
+            operator_count: None,
+            last_char_search: None,
+            search_origin: Vec2::default(),
+            block_insert: None,
+            substitute: None,
+            message,
+            showcmd: String::new(),
+            recorder: Recorder::default(),
+            replaced: Vec::new(),
+            recording: None,
+            typeahead: VecDeque::new(),
+            last_macro: None,
+            failed: false,
+            insert_once: None,
+            repeated_insert: None,
+            size,
+            quit: false,
+        }
+    }
+
+    pub fn render(&self, term: &mut impl Write) {
+        self.buffer.render(term);
+
+        if self.mode == Mode::Command {
+            self.command_line.render(term);
+        } else {
+            let message = match (&self.substitute, &self.recording) {
+                (Some(substitute), _) => Some(substitute.prompt()),
+                (None, Some(recording)) if self.message.is_none() => {
+                    Some(format!("recording @{}", recording.register))
+                }
+                (None, _) => self.message.clone(),
+            };
+
+            if let Some(message) = message {
+                write!(
+                    term,
+                    "{}{}{}{}{}",
+                    termion::cursor::Save,
+                    termion::cursor::Goto(1, self.size.y as u16),
+                    message,
+                    termion::clear::UntilNewline,
+                    termion::cursor::Restore
+                )
+                .unwrap();
+            }
+
+            if !self.showcmd.is_empty() {
+                let x = self.size.x.saturating_sub(self.showcmd.len() + 10);
+                write!(
+                    term,
+                    "{}{}{}{}",
+                    termion::cursor::Save,
+                    termion::cursor::Goto(x as u16 + 1, self.size.y as u16),
+                    self.showcmd,
+                    termion::cursor::Restore
+                )
+                .unwrap();
+            }
+        }
+
+        term.flush().unwrap();
+    }
+
+    /// Handles a key typed in Normal or Operator-pending mode. Returns
+    /// `false` while the command still waits for more keys.
+    fn update_normal(&mut self, evt: Event) -> bool {
+        let key = match evt {
+            Event::Key(key) => key,
+            _ => return true,
+        };
+
+        match key {
+            Key::Char(c @ '1'..='9') => return !self.push_count(c),
+            Key::Char('0') if self.count.is_some() => return !self.push_count('0'),
+            _ => {}
+        }
+
+        self.record_key(key);
+        let count = self.count();
+
+        if let Some(motion) = Motion::from_key(key) {
+            self.motion(motion);
+            return true;
+        }
+
+        let operator_pending = matches!(self.mode, Mode::OperatorPending(_));
+
+        match key {
+            Key::Char('g') => {
+                self.pending = Some(Pending::G);
+                return false;
+            }
+            Key::Char('z') if !operator_pending => {
+                self.pending = Some(Pending::Scroll);
+                return false;
+            }
+            Key::Ctrl(c @ ('e' | 'y' | 'd' | 'u' | 'f' | 'b')) if !operator_pending => {
+                self.scroll_window(c, count);
+                return true;
+            }
+            Key::Char(c @ ('f' | 'F' | 't' | 'T')) => {
+                self.pending = Some(Pending::Find {
+                    forward: c.is_lowercase(),
+                    till: c.eq_ignore_ascii_case(&'t'),
+                });
+                return false;
+            }
+            Key::Char(c @ (';' | ',')) => {
+                if let Some(search) = self.last_char_search {
+                    let search = if c == ',' { search.reversed() } else { search };
+                    self.motion(Motion::FindChar {
+                        search,
+                        repeat: true,
+                    });
+                    return true;
+                }
+            }
+            Key::Char(c @ ('\'' | '`')) => {
+                self.pending = Some(Pending::Mark {
+                    linewise: c == '\'',
+                });
+                return false;
+            }
+            Key::Char(c @ ('i' | 'a')) if self.mode != Mode::Normal => {
+                self.pending = Some(Pending::TextObject { inner: c == 'i' });
+                return false;
+            }
+            _ => {}
+        }
+
+        if let Mode::Visual(kind) = self.mode {
+            return self.update_visual(kind, key, count);
+        }
+
+        if let Mode::OperatorPending(operator) = self.mode {
+            self.mode = Mode::Normal;
+            if key == Key::Char(operator.key()) {
+                self.operate_lines(operator, count);
+            }
+            return true;
+        }
+
+        if let Some(operator) = Operator::from_key(key) {
+            self.mode = Mode::OperatorPending(operator);
+            self.operator_count = self.count.take();
+            return false;
+        }
+
+        match key {
+            Key::Char('x') => self.operate(Operator::Delete, Motion::Right, count),
+            Key::Char('p') => self.put(false, count.unwrap_or(1)),
+            Key::Char('P') => self.put(true, count.unwrap_or(1)),
+            Key::Char('"') => {
+                self.pending = Some(Pending::Register);
+                return false;
+            }
+            Key::Char('Z') => {
+                self.pending = Some(Pending::Z);
+                return false;
+            }
+            Key::Char('J') => {
+                let below = self.buffer.line_count() - 1 - self.buffer.cursor().y;
+                for _ in 0..(count.unwrap_or(1).max(2) - 1).min(below) {
+                    self.buffer.join_line();
+                }
+            }
+            Key::Char('u') => {
+                for _ in 0..count.unwrap_or(1) {
+                    if !self.buffer.undo() {
+                        break;
+                    }
+                }
+            }
+            Key::Ctrl('r') => {
+                for _ in 0..count.unwrap_or(1) {
+                    if !self.buffer.redo() {
+                        break;
+                    }
+                }
+            }
+            Key::Char('.') => self.repeat_change(count),
+            Key::Char('q') if self.recording.is_some() => self.stop_recording(),
+            Key::Char('q') => {
+                self.pending = Some(Pending::Record);
+                return false;
+            }
+            Key::Char('@') => {
+                self.pending = Some(Pending::Execute);
+                return false;
+            }
+            Key::Char('m') => {
+                self.pending = Some(Pending::SetMark);
+                return false;
+            }
+            Key::Char('r') => {
+                self.pending = Some(Pending::Replace);
+                return false;
+            }
+            Key::Char('R') => {
+                self.replaced.clear();
+                self.mode = Mode::Replace;
+            }
+            Key::Ctrl('o') => self.failed = !self.buffer.walk_jumps(false, count.unwrap_or(1)),
+            Key::Char('\t') => self.failed = !self.buffer.walk_jumps(true, count.unwrap_or(1)),
+            Key::Char('v') => self.start_visual(VisualKind::Char),
+            Key::Char('V') => self.start_visual(VisualKind::Line),
+            Key::Ctrl('v') => self.start_visual(VisualKind::Block),
+            Key::Char('i') => self.start_insert(count, false),
+            Key::Char('a') => {
+                self.buffer.move_cursor_right(true);
+                self.start_insert(count, false);
+            }
+            Key::Char('I') => {
+                self.buffer.move_cursor_first_non_blank();
+                self.start_insert(count, false);
+            }
+            Key::Char('A') => {
+                let y = self.buffer.cursor().y;
+                self.buffer.set_insert_cursor(Vec2::new(usize::MAX, y));
+                self.start_insert(count, false);
+            }
+            Key::Char('o') => {
+                self.buffer.insert_line_after_cursor("".to_string());
+                self.buffer.move_cursor_down();
+                self.buffer.move_cursor_first_character();
+                self.start_insert(count, true);
+            }
+            Key::Char('O') => {
+                self.buffer.insert_line_before_cursor("".to_string());
+                let y = self.buffer.cursor().y;
+                self.buffer.set_insert_cursor(Vec2::new(0, y));
+                self.start_insert(count, true);
+            }
+            // like vim, "s" on an empty line only starts Insert mode
+            Key::Char('s') if self.buffer.current_line().is_empty() => {
+                self.start_insert(None, false);
+            }
+            Key::Char('s') => self.operate(Operator::Change, Motion::Right, count),
+            Key::Char('S') => self.operate_lines(Operator::Change, count),
+            // like vim, "C" starts inserting even when there is nothing to
+            // change
+            Key::Char('C') if self.buffer.current_line().is_empty() && count.is_none() => {
+                self.start_insert(None, false);
+            }
+            Key::Char('C') => self.operate(Operator::Change, Motion::LineEnd, count),
+            Key::Char('D') => self.operate(Operator::Delete, Motion::LineEnd, count),
+            Key::Char('Y') => self.operate_lines(Operator::Yank, count),
+            Key::Char(c @ ('*' | '#')) => {
+                if let Some((x, word)) = self.buffer.word_under_cursor() {
+                    let y = self.buffer.cursor().y;
+                    self.buffer.set_cursor(Vec2::new(x, y));
+                    self.buffer.set_search(Search::word(&word, c == '*'));
+                    self.motion(Motion::SearchNext { reverse: false });
+                }
+            }
+            Key::Char(c @ (':' | '/' | '?')) => self.open_command_line(c),
+            _ => {}
+        }
+
+        true
+    }
+
+    fn update_pending(&mut self, pending: Pending, evt: Event) -> bool {
+        if let Event::Key(key) = evt {
+            self.record_key(key);
+        }
+
+        match (pending, evt) {
+            (Pending::Register, Event::Key(Key::Char(c))) if register::is_valid(c) => {
+                self.register = Some(c);
+                return false;
+            }
+            (Pending::Z, Event::Key(Key::Char('Z'))) => {
+                self.quit = self.execute("x");
+                return true;
+            }
+            (Pending::Z, Event::Key(Key::Char('Q'))) => {
+                self.quit = self.execute("q!");
+                return true;
+            }
+            (Pending::Scroll, Event::Key(Key::Char(c @ ('t' | 'z' | 'b' | '\n' | '.' | '-')))) => {
+                if let Some(count) = self.count() {
+                    let x = self.buffer.cursor().x;
+                    self.buffer.set_cursor(Vec2::new(x, count - 1));
+                }
+
+                let position = match c {
+                    't' | '\n' => WindowPosition::Top,
+                    'z' | '.' => WindowPosition::Middle,
+                    _ => WindowPosition::Bottom,
+                };
+                self.buffer.scroll_cursor_to(position);
+
+                // the forms not typed with a letter also go to the first
+                // non-blank
+                if !c.is_ascii_alphabetic() {
+                    self.buffer.move_cursor_first_non_blank();
+                }
+                return true;
+            }
+            (Pending::G, Event::Key(key @ Key::Char(';' | ','))) => {
+                let forward = key == Key::Char(',');
+                if let Err(error) = self.buffer.walk_changes(forward, self.count().unwrap_or(1)) {
+                    self.message = Some(error);
+                    self.failed = true;
+                }
+                return true;
+            }
+            (Pending::G, Event::Key(key)) => {
+                if let Some(motion) = Motion::from_g_key(key) {
+                    self.motion(motion);
+                    return true;
+                }
+
+                match (Operator::from_g_key(key), self.mode) {
+                    (Some(operator), Mode::Normal) => {
+                        self.mode = Mode::OperatorPending(operator);
+                        self.operator_count = self.count.take();
+                        return false;
+                    }
+                    (Some(operator), Mode::Visual(_)) => {
+                        self.visual_operate(operator, self.count());
+                        return true;
+                    }
+                    _ => {}
+                }
+            }
+            (Pending::Replace, Event::Key(Key::Char(c))) => {
+                if let Mode::Visual(_) = self.mode {
+                    self.visual_replace(c);
+                } else if !self.buffer.replace_chars(c, self.count().unwrap_or(1)) {
+                    self.failed = true;
+                }
+                return true;
+            }
+            (Pending::Find { forward, till }, Event::Key(Key::Char(c))) => {
+                let search = CharSearch { c, forward, till };
+                self.last_char_search = Some(search);
+                self.motion(Motion::FindChar {
+                    search,
+                    repeat: false,
+                });
+                return true;
+            }
+            (Pending::Record, Event::Key(Key::Char(c))) if register::is_valid(c) => {
+                self.start_recording(c);
+                return true;
+            }
+            (Pending::Execute, Event::Key(Key::Char(c))) if c == '@' || register::is_valid(c) => {
+                self.run_macro(c, self.count());
+                return true;
+            }
+            (Pending::SetMark, Event::Key(Key::Char(c))) if mark::can_set(c) => {
+                let cursor = self.buffer.cursor();
+                match c {
+                    '\'' | '`' => self.buffer.marks_mut().push_jump(cursor),
+                    c => self.buffer.marks_mut().set(c, cursor),
+                }
+                return true;
+            }
+            (Pending::Mark { linewise }, Event::Key(Key::Char(c))) => {
+                if self.buffer.marks().get(c).is_some() {
+                    self.motion(Motion::Mark { name: c, linewise });
+                    return true;
+                }
+
+                let error = if mark::is_valid(c) {
+                    "E20: Mark not set"
+                } else {
+                    "E78: Unknown mark"
+                };
+                self.message = Some(error.to_string());
+                self.failed = true;
+            }
+            (Pending::TextObject { inner }, Event::Key(key)) => {
+                if let Some(object) = TextObject::from_key(key) {
+                    self.text_object(object, inner);
+                    return true;
+                }
+            }
+            _ => {}
+        }
+
+        // an invalid key cancels the whole command
+        if let Mode::OperatorPending(_) = self.mode {
+            self.mode = Mode::Normal;
+        }
+        true
+    }
+
+    fn open_command_line(&mut self, prefix: char) {
+        self.command_line = Buffer::from_lines(
+            vec![prefix.to_string()],
+            Vec2::new(0, self.size.y - 1),
+            Vec2::new(self.size.x, 1),
+            false,
+        );
+        self.command_line.move_cursor_right(true);
+        self.search_origin = self.buffer.cursor();
+        self.mode = Mode::Command;
+    }
+
+    fn close_command_line(&mut self) {
+        self.buffer.set_incsearch(None);
+        self.buffer.set_cursor(self.search_origin);
+        self.mode = Mode::Normal;
+    }
+
+    /// Highlights the pattern being typed after `/` or `?`, and moves to its
+    /// first match.
+    fn update_incsearch(&mut self) {
+        let line = self.command_line.current_line();
+        let forward = line.starts_with('/');
+        if !forward && !line.starts_with('?') {
+            return;
+        }
+
+        let search = Search::new(&line[1..], forward)
+            .ok()
+            .filter(|_| line.len() > 1);
+        let found = search.as_ref().and_then(|search| {
+            self.buffer
+                .find_match(&search.regex, self.search_origin, forward)
+        });
+
+        self.buffer.set_cursor(found.unwrap_or(self.search_origin));
+        self.buffer.set_incsearch(search.map(|search| search.regex));
+    }
+
+    /// Searches for the pattern typed after `/` or `?`, or for the last one
+    /// when it is empty.
+    fn search(&mut self, pattern: &str, forward: bool) {
+        let search = if pattern.is_empty() {
+            self.buffer.search().map(|search| Search {
+                regex: search.regex.clone(),
+                forward,
+            })
+        } else {
+            match Search::new(pattern, forward) {
+                Ok(search) => Some(search),
+                Err(_) => {
+                    self.message = Some(format!("E383: Invalid search string: {}", pattern));
+                    self.failed = true;
+                    return;
+                }
+            }
+        };
+
+        let from = self.buffer.cursor();
+        match search {
+            Some(search) => self.buffer.set_search(search),
+            None => return self.search_not_found(),
+        }
+        if !self.buffer.search_next(false) {
+            self.search_not_found();
+        } else if self.buffer.cursor() != from {
+            self.buffer.marks_mut().push_jump(from);
+        }
+    }
+
+    /// Reports that the last pattern searched has no match, or that there
+    /// is none.
+    fn search_not_found(&mut self) {
+        self.message = Some(match self.buffer.search() {
+            Some(search) => format!("E486: Pattern not found: {}", search.regex.as_str()),
+            None => "E35: No previous regular expression".to_string(),
+        });
+        self.failed = true;
+    }
+
+    /// Moves to the last substituted line and reports what was done.
+    fn finish_substitute(&mut self, substitute: Substitute) {
+        if let Some(y) = substitute.last_line {
+            self.buffer
+                .set_cursor(Vec2::new(self.buffer.first_non_blank(y), y));
+        }
+
+        self.message = Some(substitute.message());
+        self.mode = Mode::Normal;
+    }
+
+    /// Moves the cursor, or applies the pending operator to the text covered
+    /// by the motion.
+    fn motion(&mut self, motion: Motion) {
+        let count = self.count();
+
+        match self.mode {
+            Mode::OperatorPending(operator) => {
+                self.mode = Mode::Normal;
+                self.operate(operator, motion, count);
+            }
+            _ => {
+                let from = self.buffer.cursor();
+                motion.apply(&mut self.buffer, count, false);
+
+                let moved = self.buffer.cursor() != from;
+                self.failed = motion.can_fail() && !moved;
+                if let (Motion::SearchNext { .. }, false) = (motion, moved) {
+                    let found = self
+                        .buffer
+                        .search()
+                        .and_then(|search| self.buffer.find_match(&search.regex, from, true));
+                    if found.is_none() {
+                        self.search_not_found();
+                    }
+                }
+                if moved && motion.is_jump() {
+                    self.buffer.marks_mut().push_jump(from);
+                }
+            }
+        }
+    }
+
+    /// Scrolls the window with `Ctrl-E`, `Ctrl-Y`, `Ctrl-D`, `Ctrl-U`,
+    /// `Ctrl-F` or `Ctrl-B`.
+    fn scroll_window(&mut self, c: char, count: Option<usize>) {
+        let scrolled = match c {
+            'e' | 'y' => self.buffer.scroll_lines(c == 'e', count.unwrap_or(1)),
+            'd' | 'u' => {
+                // like vim, a count sets the lines scrolled from now on
+                if let Some(count) = count {
+                    self.buffer.set_scroll(count);
+                }
+                self.buffer.scroll_half_page(c == 'd', self.buffer.scroll())
+            }
+            _ => self.buffer.scroll_page(c == 'f', count.unwrap_or(1)),
+        };
+        self.failed = !scrolled;
+    }
+
+    /// Appends a digit to the count typed before a command. Returns `false`
+    /// when the count is too big.
+    fn push_count(&mut self, digit: char) -> bool {
+        let digit = digit.to_digit(10).unwrap() as usize;
+        let count = self.count.unwrap_or(0);
+
+        match count.checked_mul(10).and_then(|c| c.checked_add(digit)) {
+            Some(count) => {
+                self.count = Some(count);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Count of the current command, where a count typed before an operator
+    /// multiplies the one typed before its motion.
+    fn count(&self) -> Option<usize> {
+        match (self.operator_count, self.count) {
+            (None, None) => None,
+            (operator_count, count) => Some(
+                operator_count
+                    .unwrap_or(1)
+                    .saturating_mul(count.unwrap_or(1)),
+            ),
+        }
+    }
+
+    /// Whether `len` bytes can be repeated `count` times, failing with
+    /// E1240 when the result would be too long.
+    fn check_repeated_len(&mut self, len: usize, count: usize) -> bool {
+        match len.checked_mul(count) {
+            Some(total) if total <= MAX_REPEATED_LEN => true,
+            _ => {
+                self.message = Some("E1240: Resulting text too long".to_string());
+                self.failed = true;
+                false
+            }
+        }
+    }
+
+    fn put(&mut self, before: bool, count: usize) {
+        let len = match self.registers.get(self.register) {
+            Some(register) => register.text.len() + 1,
+            None => return,
+        };
+        if !self.check_repeated_len(len, count) {
+            return;
+        }
+
+        if let Some(register) = self.registers.get(self.register) {
+            let text = match register.kind {
+                RegisterKind::Charwise => register.text.repeat(count),
+                RegisterKind::Linewise => vec![register.text.as_str(); count].join("\n"),
+                RegisterKind::Blockwise => register
+                    .text
+                    .split('\n')
+                    .map(|line| line.repeat(count))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            };
+
+            self.buffer.put(&Register::new(text, register.kind), before);
+        }
+    }
+
+    /// Handles an event typed by the user, and then the keys of the macros
+    /// it ran. Returns whether vipi should exit.
+    pub fn update(&mut self, evt: Event) -> bool {
+        if let Some(recording) = &mut self.recording {
+            recording.events.push(evt.clone());
+        }
+
+        let mut quit = self.handle(evt);
+        while !quit {
+            if self.failed {
+                self.typeahead.clear();
+            }
+
+            match self.typeahead.pop_front() {
+                Some(evt) => quit = self.handle(evt),
+                None => break,
+            }
+        }
+
+        quit
+    }
+
+    fn handle(&mut self, evt: Event) -> bool {
+        self.message = None;
+        self.failed = false;
+        let resuming = self.insert_once.is_some() && self.mode != Mode::Insert;
+
+        match &self.mode {
+            Mode::Normal | Mode::OperatorPending(_) | Mode::Visual(_) => {
+                let typed = match evt {
+                    Event::Key(Key::Char(c)) => Some(c),
+                    _ => None,
+                };
+
+                let completed = match self.pending.take() {
+                    Some(pending) => self.update_pending(pending, evt),
+                    None => self.update_normal(evt),
+                };
+
+                if completed {
+                    self.record_count(self.count());
+                    self.register = None;
+                    self.count = None;
+                    self.operator_count = None;
+                    self.showcmd.clear();
+                } else if let Some(c) = typed {
+                    self.showcmd.push(c);
+                }
+            }
+            Mode::Command => match evt {
+                Event::Key(Key::Esc) => self.close_command_line(),
+                Event::Key(Key::Backspace) => {
+                    if self.command_line.current_line().len() == 1 {
+                        self.close_command_line();
+                    } else {
+                        self.command_line.backspace();
+                        self.update_incsearch();
+                    }
+                }
+                Event::Key(Key::Char('\n')) => {
+                    let line = self.command_line.current_line().to_string();
+                    self.close_command_line();
+                    self.discard_recording();
+
+                    if let Some(pattern) = line.strip_prefix('/') {
+                        self.search(pattern, true);
+                        return false;
+                    }
+
+                    if let Some(pattern) = line.strip_prefix('?') {
+                        self.search(pattern, false);
+                        return false;
+                    }
+
+                    if let Some(command) = line.strip_prefix(':') {
+                        return self.execute(command);
+                    }
+                }
+                Event::Key(Key::Char(c)) => {
+                    self.command_line.insert_char(c);
+                    self.update_incsearch();
+                }
+                _ => {}
+            },
+            Mode::Confirm => {
+                if let (Some(mut substitute), Event::Key(key)) = (self.substitute.take(), evt) {
+                    let done = match key {
+                        Key::Char('y') => {
+                            substitute.accept(&mut self.buffer);
+                            false
+                        }
+                        Key::Char('l') => {
+                            substitute.accept(&mut self.buffer);
+                            true
+                        }
+                        Key::Char('n') => {
+                            substitute.skip(&self.buffer);
+                            false
+                        }
+                        Key::Char('a') => {
+                            substitute.run(&mut self.buffer);
+                            true
+                        }
+                        Key::Char('q') | Key::Esc => true,
+                        _ => false,
+                    };
+
+                    match substitute.next(&self.buffer) {
+                        Some(pos) if !done => {
+                            self.buffer.set_cursor(pos);
+                            self.substitute = Some(substitute);
+                        }
+                        _ => self.finish_substitute(substitute),
+                    }
+                }
+            }
+            Mode::Insert => self.update_insert(evt),
+            Mode::Replace => {
+                if let Event::Key(key) = evt {
+                    self.record_key(key);
+                }
+
+                match evt {
+                    Event::Key(Key::Esc) => {
+                        self.replaced.clear();
+                        self.buffer.clamp_cursor(false);
+                        self.mode = Mode::Normal;
+                    }
+                    Event::Key(Key::Char('\n')) => {
+                        self.buffer.insert_new_line();
+                        self.replaced.push(None);
+                    }
+                    Event::Key(Key::Backspace) => match self.replaced.pop() {
+                        Some(original) => {
+                            self.buffer.backspace();
+                            if let Some(original) = original {
+                                self.buffer.insert_text(self.buffer.cursor(), &original);
+                            }
+                        }
+                        // like vim, nothing typed before Replace mode is deleted
+                        None => self.buffer.move_cursor_left(),
+                    },
+                    Event::Key(Key::Char(c)) => {
+                        let original = self.buffer.overwrite_char(c);
+                        self.replaced.push(original);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // every Normal mode command is its own undo step, while a whole
+        // Insert mode session is only committed once it is left
+        if self.mode == Mode::Normal && self.pending.is_none() {
+            let changed = self.buffer.commit_undo_step();
+            if self.count.is_none() && self.register.is_none() {
+                self.finish_recording(changed);
+            }
+        }
+
+        if resuming {
+            self.resume_insert();
+        }
+
+        self.quit
+    }
+}
+
+pub fn write_debug(str: &str) {
+    let mut debug_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open("/tmp/vipi.debug")
+        .expect("Failed to open debug file");
+
+    debug_file
+        .write_all(&format!("{}\n", str).into_bytes())
+        .expect("Failed to write debug file");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(lines: &[&str]) -> State {
+        let lines = lines.iter().map(|line| line.to_string()).collect();
+        let size = Vec2::new(100, 20);
+
+        State::new(
+            Buffer::from_lines(lines, Vec2::default(), Vec2::new(size.x, size.y - 1), true),
+            size,
+        )
+    }
+
+    /// Types `keys`, where special keys are written as the text a
+    /// terminal sends for them.
+    fn feed(state: &mut State, keys: &str) {
+        for evt in macros::decode(keys) {
+            state.update(evt);
+        }
+    }
+
+    /// Path in the temp directory, removed before being returned.
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("vipi-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path.to_str().unwrap().to_string()
+    }
+
+    fn file_state(path: &str) -> State {
+        let size = Vec2::new(100, 20);
+        State::new(
+            Buffer::from_file_path(path, Vec2::default(), Vec2::new(size.x, size.y - 1)),
+            size,
+        )
+    }
+
+    fn lines(state: &State) -> Vec<String> {
+        state
+            .buffer
+            .lines_text(0, usize::MAX)
+            .split('\n')
+            .map(|line| line.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn undo_insert_session() {
+        let mut state = state(&["hello"]);
+
+        feed(&mut state, "ione two\x1bxx");
+        assert_eq!(lines(&state), vec!["one twollo"]);
+
+        feed(&mut state, "u");
+        assert_eq!(lines(&state), vec!["one twoello"]);
+
+        feed(&mut state, "uu");
+        assert_eq!(lines(&state), vec!["hello"]);
+        assert_eq!(state.buffer.cursor(), Vec2::new(0, 0));
+
+        feed(&mut state, "\x12");
+        assert_eq!(lines(&state), vec!["one twohello"]);
+    }
+
+    #[test]
+    fn delete_and_put_line() {
+        let mut state = state(&["one", "two", "three"]);
+
+        feed(&mut state, "ddp");
+        assert_eq!(lines(&state), vec!["two", "one", "three"]);
+        assert_eq!(state.buffer.cursor(), Vec2::new(0, 1));
+
+        feed(&mut state, "kP");
+        assert_eq!(lines(&state), vec!["one", "two", "one", "three"]);
+    }
+
+    #[test]
+    fn yank_to_named_register() {
+        let mut state = state(&["one", "two"]);
+
+        feed(&mut state, "\"ayyjx\"ap");
+        assert_eq!(lines(&state), vec!["one", "wo", "one"]);
+
+        feed(&mut state, "P");
+        assert_eq!(lines(&state), vec!["one", "wo", "tone"]);
+    }
+
+    #[test]
+    fn put_characterwise() {
+        let mut state = state(&["abc"]);
+
+        feed(&mut state, "xp");
+        assert_eq!(lines(&state), vec!["bac"]);
+        assert_eq!(state.buffer.cursor(), Vec2::new(1, 0));
+
+        feed(&mut state, "\"-P");
+        assert_eq!(lines(&state), vec!["baac"]);
+    }
+
+    #[test]
+    fn delete_word() {
+        let mut state = state(&["one two three"]);
+
+        feed(&mut state, "dw");
+        assert_eq!(lines(&state), vec!["two three"]);
+
+        feed(&mut state, "wdw");
+        assert_eq!(lines(&state), vec!["two "]);
+        assert_eq!(state.registers.get(None).unwrap().text, "three");
+    }
+
+    #[test]
+    fn delete_word_at_end_of_line() {
+        let mut state = state(&["one two", "three"]);
+
+        feed(&mut state, "wdw");
+        assert_eq!(lines(&state), vec!["one ", "three"]);
+    }
+
+    #[test]
+    fn change_word() {
+        let mut state = state(&["one two", "three"]);
+
+        feed(&mut state, "cwuno\x1b");
+        assert_eq!(lines(&state), vec!["uno two", "three"]);
+
+        feed(&mut state, "wcwdos\x1b");
+        assert_eq!(lines(&state), vec!["uno dos", "three"]);
+
+        feed(&mut state, "u");
+        assert_eq!(lines(&state), vec!["uno two", "three"]);
+    }
+
+    #[test]
+    fn delete_to_line_start() {
+        let mut state = state(&["one two"]);
+
+        feed(&mut state, "wd0");
+        assert_eq!(lines(&state), vec!["two"]);
+        assert_eq!(state.buffer.cursor(), Vec2::new(0, 0));
+    }
+
+    #[test]
+    fn yank_lines_down() {
+        let mut state = state(&["one", "two", "three"]);
+
+        feed(&mut state, "yj");
+        let register = state.registers.get(None).unwrap();
+        assert_eq!(register.text, "one\ntwo");
+        assert_eq!(register.kind, RegisterKind::Linewise);
+        assert_eq!(state.buffer.cursor(), Vec2::new(0, 0));
+    }
+
+    #[test]
+    fn delete_lines_up() {
+        let mut state = state(&["one", "two", "three"]);
+
+        feed(&mut state, "jjdk");
+        assert_eq!(lines(&state), vec!["one"]);
+    }
+
+    #[test]
+    fn change_line() {
+        let mut state = state(&["one", "two"]);
+
+        feed(&mut state, "ccuno\x1b");
+        assert_eq!(lines(&state), vec!["uno", "two"]);
+    }
+
+    #[test]
+    fn operator_cancelled_by_other_key() {
+        let mut state = state(&["one two"]);
+
+        feed(&mut state, "dxw");
+        assert_eq!(lines(&state), vec!["one two"]);
+        assert_eq!(state.buffer.cursor(), Vec2::new(4, 0));
+    }
+
+    #[test]
+    fn count_motion() {
+        let mut state = state(&["a b c d e", "f", "g"]);
+
+        feed(&mut state, "3w");
+        assert_eq!(state.buffer.cursor(), Vec2::new(6, 0));
+
+        feed(&mut state, "10j");
+        assert_eq!(state.buffer.cursor(), Vec2::new(0, 2));
+    }
+
+    #[test]
+    fn count_zero_after_digit() {
+        let mut state = state(&["0123456789abcdef"]);
+
+        feed(&mut state, "10l");
+        assert_eq!(state.buffer.cursor(), Vec2::new(10, 0));
+
+        feed(&mut state, "0");
+        assert_eq!(state.buffer.cursor(), Vec2::new(0, 0));
+    }
+
+    #[test]
+    fn count_delete_chars() {
+        let mut state = state(&["abcdef"]);
+
+        feed(&mut state, "4x");
+        assert_eq!(lines(&state), vec!["ef"]);
+        assert_eq!(state.registers.get(None).unwrap().text, "abcd");
+
+        feed(&mut state, "l5x");
+        assert_eq!(lines(&state), vec!["e"]);
+    }
+
+    #[test]
+    fn count_join_lines() {
+        let mut state = state(&["a", "b", "c", "d"]);
+
+        feed(&mut state, "2J");
+        assert_eq!(lines(&state), vec!["a b", "c", "d"]);
+
+        feed(&mut state, "3J");
+        assert_eq!(lines(&state), vec!["a b c d"]);
+    }
+
+    #[test]
+    fn count_operator_lines() {
+        let mut state = state(&["a", "b", "c", "d"]);
+
+        feed(&mut state, "3dd");
+        assert_eq!(lines(&state), vec!["d"]);
+        assert_eq!(state.registers.get(None).unwrap().text, "a\nb\nc");
+    }
+
+    #[test]
+    fn huge_counts() {
+        let mut state = state(&["abc", "def", "ghi"]);
+
+        feed(&mut state, "18446744073709551615J");
+        assert_eq!(lines(&state), vec!["abc def ghi"]);
+
+        feed(&mut state, "ujj18446744073709551615dd");
+        assert_eq!(lines(&state), vec!["abc", "def"]);
+
+        feed(&mut state, "18446744073709551615x");
+        assert_eq!(lines(&state), vec!["abc", ""]);
+
+        feed(&mut state, "99999999p");
+        assert_eq!(lines(&state), vec!["abc", ""]);
+        assert_eq!(
+            state.message.as_deref(),
+            Some("E1240: Resulting text too long")
+        );
+
+        feed(&mut state, "18446744073709551615ugg99999999999ia\x1b");
+        assert_eq!(lines(&state), vec!["aabc", "def", "ghi"]);
+    }
+
+    #[test]
+    fn huge_search_count() {
+        let mut state = state(&["a b a", "a"]);
+
+        feed(&mut state, "/a\n18446744073709551615n");
+        assert_eq!(state.buffer.cursor(), Vec2::new(4, 0));
+
+        feed(&mut state, "18446744073709551614N");
+        assert_eq!(state.buffer.cursor(), Vec2::new(0, 1));
+    }
+
+    #[test]
+    fn count_multiplies_operator_and_motion() {
+        let mut state = state(&["a b c d e f g h"]);
+
+        feed(&mut state, "2d3w");
+        assert_eq!(lines(&state), vec!["g h"]);
+    }
+
+    #[test]
+    fn count_put() {
+        let mut state = state(&["ab"]);
+
+        feed(&mut state, "x3p");
+        assert_eq!(lines(&state), vec!["baaa"]);
+    }
+
+    #[test]
+    fn showcmd() {
+        let mut state = state(&["a b c"]);
+
+        feed(&mut state, "\"a2d");
+        assert_eq!(state.showcmd, "\"a2d");
+
+        feed(&mut state, "w");
+        assert_eq!(state.showcmd, "");
+    }
+
+    #[test]
+    fn goto_lines() {
+        let mut state = state(&["a", "b", "  c", "d"]);
+
+        feed(&mut state, "G");
+        assert_eq!(state.buffer.cursor(), Vec2::new(0, 3));
+
+        feed(&mut state, "3G");
+        assert_eq!(state.buffer.cursor(), Vec2::new(2, 2));
+
+        feed(&mut state, "gg");
+        assert_eq!(state.buffer.cursor(), Vec2::new(0, 0));
+
+        feed(&mut state, "2gg");
+        assert_eq!(state.buffer.cursor(), Vec2::new(0, 1));
+    }
+
+    #[test]
+    fn delete_with_word_motions() {
+        let mut state = state(&["one two three"]);
+
+        feed(&mut state, "de");
+        assert_eq!(lines(&state), vec![" two three"]);
+
+        feed(&mut state, "$db");
+        assert_eq!(lines(&state), vec![" two e"]);
+
+        feed(&mut state, "dge");
+        assert_eq!(lines(&state), vec![" tw"]);
+    }
+
+    #[test]
+    fn operate_on_character_under_cursor() {
+        let cases = [
+            ("abc", "$d$", "ab"),
+            ("ab cd", "$de", "ab c"),
+            ("abc", "$c$X\x1b", "abX"),
+        ];
+        for (line, keys, expected) in cases {
+            let mut state = state(&[line]);
+            feed(&mut state, keys);
+            assert_eq!(lines(&state), vec![expected], "{}", keys);
+        }
+
+        // an exclusive motion that stays where it is covers nothing
+        let mut state = state(&["abc"]);
+        feed(&mut state, "d0");
+        assert_eq!(lines(&state), vec!["abc"]);
+        assert!(state.failed);
+    }
+
+    #[test]
+    fn delete_to_line_end_with_count() {
+        let mut state = state(&["one", "two", "three"]);
+
+        feed(&mut state, "l2d$");
+        assert_eq!(lines(&state), vec!["o", "three"]);
+    }
+
+    #[test]
+    fn delete_to_file_boundaries() {
+        let mut state = state(&["a", "b", "c", "d"]);
+
+        feed(&mut state, "jjdgg");
+        assert_eq!(lines(&state), vec!["d"]);
+
+        feed(&mut state, "uggjdG");
+        assert_eq!(lines(&state), vec!["a"]);
+    }
+
+    #[test]
+    fn delete_to_file_boundary_on_it() {
+        let mut state = state(&["a", "b"]);
+
+        feed(&mut state, "jdG");
+        assert_eq!(lines(&state), vec!["a"]);
+        feed(&mut state, "dgg");
+        assert_eq!(lines(&state), vec![""]);
+    }
+
+    #[test]
+    fn find_char_and_repeat() {
+        let mut state = state(&["a(b, c), d(e)"]);
+
+        feed(&mut state, "f,");
+        assert_eq!(state.buffer.cursor(), Vec2::new(3, 0));
+
+        feed(&mut state, ";");
+        assert_eq!(state.buffer.cursor(), Vec2::new(7, 0));
+
+        feed(&mut state, ",");
+        assert_eq!(state.buffer.cursor(), Vec2::new(3, 0));
+
+        feed(&mut state, "t)");
+        assert_eq!(state.buffer.cursor(), Vec2::new(5, 0));
+
+        feed(&mut state, ";");
+        assert_eq!(state.buffer.cursor(), Vec2::new(11, 0));
+
+        feed(&mut state, "2F(");
+        assert_eq!(state.buffer.cursor(), Vec2::new(1, 0));
+    }
+
+    #[test]
+    fn delete_and_change_with_find_char() {
+        let mut state = state(&["a(b, c), d(e)"]);
+
+        feed(&mut state, "df,");
+        assert_eq!(lines(&state), vec![" c), d(e)"]);
+
+        feed(&mut state, "$dT(");
+        assert_eq!(lines(&state), vec![" c), d()"]);
+
+        feed(&mut state, "0ct)x\x1b");
+        assert_eq!(lines(&state), vec!["x), d()"]);
+    }
+
+    #[test]
+    fn find_char_not_found() {
+        let mut state = state(&["abc"]);
+
+        feed(&mut state, "fzdfz");
+        assert_eq!(lines(&state), vec!["abc"]);
+        assert_eq!(state.buffer.cursor(), Vec2::new(0, 0));
+    }
+
+    #[test]
+    fn search_forward_and_backward() {
+        let mut state = state(&["one two", "two one", "three two"]);
+
+        feed(&mut state, "/two\n");
+        assert_eq!(state.buffer.cursor(), Vec2::new(4, 0));
+        assert_eq!(state.mode, Mode::Normal);
+
+        feed(&mut state, "n");
+        assert_eq!(state.buffer.cursor(), Vec2::new(0, 1));
+
+        feed(&mut state, "2n");
+        assert_eq!(state.buffer.cursor(), Vec2::new(4, 0));
+
+        feed(&mut state, "N");
+        assert_eq!(state.buffer.cursor(), Vec2::new(6, 2));
+
+        feed(&mut state, "?on\n");
+        assert_eq!(state.buffer.cursor(), Vec2::new(4, 1));
+
+        feed(&mut state, "n");
+        assert_eq!(state.buffer.cursor(), Vec2::new(0, 0));
+
+        feed(&mut state, "/\n");
+        assert_eq!(state.buffer.cursor(), Vec2::new(4, 1));
+    }
+
+    #[test]
+    fn search_errors() {
+        let mut state = state(&["one two"]);
+
+        feed(&mut state, "n");
+        assert_eq!(
+            state.message.as_deref(),
+            Some("E35: No previous regular expression")
+        );
+
+        feed(&mut state, "w/four\n");
+        assert_eq!(state.buffer.cursor(), Vec2::new(4, 0));
+        assert_eq!(
+            state.message.as_deref(),
+            Some("E486: Pattern not found: four")
+        );
+        assert!(state.failed);
+
+        feed(&mut state, "N");
+        assert_eq!(
+            state.message.as_deref(),
+            Some("E486: Pattern not found: four")
+        );
+
+        feed(&mut state, "?t(\n");
+        assert_eq!(state.buffer.cursor(), Vec2::new(4, 0));
+        assert_eq!(
+            state.message.as_deref(),
+            Some("E383: Invalid search string: t(")
+        );
+    }
+
+    #[test]
+    fn search_regex() {
+        let mut state = state(&["a1 b22 c333"]);
+
+        feed(&mut state, "/[a-z]\\d{2,}\n");
+        assert_eq!(state.buffer.cursor(), Vec2::new(3, 0));
+
+        feed(&mut state, "n");
+        assert_eq!(state.buffer.cursor(), Vec2::new(7, 0));
+    }
+
+    #[test]
+    fn search_word_under_cursor() {
+        let mut state = state(&["foo foobar", "bar foo", "foo"]);
+
+        feed(&mut state, "*");
+        assert_eq!(state.buffer.cursor(), Vec2::new(4, 1));
+
+        feed(&mut state, "n");
+        assert_eq!(state.buffer.cursor(), Vec2::new(0, 2));
+
+        feed(&mut state, "#");
+        assert_eq!(state.buffer.cursor(), Vec2::new(4, 1));
+    }
+
+    #[test]
+    fn incremental_search() {
+        let mut state = state(&["one", "two", "three"]);
+
+        feed(&mut state, "/th");
+        assert_eq!(state.buffer.cursor(), Vec2::new(0, 2));
+
+        feed(&mut state, "\x1b");
+        assert_eq!(state.buffer.cursor(), Vec2::new(0, 0));
+        assert_eq!(state.mode, Mode::Normal);
+    }
+
+    #[test]
+    fn delete_to_search_match() {
+        let mut state = state(&["one two three"]);
+
+        feed(&mut state, "/three\n0dn");
+        assert_eq!(lines(&state), vec!["three"]);
+    }
+
+    #[test]
+    fn highlight_until_noh() {
+        let mut state = state(&["one two"]);
+        let render = |state: &State| {
+            let mut out = Vec::new();
+            state.render(&mut out);
+            String::from_utf8(out).unwrap()
+        };
+        let invert = termion::style::Invert.to_string();
+
+        assert!(!render(&state).contains(&invert));
+
+        feed(&mut state, "/two\n");
+        assert!(render(&state).contains(&invert));
+
+        feed(&mut state, ":noh\n");
+        assert!(!render(&state).contains(&invert));
+
+        feed(&mut state, "n");
+        assert!(render(&state).contains(&invert));
+    }
+
+    #[test]
+    fn substitute_range() {
+        let mut state = state(&["a a", "a a", "a a", "a a"]);
+
+        feed(&mut state, ":2,3s/a/b/g\n");
+        assert_eq!(lines(&state), vec!["a a", "b b", "b b", "a a"]);
+        assert_eq!(state.buffer.cursor(), Vec2::new(0, 2));
+        assert_eq!(state.message.as_deref(), Some("4 substitutions on 2 lines"));
+
+        feed(&mut state, "u");
+        assert_eq!(lines(&state), vec!["a a", "a a", "a a", "a a"]);
+        assert_eq!(state.message, None);
+    }
+
+    #[test]
+    fn substitute_current_line_and_whole_buffer() {
+        let mut state = state(&["one", "two", "one"]);
+
+        feed(&mut state, ":s/o/0/\n");
+        assert_eq!(lines(&state), vec!["0ne", "two", "one"]);
+
+        feed(&mut state, ":%s/(\\w)(\\w)/\\2\\1\n");
+        assert_eq!(lines(&state), vec!["n0e", "wto", "noe"]);
+    }
+
+    #[test]
+    fn substitute_confirm() {
+        let mut state = state(&["a a", "a"]);
+
+        feed(&mut state, ":%s/a/b/gc\n");
+        assert_eq!(state.mode, Mode::Confirm);
+        assert_eq!(state.buffer.cursor(), Vec2::new(0, 0));
+
+        feed(&mut state, "n");
+        assert_eq!(state.buffer.cursor(), Vec2::new(2, 0));
+
+        feed(&mut state, "y");
+        assert_eq!(state.buffer.cursor(), Vec2::new(0, 1));
+
+        feed(&mut state, "q");
+        assert_eq!(state.mode, Mode::Normal);
+        assert_eq!(lines(&state), vec!["a b", "a"]);
+        assert_eq!(state.message.as_deref(), Some("1 substitution on 1 line"));
+
+        feed(&mut state, "u");
+        assert_eq!(lines(&state), vec!["a a", "a"]);
+    }
+
+    #[test]
+    fn substitute_errors() {
+        let mut state = state(&["a"]);
+
+        feed(&mut state, ":s/b/c/\n");
+        assert_eq!(state.message.as_deref(), Some("E486: Pattern not found: b"));
+
+        feed(&mut state, ":5s/a/c/\n");
+        assert_eq!(state.message.as_deref(), Some("E16: Invalid range"));
+        assert_eq!(lines(&state), vec!["a"]);
+    }
+
+    #[test]
+    fn ex_commands_chained() {
+        let mut state = state(&["a b", "a b"]);
+
+        feed(&mut state, ":%s/a/x/ | %s/b/y/\n");
+        assert_eq!(lines(&state), vec!["x y", "x y"]);
+
+        feed(&mut state, ":1s/x/z/|foo|s/y/z/\n");
+        assert_eq!(lines(&state), vec!["z y", "x y"]);
+        assert_eq!(
+            state.message.as_deref(),
+            Some("E492: Not an editor command: foo")
+        );
+    }
+
+    #[test]
+    fn ex_command_errors() {
+        let mut state = state(&["a"]);
+
+        feed(&mut state, ":nohlsearchx\n");
+        assert_eq!(
+            state.message.as_deref(),
+            Some("E492: Not an editor command: nohlsearchx")
+        );
+
+        feed(&mut state, ":1noh\n");
+        assert_eq!(state.message.as_deref(), Some("E481: No range allowed"));
+
+        feed(&mut state, ":noh!\n");
+        assert_eq!(state.message.as_deref(), Some("E477: No ! allowed"));
+
+        feed(&mut state, ":w\n");
+        assert_eq!(state.message.as_deref(), Some("E32: No file name"));
+        assert_eq!(state.mode, Mode::Normal);
+    }
+
+    #[test]
+    fn ex_quit() {
+        let mut state = state(&["a"]);
+
+        assert!(state.execute("q"));
+        assert!(state.execute("quit!"));
+        assert!(!state.execute("noh"));
+    }
+
+    #[test]
+    fn ex_goto_line() {
+        let mut state = state(&["a", "  b", "c"]);
+
+        feed(&mut state, ":2\n");
+        assert_eq!(state.buffer.cursor(), Vec2::new(2, 1));
+
+        feed(&mut state, ":$\n");
+        assert_eq!(state.buffer.cursor(), Vec2::new(0, 2));
+    }
+
+    #[test]
+    fn write_and_quit() {
+        let path = temp_path("write_and_quit");
+        std::fs::write(&path, "one\ntwo").unwrap();
+        let mut state = file_state(&path);
+
+        assert!(state.execute("q"));
+
+        feed(&mut state, "x");
+        assert!(!state.execute("q"));
+        assert_eq!(
+            state.message.as_deref(),
+            Some("E37: No write since last change (add ! to override)")
+        );
+
+        feed(&mut state, ":w\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "ne\ntwo");
+        assert_eq!(
+            state.message.as_deref(),
+            Some(format!("\"{}\" 2L, 6B written", path).as_str())
+        );
+        assert!(state.execute("q"));
+
+        feed(&mut state, "x");
+        assert!(state.execute("q!"));
+        assert!(state.execute("wq"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "e\ntwo");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn write_to_other_file() {
+        let path = temp_path("write_to_other_file");
+        let other = temp_path("write_to_other_file_other");
+        std::fs::write(&path, "one").unwrap();
+        std::fs::write(&other, "other").unwrap();
+        let mut state = file_state(&path);
+
+        feed(&mut state, "x");
+        feed(&mut state, &format!(":w {}\n", other));
+        assert_eq!(
+            state.message.as_deref(),
+            Some("E13: File exists (add ! to override)")
+        );
+
+        feed(&mut state, &format!(":w! {}\n", other));
+        assert_eq!(std::fs::read_to_string(&other).unwrap(), "ne");
+        assert!(state.buffer.is_modified());
+
+        feed(&mut state, &format!(":saveas {}\n", other));
+        assert_eq!(
+            state.message.as_deref(),
+            Some("E13: File exists (add ! to override)")
+        );
+
+        feed(&mut state, &format!(":sav! {}\n", other));
+        assert_eq!(state.buffer.file_path(), Some(other.as_str()));
+        assert!(!state.buffer.is_modified());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one");
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&other).unwrap();
+    }
+
+    #[test]
+    fn write_without_file_name() {
+        let path = temp_path("write_without_file_name");
+        let mut state = state(&["a"]);
+
+        feed(&mut state, "x");
+        assert!(!state.execute("x"));
+        assert_eq!(state.message.as_deref(), Some("E32: No file name"));
+
+        assert!(state.execute(&format!("x {}", path)));
+        assert_eq!(state.buffer.file_path(), Some(path.as_str()));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn keep_file_encoding() {
+        let path = temp_path("keep_file_encoding");
+        std::fs::write(&path, b"caf\xe9\nna\xefve").unwrap();
+        let mut state = file_state(&path);
+
+        assert_eq!(lines(&state), vec!["caf\u{e9}", "na\u{ef}ve"]);
+        feed(&mut state, ":set fenc?\n");
+        assert_eq!(state.message.as_deref(), Some("fileencoding=latin1"));
+
+        feed(&mut state, "x:w\n");
+        assert_eq!(std::fs::read(&path).unwrap(), b"af\xe9\nna\xefve");
+
+        feed(&mut state, "A\u{263a}\x1b:w\n");
+        assert_eq!(
+            state.message.as_deref(),
+            Some(format!("\"{}\" E513: Write error, conversion failed", path).as_str())
+        );
+        assert_eq!(std::fs::read(&path).unwrap(), b"af\xe9\nna\xefve");
+
+        feed(&mut state, ":set fenc=utf-16le\n:w\n");
+        assert_eq!(
+            std::fs::read(&path).unwrap(),
+            b"\xff\xfea\x00f\x00\xe9\x00:&\n\x00n\x00a\x00\xef\x00v\x00e\x00"
+        );
+
+        feed(&mut state, ":set fenc=foo\n");
+        assert_eq!(
+            state.message.as_deref(),
+            Some("E474: Invalid argument: fenc=foo")
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn keep_invalid_bytes_of_binary_files() {
+        let path = temp_path("keep_invalid_bytes_of_binary_files");
+        let bytes = b"\x7fELF\x00\xc3\xa9\xff\xfe";
+        std::fs::write(&path, bytes).unwrap();
+        let mut state = file_state(&path);
+
+        assert_eq!(
+            state.message.as_deref(),
+            Some(format!("\"{}\" Warning: looks like a binary file", path).as_str())
+        );
+
+        feed(&mut state, "ix\x1b0x:w\n");
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn keep_line_endings() {
+        let path = temp_path("keep_line_endings");
+        std::fs::write(&path, "one\r\ntwo").unwrap();
+        let mut state = file_state(&path);
+
+        assert_eq!(lines(&state), vec!["one", "two"]);
+        feed(&mut state, ":set ff? eol? fixeol?\n");
+        assert_eq!(
+            state.message.as_deref(),
+            Some("fileformat=dos noendofline nofixendofline")
+        );
+
+        feed(&mut state, "x:w\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "ne\r\ntwo");
+
+        feed(&mut state, ":set fixeol\n:w\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "ne\r\ntwo\r\n");
+
+        feed(&mut state, ":set ff=mac\n:w\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "ne\rtwo\r");
+        let mut state = file_state(&path);
+        assert_eq!(lines(&state), vec!["ne", "two"]);
+
+        feed(&mut state, ":set ff=unix noeol\n:w\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "ne\ntwo\n");
+        feed(&mut state, ":set nofixeol\n:w\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "ne\ntwo");
+        feed(&mut state, ":set inveol\n:w\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "ne\ntwo\n");
+
+        feed(&mut state, ":set ff=cr\n");
+        assert_eq!(
+            state.message.as_deref(),
+            Some("E474: Invalid argument: ff=cr")
+        );
+        feed(&mut state, ":set noff\n");
+        assert_eq!(
+            state.message.as_deref(),
+            Some("E474: Invalid argument: noff")
+        );
+        feed(&mut state, ":set eol=1\n");
+        assert_eq!(
+            state.message.as_deref(),
+            Some("E474: Invalid argument: eol=1")
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn new_files_end_with_line_break() {
+        let path = temp_path("new_files_end_with_line_break");
+        let mut state = file_state(&path);
+
+        feed(&mut state, ":set eol? fixeol?\n");
+        assert_eq!(state.message.as_deref(), Some("endofline fixendofline"));
+        feed(&mut state, ":w\n");
+        assert_eq!(std::fs::read(&path).unwrap(), b"");
+
+        feed(&mut state, "ihello\x1b:w\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello\n");
+
+        std::fs::write(&path, "\n").unwrap();
+        let mut state = file_state(&path);
+        feed(&mut state, ":w\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "\n");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn zz_and_zq() {
+        let path = temp_path("zz_and_zq");
+        std::fs::write(&path, "one").unwrap();
+
+        let mut state = file_state(&path);
+        feed(&mut state, "x");
+        assert!(!state.update(Event::Key(Key::Char('Z'))));
+        assert!(state.update(Event::Key(Key::Char('Q'))));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one");
+
+        let mut state = file_state(&path);
+        feed(&mut state, "x");
+        feed(&mut state, "Z");
+        assert!(state.update(Event::Key(Key::Char('Z'))));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "ne");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn visual_delete_and_yank() {
+        let mut state = state(&["one two", "three"]);
+
+        feed(&mut state, "lvjd");
+        assert_eq!(lines(&state), vec!["oree"]);
+        assert_eq!(state.mode, Mode::Normal);
+        assert_eq!(state.buffer.selection(), None);
+
+        feed(&mut state, "ugg0vly$p");
+        assert_eq!(lines(&state), vec!["one twoon", "three"]);
+    }
+
+    #[test]
+    fn visual_line() {
+        let mut state = state(&["a", "b", "c", "d"]);
+
+        feed(&mut state, "jVjd");
+        assert_eq!(lines(&state), vec!["a", "d"]);
+
+        feed(&mut state, "Vkyjp");
+        assert_eq!(lines(&state), vec!["a", "d", "a", "d"]);
+    }
+
+    #[test]
+    fn visual_switch_and_swap_ends() {
+        let mut state = state(&["abcdef"]);
+
+        feed(&mut state, "lvll");
+        assert_eq!(state.mode, Mode::Visual(VisualKind::Char));
+
+        feed(&mut state, "o");
+        assert_eq!(state.buffer.cursor(), Vec2::new(1, 0));
+
+        feed(&mut state, "hd");
+        assert_eq!(lines(&state), vec!["ef"]);
+
+        feed(&mut state, "vV");
+        assert_eq!(state.mode, Mode::Visual(VisualKind::Line));
+        feed(&mut state, "V");
+        assert_eq!(state.mode, Mode::Normal);
+    }
+
+    #[test]
+    fn visual_block_delete_and_put() {
+        let mut state = state(&["abcd", "efgh", "ijkl", "m"]);
+
+        feed(&mut state, "l\x16jjld");
+        assert_eq!(lines(&state), vec!["ad", "eh", "il", "m"]);
+
+        feed(&mut state, "$p");
+        assert_eq!(lines(&state), vec!["adbc", "ehfg", "iljk", "m"]);
+
+        feed(&mut state, "uGp");
+        assert_eq!(lines(&state), vec!["ad", "eh", "il", "mbc", " fg", " jk"]);
+    }
+
+    #[test]
+    fn visual_block_insert() {
+        let mut state = state(&["abc", "def", "ghi"]);
+
+        feed(&mut state, "l\x16jjI-\x1b");
+        assert_eq!(lines(&state), vec!["a-bc", "d-ef", "g-hi"]);
+
+        feed(&mut state, "u");
+        assert_eq!(lines(&state), vec!["abc", "def", "ghi"]);
+    }
+
+    #[test]
+    fn visual_block_append_pads_short_lines() {
+        let mut state = state(&["abc", "d", "efg"]);
+
+        feed(&mut state, "l\x16GlA+\x1b");
+        assert_eq!(lines(&state), vec!["ab+c", "d +", "ef+g"]);
+    }
+
+    #[test]
+    fn visual_block_change() {
+        let mut state = state(&["abcd", "efgh"]);
+
+        feed(&mut state, "l\x16jlcX\x1b");
+        assert_eq!(lines(&state), vec!["aXd", "eXh"]);
+    }
+
+    #[test]
+    fn visual_block_on_multibyte_and_wide_characters() {
+        let cases: &[(&[&str], &str, &[&str])] = &[
+            (&["aéb", "ébb"], "l\x16jAX\x1b", &["aéXb", "ébXb"]),
+            (&["abc", "ébc"], "l\x16jd", &["ac", "éc"]),
+            (&["a中b", "abcd"], "l\x16jd", &["ab", "ad"]),
+            (&["中文", "abcd"], "l\x16jI-\x1b", &["中-文", "ab-cd"]),
+            (&["ab", "中c"], "\x16jy$p", &["abab", "中中c"]),
+            (&["éab", "abc"], "l\x16jlOhd", &["", ""]),
+        ];
+
+        for (text, keys, expected) in cases {
+            let mut state = state(text);
+            feed(&mut state, keys);
+            assert_eq!(lines(&state), *expected, "{:?}", keys);
+        }
+    }
+
+    #[test]
+    fn visual_shift() {
+        let mut state = state(&["a", "b", "c"]);
+
+        feed(&mut state, "Vj>");
+        assert_eq!(lines(&state), vec!["    a", "    b", "c"]);
+        assert_eq!(state.buffer.cursor(), Vec2::new(4, 0));
+
+        feed(&mut state, "vj2<");
+        assert_eq!(lines(&state), vec!["a", "b", "c"]);
+
+        feed(&mut state, "2>>");
+        assert_eq!(lines(&state), vec!["    a", "    b", "c"]);
+    }
+
+    #[test]
+    fn visual_case() {
+        let mut state = state(&["Hello World"]);
+
+        feed(&mut state, "ve~");
+        assert_eq!(lines(&state), vec!["hELLO World"]);
+
+        feed(&mut state, "wveU");
+        assert_eq!(lines(&state), vec!["hELLO WORLD"]);
+
+        feed(&mut state, "Vu");
+        assert_eq!(lines(&state), vec!["hello world"]);
+
+        feed(&mut state, "0gUw");
+        assert_eq!(lines(&state), vec!["HELLO world"]);
+
+        feed(&mut state, "g~~");
+        assert_eq!(lines(&state), vec!["hello WORLD"]);
+    }
+
+    #[test]
+    fn case_mapped_to_several_characters() {
+        let mut state = state(&["straße", "İx"]);
+
+        feed(&mut state, "gUU");
+        assert_eq!(lines(&state), vec!["STRASSE", "İx"]);
+
+        feed(&mut state, "jg~~");
+        assert_eq!(lines(&state), vec!["STRASSE", "i\u{307}X"]);
+
+        feed(&mut state, "ggVjgu");
+        assert_eq!(lines(&state), vec!["strasse", "i\u{307}x"]);
+    }
+
+    #[test]
+    fn visual_join_and_replace() {
+        let mut state = state(&["a", "b", "c", "d"]);
+
+        feed(&mut state, "VjjJ");
+        assert_eq!(lines(&state), vec!["a b c", "d"]);
+
+        feed(&mut state, "0vllrx");
+        assert_eq!(lines(&state), vec!["xxx c", "d"]);
+
+        feed(&mut state, "\x16jr-");
+        assert_eq!(lines(&state), vec!["-xx c", "-"]);
+    }
+
+    #[test]
+    fn visual_selection_is_highlighted() {
+        let mut state = state(&["one two"]);
+        let render = |state: &State| {
+            let mut out = Vec::new();
+            state.render(&mut out);
+            String::from_utf8(out).unwrap()
+        };
+        let invert = termion::style::Invert.to_string();
+
+        assert!(!render(&state).contains(&invert));
+
+        feed(&mut state, "vl");
+        assert_eq!(render(&state).matches(&invert).count(), 2);
+
+        feed(&mut state, "\x1b");
+        assert!(!render(&state).contains(&invert));
+    }
+
+    #[test]
+    fn text_objects_with_operators() {
+        let mut state = state(&["call(one, two) \"x y\" end", "", "next"]);
+
+        feed(&mut state, "fnciwONE\x1b");
+        assert_eq!(lines(&state)[0], "call(ONE, two) \"x y\" end");
+
+        feed(&mut state, "di(");
+        assert_eq!(lines(&state)[0], "call() \"x y\" end");
+
+        feed(&mut state, "f\"lyi\"$p");
+        assert_eq!(lines(&state)[0], "call() \"x y\" endx y");
+
+        feed(&mut state, "0f\"da\"");
+        assert_eq!(lines(&state)[0], "call() endx y");
+
+        feed(&mut state, "dap");
+        assert_eq!(lines(&state), vec!["next"]);
+    }
+
+    #[test]
+    fn text_object_not_found() {
+        let mut state = state(&["one two"]);
+
+        feed(&mut state, "di(dix");
+        assert_eq!(lines(&state), vec!["one two"]);
+        assert_eq!(state.mode, Mode::Normal);
+    }
+
+    #[test]
+    fn text_objects_in_visual_mode() {
+        let mut state = state(&["f(a, [b, c])"]);
+
+        feed(&mut state, "fbvi[");
+        assert_eq!(state.mode, Mode::Visual(VisualKind::Char));
+        feed(&mut state, "d");
+        assert_eq!(lines(&state), vec!["f(a, [])"]);
+
+        feed(&mut state, "va(d");
+        assert_eq!(lines(&state), vec!["f"]);
+    }
+
+    #[test]
+    fn paragraph_selects_lines() {
+        let mut state = state(&["a", "b", "", "c"]);
+
+        feed(&mut state, "vip");
+        assert_eq!(state.mode, Mode::Visual(VisualKind::Line));
+        feed(&mut state, "d");
+        assert_eq!(lines(&state), vec!["", "c"]);
+    }
+
+    #[test]
+    fn repeat_operator_with_new_count() {
+        let mut state = state(&["a b c d e f g"]);
+
+        feed(&mut state, "2dw.");
+        assert_eq!(lines(&state), vec!["e f g"]);
+
+        feed(&mut state, "1.");
+        assert_eq!(lines(&state), vec!["f g"]);
+
+        feed(&mut state, "u");
+        assert_eq!(lines(&state), vec!["e f g"]);
+    }
+
+    #[test]
+    fn repeat_x_and_join() {
+        let mut state = state(&["abcdefg", "h", "i", "j"]);
+
+        feed(&mut state, "x..");
+        assert_eq!(lines(&state)[0], "defg");
+        feed(&mut state, "2.");
+        assert_eq!(lines(&state)[0], "fg");
+
+        feed(&mut state, "J.");
+        assert_eq!(lines(&state), vec!["fg h i", "j"]);
+    }
+
+    #[test]
+    fn repeat_insert() {
+        let mut state = state(&["x", "y"]);
+
+        feed(&mut state, "ifoo \x1bj0.");
+        assert_eq!(lines(&state), vec!["foo x", "foo y"]);
+
+        feed(&mut state, "$a!\x1bk.");
+        assert_eq!(lines(&state), vec!["foo x!", "foo y!"]);
+
+        feed(&mut state, "ob\x1b.");
+        assert_eq!(lines(&state), vec!["foo x!", "b", "b", "foo y!"]);
+    }
+
+    #[test]
+    fn repeat_change_with_text_object() {
+        let mut state = state(&["one two three"]);
+
+        feed(&mut state, "ciwX\x1bw.");
+        assert_eq!(lines(&state), vec!["X X three"]);
+    }
+
+    #[test]
+    fn repeat_ignores_commands_without_changes() {
+        let mut state = state(&["a b c d"]);
+
+        feed(&mut state, "dwywjlu\x12.");
+        assert_eq!(lines(&state), vec!["c d"]);
+    }
+
+    #[test]
+    fn record_and_run_macro() {
+        let mut state = state(&["a1", "b2", "c3", "d4", "e5"]);
+
+        feed(&mut state, "qa0xjq");
+        assert!(state.recording.is_none());
+        assert_eq!(state.registers.get(Some('a')).unwrap().text, "0xj");
+        assert_eq!(lines(&state)[0], "1");
+
+        feed(&mut state, "@a2@a");
+        assert_eq!(lines(&state), vec!["1", "2", "3", "4", "e5"]);
+
+        feed(&mut state, "@@");
+        assert_eq!(lines(&state)[4], "5");
+    }
+
+    #[test]
+    fn recursive_macro_stops_on_failure() {
+        let mut state = state(&["a1", "b2", "c3", "d4"]);
+
+        feed(&mut state, "qaqqa0xj@aq");
+        assert_eq!(lines(&state), vec!["1", "b2", "c3", "d4"]);
+
+        feed(&mut state, "@a");
+        assert_eq!(lines(&state), vec!["1", "2", "3", "4"]);
+        assert_eq!(state.buffer.cursor().y, 3);
+    }
+
+    #[test]
+    fn macro_with_insert_mode() {
+        let mut state = state(&["one", "two"]);
+
+        feed(&mut state, "qq$a!\x1bjq@q");
+        assert_eq!(lines(&state), vec!["one!", "two!"]);
+        assert_eq!(state.registers.get(Some('q')).unwrap().text, "$a!\x1bj");
+    }
+
+    #[test]
+    fn macro_can_be_put_and_yanked_back() {
+        let mut state = state(&["x", "hello"]);
+
+        feed(&mut state, "qbxq");
+        assert_eq!(lines(&state)[0], "");
+        feed(&mut state, "\"bp");
+        assert_eq!(lines(&state)[0], "x");
+
+        feed(&mut state, "ccx$a!\x1b");
+        feed(&mut state, "0\"by$j@b");
+        assert_eq!(lines(&state), vec!["x$a!", "ello!"]);
+    }
+
+    #[test]
+    fn set_and_jump_to_marks() {
+        let mut state = state(&["one", "  two", "three", "four"]);
+
+        feed(&mut state, "jlmaG'a");
+        assert_eq!(state.buffer.cursor(), Vec2::new(2, 1));
+        feed(&mut state, "G`a");
+        assert_eq!(state.buffer.cursor(), Vec2::new(1, 1));
+
+        feed(&mut state, "Gd'a");
+        assert_eq!(lines(&state), vec!["one"]);
+
+        feed(&mut state, "'z");
+        assert_eq!(state.message.as_deref(), Some("E20: Mark not set"));
+    }
+
+    #[test]
+    fn marks_follow_inserted_and_deleted_lines() {
+        let mut state = state(&["a", "b", "c"]);
+
+        feed(&mut state, "Gmcggdd'c");
+        assert_eq!(state.buffer.cursor().y, 1);
+
+        feed(&mut state, "ggonew\x1bgg'c");
+        assert_eq!(state.buffer.cursor().y, 2);
+    }
+
+    #[test]
+    fn marks_on_deleted_lines_are_removed() {
+        let mut state = state(&["a", "b", "c", "d"]);
+
+        feed(&mut state, "jmaddgg'a");
+        assert_eq!(state.message.as_deref(), Some("E20: Mark not set"));
+        assert_eq!(state.buffer.cursor().y, 0);
+
+        feed(&mut state, "Gmbjdd'b");
+        assert_eq!(state.message.as_deref(), Some("E20: Mark not set"));
+
+        feed(&mut state, "ggmcjJ'c");
+        assert_eq!(state.buffer.cursor(), Vec2::new(0, 0));
+    }
+
+    #[test]
+    fn previous_context_and_jump_list() {
+        let lines: Vec<String> = (1..=10).map(|i| i.to_string()).collect();
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let mut state = state(&lines);
+
+        feed(&mut state, "G''");
+        assert_eq!(state.buffer.cursor().y, 0);
+        feed(&mut state, "``");
+        assert_eq!(state.buffer.cursor().y, 9);
+
+        feed(&mut state, "5Ggg\x0f");
+        assert_eq!(state.buffer.cursor().y, 4);
+        feed(&mut state, "\x0f");
+        assert_eq!(state.buffer.cursor().y, 9);
+        feed(&mut state, "\t");
+        assert_eq!(state.buffer.cursor().y, 4);
+        feed(&mut state, "\t");
+        assert_eq!(state.buffer.cursor().y, 0);
+    }
+
+    #[test]
+    fn change_list() {
+        let mut state = state(&["ab", "cd", "ef", "gh"]);
+
+        feed(&mut state, "xjjxgg'.");
+        assert_eq!(state.buffer.cursor().y, 2);
+
+        feed(&mut state, "g;");
+        assert_eq!(state.buffer.cursor().y, 2);
+        feed(&mut state, "g;");
+        assert_eq!(state.buffer.cursor().y, 0);
+        feed(&mut state, "g;");
+        assert_eq!(
+            state.message.as_deref(),
+            Some("E662: At start of changelist")
+        );
+        feed(&mut state, "g,");
+        assert_eq!(state.buffer.cursor().y, 2);
+    }
+
+    #[test]
+    fn special_marks_and_ex_ranges() {
+        let mut state = state(&["one two", "a", "b", "c", "d"]);
+
+        feed(&mut state, "wyiw0`[");
+        assert_eq!(state.buffer.cursor(), Vec2::new(4, 0));
+        feed(&mut state, "0`]");
+        assert_eq!(state.buffer.cursor(), Vec2::new(6, 0));
+
+        feed(&mut state, "jmajmb:'a,'bs/^/-/\n");
+        assert_eq!(lines(&state), vec!["one two", "-a", "-b", "c", "d"]);
+
+        feed(&mut state, "jVj\x1bgg:'<,'>s/^/#/\n");
+        assert_eq!(lines(&state), vec!["one two", "-a", "-b", "#c", "#d"]);
+    }
+
+    #[test]
+    fn replace_char() {
+        let mut state = state(&["abcdef", "gh"]);
+
+        feed(&mut state, "rx");
+        assert_eq!(lines(&state)[0], "xbcdef");
+
+        feed(&mut state, "l3ry");
+        assert_eq!(lines(&state)[0], "xyyyef");
+        assert_eq!(state.buffer.cursor(), Vec2::new(3, 0));
+
+        feed(&mut state, "5rz");
+        assert_eq!(lines(&state)[0], "xyyyef");
+
+        feed(&mut state, "2r\n");
+        assert_eq!(lines(&state), vec!["xyy", "f", "gh"]);
+        assert_eq!(state.buffer.cursor(), Vec2::new(0, 1));
+
+        feed(&mut state, "jr\x1b");
+        assert_eq!(lines(&state)[2], "gh");
+    }
+
+    #[test]
+    fn replace_mode() {
+        let mut state = state(&["abc", "d"]);
+
+        feed(&mut state, "lRxyz!");
+        assert_eq!(lines(&state)[0], "axyz!");
+
+        feed(&mut state, "\x7f\x7f\x7f");
+        assert_eq!(lines(&state)[0], "axc");
+
+        feed(&mut state, "\x7f\x7f\x7f");
+        assert_eq!(lines(&state)[0], "abc");
+        assert_eq!(state.buffer.cursor(), Vec2::new(0, 0));
+
+        feed(&mut state, "\x1bu");
+        assert_eq!(lines(&state)[0], "abc");
+    }
+
+    #[test]
+    fn replace_mode_line_break_and_undo() {
+        let mut state = state(&["abcd"]);
+
+        feed(&mut state, "Rx\nyz\x1b");
+        assert_eq!(lines(&state), vec!["x", "yzd"]);
+        assert_eq!(state.mode, Mode::Normal);
+
+        feed(&mut state, "u");
+        assert_eq!(lines(&state), vec!["abcd"]);
+    }
+
+    #[test]
+    fn insert_mode_navigation() {
+        let mut state = state(&["abc", "defgh"]);
+
+        feed(&mut state, "i\x1b[C\x1b[Cx\x1b[By\x1b[Fz\x1b[H\x1b[A_\x1b");
+        assert_eq!(lines(&state), vec!["_abxc", "defyghz"]);
+
+        // each cursor move starts a new undo step
+        feed(&mut state, "u");
+        assert_eq!(lines(&state), vec!["abxc", "defyghz"]);
+    }
+
+    #[test]
+    fn insert_mode_page_keys() {
+        let lines_text: Vec<String> = (0..50).map(|i| i.to_string()).collect();
+        let lines_text: Vec<&str> = lines_text.iter().map(String::as_str).collect();
+        let mut state = state(&lines_text);
+
+        feed(&mut state, "i\x1b[6~");
+        assert_eq!(state.buffer.cursor(), Vec2::new(0, 17));
+
+        feed(&mut state, "\x1b[5~\x1b[5~");
+        assert_eq!(state.buffer.cursor(), Vec2::new(0, 0));
+    }
+
+    #[test]
+    fn insert_mode_delete() {
+        let mut state = state(&["abc", "def"]);
+
+        feed(&mut state, "a\x1b[3~\x1b[3~\x1b[3~x\x1b");
+        assert_eq!(lines(&state), vec!["axdef"]);
+    }
+
+    #[test]
+    fn insert_mode_delete_word_and_line() {
+        let mut state = state(&["    foo bar.baz"]);
+
+        feed(&mut state, "$a\x17");
+        assert_eq!(lines(&state), vec!["    foo bar."]);
+        feed(&mut state, "\x17\x17");
+        assert_eq!(lines(&state), vec!["    foo "]);
+        feed(&mut state, "x\x15");
+        assert_eq!(lines(&state), vec!["    "]);
+        feed(&mut state, "\x15");
+        assert_eq!(lines(&state), vec![""]);
+    }
+
+    #[test]
+    fn insert_mode_delete_word_joins_lines() {
+        let mut state = state(&["foo", "bar"]);
+
+        feed(&mut state, "ji\x17\x17x\x1b");
+        assert_eq!(lines(&state), vec!["xbar"]);
+    }
+
+    #[test]
+    fn insert_mode_register() {
+        let mut state = state(&["foo bar"]);
+
+        feed(&mut state, "yw$a \x12\"\x1b");
+        assert_eq!(lines(&state), vec!["foo bar foo "]);
+
+        feed(&mut state, "yy0i\x12\"\x1b");
+        assert_eq!(lines(&state), vec!["foo bar foo ", "foo bar foo "]);
+    }
+
+    #[test]
+    fn insert_mode_runs_one_normal_command() {
+        let mut state = state(&["abc", "def", "ghi"]);
+
+        feed(&mut state, "$a\x0fjx\x1b");
+        assert_eq!(lines(&state), vec!["abc", "dexf", "ghi"]);
+
+        feed(&mut state, "gg0i\x0fddy");
+        assert_eq!(state.mode, Mode::Insert);
+        feed(&mut state, "\x1b");
+        assert_eq!(lines(&state), vec!["ydexf", "ghi"]);
+    }
+
+    #[test]
+    fn insert_mode_shift() {
+        let mut state = state(&["  foo", ""]);
+
+        feed(&mut state, "$i\x14");
+        assert_eq!(lines(&state), vec!["      foo", ""]);
+        assert_eq!(state.buffer.cursor(), Vec2::new(8, 0));
+
+        feed(&mut state, "\x04\x04x");
+        assert_eq!(lines(&state), vec!["foxo", ""]);
+
+        feed(&mut state, "\x1bja\x14y\x1b");
+        assert_eq!(lines(&state), vec!["foxo", "    y"]);
+    }
+
+    #[test]
+    fn insert_mode_literal() {
+        let mut state = state(&[""]);
+
+        feed(&mut state, "i\x16\x1b\x16\x01\x16\n\x1b");
+        assert_eq!(lines(&state), vec!["\x1b\x01\r"]);
+    }
+
+    #[test]
+    fn insert_at_line_start_and_end() {
+        let mut state = state(&["  foo bar"]);
+
+        feed(&mut state, "$Ix\x1b");
+        assert_eq!(lines(&state), vec!["  xfoo bar"]);
+
+        feed(&mut state, "0Ay\x1b");
+        assert_eq!(lines(&state), vec!["  xfoo bary"]);
+        assert_eq!(state.buffer.cursor(), Vec2::new(10, 0));
+    }
+
+    #[test]
+    fn insert_with_count() {
+        let mut state = state(&["ab"]);
+
+        feed(&mut state, "3ax\x1b");
+        assert_eq!(lines(&state), vec!["axxxb"]);
+        assert_eq!(state.buffer.cursor(), Vec2::new(4, 0));
+
+        feed(&mut state, "2Ayz\x1b");
+        assert_eq!(lines(&state), vec!["axxxbyzyz"]);
+
+        feed(&mut state, "u");
+        assert_eq!(lines(&state), vec!["axxxb"]);
+    }
+
+    #[test]
+    fn open_line_above() {
+        let mut state = state(&["foo", "bar"]);
+
+        feed(&mut state, "jOx\x1b");
+        assert_eq!(lines(&state), vec!["foo", "x", "bar"]);
+
+        feed(&mut state, "gg2Oy\x1b");
+        assert_eq!(lines(&state), vec!["y", "y", "foo", "x", "bar"]);
+        assert_eq!(state.buffer.cursor(), Vec2::new(0, 1));
+
+        feed(&mut state, "G3oz\x1b");
+        assert_eq!(
+            lines(&state),
+            vec!["y", "y", "foo", "x", "bar", "z", "z", "z"]
+        );
+    }
+
+    #[test]
+    fn substitute_chars_and_lines() {
+        let mut state = state(&["foo bar", "  baz", ""]);
+
+        feed(&mut state, "2sx\x1b");
+        assert_eq!(lines(&state), vec!["xo bar", "  baz", ""]);
+
+        feed(&mut state, "jSy\x1b");
+        assert_eq!(lines(&state), vec!["xo bar", "y", ""]);
+
+        feed(&mut state, "jsz\x1b");
+        assert_eq!(lines(&state), vec!["xo bar", "y", "z"]);
+
+        feed(&mut state, "gg\"a2Sw\x1b");
+        assert_eq!(lines(&state), vec!["w", "z"]);
+        assert_eq!(state.registers.get(Some('a')).unwrap().text, "xo bar\ny");
+    }
+
+    #[test]
+    fn change_and_delete_to_line_end() {
+        let mut state = state(&["foo bar", "baz", "qux"]);
+
+        feed(&mut state, "wCx\x1b");
+        assert_eq!(lines(&state), vec!["foo x", "baz", "qux"]);
+
+        feed(&mut state, "0lD");
+        assert_eq!(lines(&state), vec!["f", "baz", "qux"]);
+        assert_eq!(state.registers.get(None).unwrap().text, "oo x");
+
+        feed(&mut state, "j2D");
+        assert_eq!(lines(&state), vec!["f", ""]);
+
+        feed(&mut state, "CX\x1b");
+        assert_eq!(lines(&state), vec!["f", "X"]);
+        assert_eq!(state.mode, Mode::Normal);
+    }
+
+    #[test]
+    fn change_and_delete_last_character() {
+        let mut state = state(&["abc"]);
+
+        feed(&mut state, "$D");
+        assert_eq!(lines(&state), vec!["ab"]);
+
+        feed(&mut state, "$CX\x1b");
+        assert_eq!(lines(&state), vec!["aX"]);
+    }
+
+    #[test]
+    fn delete_and_yank_lines() {
+        let mut state = state(&["a", "b", "c", "d"]);
+
+        feed(&mut state, "\"b2Yj3dd");
+        assert_eq!(lines(&state), vec!["a"]);
+        assert_eq!(state.registers.get(Some('b')).unwrap().text, "a\nb");
+        assert_eq!(state.registers.get(None).unwrap().text, "b\nc\nd");
+
+        feed(&mut state, "yyp\"bP");
+        assert_eq!(lines(&state), vec!["a", "a", "b", "a"]);
+    }
+
+    fn numbered_state(count: usize) -> State {
+        let text: Vec<String> = (0..count).map(|i| i.to_string()).collect();
+        state(&text.iter().map(String::as_str).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn scroll_keys() {
+        let mut state = numbered_state(200);
+
+        feed(&mut state, "\x04");
+        assert_eq!(state.buffer.cursor().y, 9);
+        assert_eq!(state.buffer.window_line(WindowPosition::Top, 1), 9);
+
+        feed(&mut state, "3\x04\x15");
+        assert_eq!(state.buffer.cursor().y, 9);
+        assert_eq!(state.buffer.scroll(), 3);
+
+        feed(&mut state, "\x06");
+        assert_eq!(state.buffer.window_line(WindowPosition::Top, 1), 26);
+        assert_eq!(state.buffer.cursor().y, 26);
+
+        feed(&mut state, "\x02");
+        assert_eq!(state.buffer.window_line(WindowPosition::Top, 1), 9);
+        assert_eq!(state.buffer.cursor().y, 26);
+
+        feed(&mut state, "2\x05");
+        assert_eq!(state.buffer.window_line(WindowPosition::Top, 1), 11);
+        assert_eq!(state.buffer.cursor().y, 26);
+
+        feed(&mut state, "20\x19");
+        assert_eq!(state.buffer.window_line(WindowPosition::Top, 1), 0);
+        assert_eq!(state.buffer.cursor().y, 18);
+
+        feed(&mut state, "\x19");
+        assert!(state.failed);
+    }
+
+    #[test]
+    fn scroll_cursor_line_with_z() {
+        let mut state = numbered_state(200);
+
+        feed(&mut state, "50Gzt");
+        assert_eq!(state.buffer.window_line(WindowPosition::Top, 1), 49);
+
+        feed(&mut state, "zb");
+        assert_eq!(state.buffer.window_line(WindowPosition::Bottom, 1), 49);
+
+        feed(&mut state, "zz");
+        assert_eq!(state.buffer.window_line(WindowPosition::Middle, 1), 49);
+
+        feed(&mut state, "100z\n");
+        assert_eq!(state.buffer.cursor().y, 99);
+        assert_eq!(state.buffer.window_line(WindowPosition::Top, 1), 99);
+    }
+
+    #[test]
+    fn window_line_motions() {
+        let mut state = numbered_state(200);
+
+        feed(&mut state, "100GztL");
+        assert_eq!(state.buffer.cursor().y, 117);
+        feed(&mut state, "M");
+        assert_eq!(state.buffer.cursor().y, 108);
+        feed(&mut state, "3H");
+        assert_eq!(state.buffer.cursor().y, 101);
+
+        feed(&mut state, "''");
+        assert_eq!(state.buffer.cursor().y, 108);
+
+        feed(&mut state, "dH");
+        assert_eq!(state.buffer.line_count(), 190);
+        assert_eq!(state.buffer.line(99), "109");
+    }
+
+    #[test]
+    fn set_scrolloff() {
+        let mut state = numbered_state(200);
+
+        feed(&mut state, ":set so=5\n");
+        assert_eq!(state.buffer.scrolloff(), 5);
+
+        feed(&mut state, "LH");
+        assert_eq!(state.buffer.cursor().y, 0);
+        feed(&mut state, "50G");
+        assert_eq!(state.buffer.window_line(WindowPosition::Bottom, 1), 49);
+
+        feed(&mut state, ":set so scr\n");
+        assert_eq!(state.message.as_deref(), Some("scrolloff=5 scroll=9"));
+
+        feed(&mut state, ":set so=x\n");
+        assert_eq!(
+            state.message.as_deref(),
+            Some("E521: Number required after =: so=x")
+        );
+
+        feed(&mut state, ":set foo\n");
+        assert_eq!(state.message.as_deref(), Some("E518: Unknown option: foo"));
+    }
+
+    #[test]
+    fn set_tabstop() {
+        let mut state = state(&["\tfoo"]);
+
+        feed(&mut state, ":set ts?\n");
+        assert_eq!(state.message.as_deref(), Some("tabstop=8"));
+
+        feed(&mut state, ":set ts=4\n:set tabstop\n");
+        assert_eq!(state.buffer.tabstop(), 4);
+        assert_eq!(state.message.as_deref(), Some("tabstop=4"));
+
+        feed(&mut state, ":set ts=0\n");
+        assert_eq!(
+            state.message.as_deref(),
+            Some("E487: Argument must be positive: ts=0")
+        );
+        feed(&mut state, ":set ts=18446744073709551615\n");
+        assert_eq!(
+            state.message.as_deref(),
+            Some("E474: Invalid argument: ts=18446744073709551615")
+        );
+        assert_eq!(state.buffer.tabstop(), 4);
+
+        feed(&mut state, ":set ts=9999\n");
+        assert_eq!(state.buffer.tabstop(), 9999);
+        assert_eq!(state.buffer.byte_to_col(Vec2::new(1, 0)), 9999);
+        state.render(&mut Vec::new());
+    }
+
+    #[test]
+    fn match_pair_motion() {
+        let mut state = state(&["if (a[0]) {", "    b(c);", "}"]);
+
+        feed(&mut state, "%");
+        assert_eq!(state.buffer.cursor(), Vec2::new(8, 0));
+        feed(&mut state, "%");
+        assert_eq!(state.buffer.cursor(), Vec2::new(3, 0));
+
+        feed(&mut state, "$%");
+        assert_eq!(state.buffer.cursor(), Vec2::new(0, 2));
+        feed(&mut state, "``");
+        assert_eq!(state.buffer.cursor(), Vec2::new(10, 0));
+
+        feed(&mut state, "j0d%");
+        assert_eq!(lines(&state), vec!["if (a[0]) {", ";", "}"]);
+
+        feed(&mut state, "%");
+        assert!(state.failed);
+    }
+
+    #[test]
+    fn highlight_matching_bracket() {
+        let mut state = state(&["f(x)", "(y"]);
+        let render = |state: &State| {
+            let mut out = Vec::new();
+            state.render(&mut out);
+            String::from_utf8(out).unwrap()
+        };
+        let matching = format!("{})", termion::color::Bg(termion::color::Cyan));
+
+        assert!(!render(&state).contains(&matching));
+
+        feed(&mut state, "l");
+        assert!(render(&state).contains(&matching));
+
+        // an unmatched bracket has nothing to highlight
+        feed(&mut state, "j0");
+        let cyan = termion::color::Bg(termion::color::Cyan).to_string();
+        assert!(!render(&state).contains(&cyan));
+    }
+}
@@ -0,0 +1,199 @@
+use std::borrow::Cow;
+use std::iter;
+
+use ropey::Rope;
+
+use crate::Vec2;
+
+/// Text of a buffer, as lines joined with `'\n'`, kept in a rope so that
+/// editing it and looking up a line take O(log n) time anywhere in a large
+/// file. Positions are made of a line and a byte column in it, like the
+/// cursor.
+#[derive(Debug, Clone, Default)]
+pub struct Lines {
+    rope: Rope,
+}
+
+impl Lines {
+    /// Pieces the text is stored in, in order.
+    pub fn chunks(&self) -> impl Iterator<Item = &str> + '_ {
+        self.rope.chunks()
+    }
+
+    /// Number of lines, which is never 0 as an empty text has an empty line.
+    pub fn len(&self) -> usize {
+        self.rope.len_lines()
+    }
+
+    /// Line `y` without its line break, only copied when it is split across
+    /// chunks of the rope.
+    pub fn line(&self, y: usize) -> Cow<'_, str> {
+        let start = self.rope.line_to_byte(y);
+        self.rope.byte_slice(start..start + self.line_len(y)).into()
+    }
+
+    pub fn line_len(&self, y: usize) -> usize {
+        let end = if y + 1 < self.len() {
+            self.rope.line_to_byte(y + 1) - 1
+        } else {
+            self.rope.len_bytes()
+        };
+        end - self.rope.line_to_byte(y)
+    }
+
+    /// Inserts `text` at `pos`, where its line breaks split the line.
+    pub fn insert(&mut self, pos: Vec2, text: &str) {
+        let at = self.char_index(pos);
+        self.rope.insert(at, text);
+    }
+
+    /// Removes the text from `start` up to, but not including, `end`.
+    pub fn remove(&mut self, start: Vec2, end: Vec2) {
+        let range = self.char_index(start)..self.char_index(end);
+        self.rope.remove(range);
+    }
+
+    /// Text from `start` up to, but not including, `end`.
+    pub fn slice(&self, start: Vec2, end: Vec2) -> String {
+        self.rope
+            .byte_slice(self.byte_index(start)..self.byte_index(end))
+            .to_string()
+    }
+
+    /// Text of `count` lines starting at line `y`, joined with `'\n'`.
+    pub fn lines_text(&self, y: usize, count: usize) -> String {
+        let last = y.saturating_add(count).min(self.len()) - 1;
+        self.slice(Vec2::new(0, y), Vec2::new(self.line_len(last), last))
+    }
+
+    /// Characters after `pos`, or before it when not `forward`, along with
+    /// their positions. Line breaks are skipped.
+    pub fn chars_around(
+        &self,
+        pos: Vec2,
+        forward: bool,
+    ) -> impl Iterator<Item = (Vec2, char)> + '_ {
+        let mut chars = self.rope.chars_at(self.char_index(pos));
+        let mut at = pos;
+        if forward {
+            if let Some(c) = chars.next() {
+                at = Self::advance(at, c);
+            }
+        }
+
+        iter::from_fn(move || loop {
+            if forward {
+                let c = chars.next()?;
+                let here = at;
+                at = Self::advance(at, c);
+                if c != '\n' {
+                    return Some((here, c));
+                }
+            } else {
+                let c = chars.prev()?;
+                if c == '\n' {
+                    at = Vec2::new(self.line_len(at.y - 1), at.y - 1);
+                } else {
+                    at.x -= c.len_utf8();
+                    return Some((at, c));
+                }
+            }
+        })
+    }
+
+    fn advance(pos: Vec2, c: char) -> Vec2 {
+        if c == '\n' {
+            Vec2::new(0, pos.y + 1)
+        } else {
+            Vec2::new(pos.x + c.len_utf8(), pos.y)
+        }
+    }
+
+    fn byte_index(&self, pos: Vec2) -> usize {
+        self.rope.line_to_byte(pos.y) + pos.x
+    }
+
+    fn char_index(&self, pos: Vec2) -> usize {
+        self.rope.byte_to_char(self.byte_index(pos))
+    }
+}
+
+impl From<&str> for Lines {
+    fn from(text: &str) -> Self {
+        Self {
+            rope: Rope::from_str(text),
+        }
+    }
+}
+
+impl From<Vec<String>> for Lines {
+    fn from(lines: Vec<String>) -> Self {
+        Self {
+            rope: Rope::from_str(&lines.join("\n")),
+        }
+    }
+}
+
+#[cfg(test)]
+impl PartialEq<Vec<&str>> for Lines {
+    fn eq(&self, other: &Vec<&str>) -> bool {
+        self.len() == other.len() && (0..self.len()).all(|y| self.line(y) == other[y])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(lines: &[&str]) -> Lines {
+        Lines::from(
+            lines
+                .iter()
+                .map(|line| line.to_string())
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn lines_without_line_breaks() {
+        let text = lines(&["héllo", "", "world"]);
+
+        assert_eq!(text.len(), 3);
+        assert_eq!(text.line(0), "héllo");
+        assert_eq!(text.line(1), "");
+        assert_eq!(text.line(2), "world");
+        assert_eq!(text.line_len(0), 6);
+        assert_eq!(text.lines_text(1, usize::MAX), "\nworld");
+        assert_eq!(lines(&[]), vec![""]);
+    }
+
+    #[test]
+    fn insert_and_remove() {
+        let mut text = lines(&["héllo", "world"]);
+
+        text.insert(Vec2::new(3, 0), "y\n\nx");
+        assert_eq!(text, vec!["héy", "", "xllo", "world"]);
+        assert_eq!(text.slice(Vec2::new(1, 0), Vec2::new(1, 2)), "éy\n\nx");
+
+        text.remove(Vec2::new(3, 0), Vec2::new(1, 2));
+        assert_eq!(text, vec!["héllo", "world"]);
+    }
+
+    #[test]
+    fn chars_around() {
+        let text = lines(&["ab", "", "é"]);
+
+        let after: Vec<_> = text.chars_around(Vec2::new(0, 0), true).collect();
+        assert_eq!(after, vec![(Vec2::new(1, 0), 'b'), (Vec2::new(0, 2), 'é')]);
+
+        let before: Vec<_> = text.chars_around(Vec2::new(2, 2), false).collect();
+        assert_eq!(
+            before,
+            vec![
+                (Vec2::new(0, 2), 'é'),
+                (Vec2::new(1, 0), 'b'),
+                (Vec2::new(0, 0), 'a')
+            ]
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use termion::event::{Event, Key};
+
+use crate::register::{Register, RegisterKind};
+use crate::State;
+
+/// Events typed since `q{register}`, stored in the register once `q` is
+/// typed again.
+#[derive(Debug)]
+pub struct Recording {
+    pub register: char,
+    pub events: Vec<Event>,
+}
+
+/// Special keys stored as the escape sequences sent by terminals.
+const SEQUENCES: [(Key, &str); 9] = [
+    (Key::Up, "\x1b[A"),
+    (Key::Down, "\x1b[B"),
+    (Key::Right, "\x1b[C"),
+    (Key::Left, "\x1b[D"),
+    (Key::Home, "\x1b[H"),
+    (Key::End, "\x1b[F"),
+    (Key::Delete, "\x1b[3~"),
+    (Key::PageUp, "\x1b[5~"),
+    (Key::PageDown, "\x1b[6~"),
+];
+
+/// Turns events into the text a terminal would send for them, so that a
+/// macro can be put into the buffer, edited and yanked back. Events other
+/// than keys are dropped.
+pub fn encode(events: &[Event]) -> String {
+    let mut text = String::new();
+
+    for event in events {
+        let key = match event {
+            Event::Key(key) => *key,
+            _ => continue,
+        };
+
+        match key {
+            Key::Char(c) => text.push(c),
+            Key::Esc => text.push('\x1b'),
+            Key::Backspace => text.push('\x7f'),
+            Key::Ctrl(c) if c.is_ascii_lowercase() => text.push((c as u8 & 0x1f) as char),
+            key => {
+                if let Some((_, sequence)) = SEQUENCES.iter().find(|(k, _)| *k == key) {
+                    text.push_str(sequence);
+                }
+            }
+        }
+    }
+
+    text
+}
+
+/// Turns the text of a register back into key events.
+pub fn decode(text: &str) -> Vec<Event> {
+    let mut events = Vec::new();
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        if let Some((key, sequence)) = SEQUENCES.iter().find(|(_, s)| rest.starts_with(s)) {
+            events.push(Event::Key(*key));
+            rest = &rest[sequence.len()..];
+            continue;
+        }
+
+        let key = match c {
+            '\x1b' => Key::Esc,
+            '\x7f' => Key::Backspace,
+            '\r' => Key::Char('\n'),
+            '\t' | '\n' => Key::Char(c),
+            '\x01'..='\x1a' => Key::Ctrl((c as u8 - 1 + b'a') as char),
+            c => Key::Char(c),
+        };
+        events.push(Event::Key(key));
+        rest = &rest[c.len_utf8()..];
+    }
+
+    events
+}
+
+impl State {
+    pub fn start_recording(&mut self, register: char) {
+        self.recording = Some(Recording {
+            register,
+            events: Vec::new(),
+        });
+    }
+
+    /// Stores the recorded events, without the `q` that stopped the
+    /// recording, in the register given to `q`.
+    pub fn stop_recording(&mut self) {
+        let mut recording = match self.recording.take() {
+            Some(recording) => recording,
+            None => return,
+        };
+
+        if recording.events.last() == Some(&Event::Key(Key::Char('q'))) {
+            recording.events.pop();
+        }
+
+        let text = encode(&recording.events);
+        self.registers.store(
+            recording.register,
+            Register::new(text, RegisterKind::Charwise),
+        );
+    }
+
+    /// Types the content of a register `count` times, where `@` stands for
+    /// the register run last.
+    pub fn run_macro(&mut self, register: char, count: Option<usize>) {
+        let register = match register {
+            '@' => match self.last_macro {
+                Some(register) => register,
+                None => {
+                    self.message = Some("E748: No previously used register".to_string());
+                    self.failed = true;
+                    return;
+                }
+            },
+            register => register,
+        };
+        self.last_macro = Some(register);
+
+        let events = match self.registers.get(Some(register)) {
+            Some(register) => decode(&register.text),
+            None => return,
+        };
+
+        if !self.check_repeated_len(events.len(), count.unwrap_or(1)) {
+            return;
+        }
+
+        // the keys go before anything still left to type, so that a macro
+        // can run another one in the middle
+        for _ in 0..count.unwrap_or(1) {
+            for event in events.iter().rev() {
+                self.typeahead.push_front(event.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_and_decode() {
+        let events: Vec<Event> = [
+            Key::Char('i'),
+            Key::Char('é'),
+            Key::Char('\n'),
+            Key::Backspace,
+            Key::Left,
+            Key::Ctrl('w'),
+            Key::Esc,
+            Key::Char('j'),
+        ]
+        .iter()
+        .map(|key| Event::Key(*key))
+        .collect();
+
+        let text = encode(&events);
+
+        assert_eq!(text, "ié\n\x7f\x1b[D\x17\x1bj");
+        assert_eq!(decode(&text), events);
+    }
+}
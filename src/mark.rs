@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::Vec2;
+
+/// Longest jump list and change list kept, like vim.
+const MAX_ENTRIES: usize = 100;
+
+pub fn is_valid(name: char) -> bool {
+    name.is_ascii_alphabetic() || matches!(name, '\'' | '`' | '.' | '[' | ']' | '<' | '>')
+}
+
+/// Whether `m` can set the mark, as the others are only set by vipi.
+pub fn can_set(name: char) -> bool {
+    is_valid(name) && name != '.'
+}
+
+/// Positions remembered in a buffer, following vim's naming:
+///
+/// - `a` to `z` set with `m`
+/// - `A` to `Z` set with `m` too, which vim keeps across files, while vipi
+///   only edits one file at a time
+/// - `'` the position before the latest jump, also named `` ` ``
+/// - `.` where the last change was made
+/// - `[` and `]` the start and end of the last changed or yanked text
+/// - `<` and `>` the start and end of the last Visual selection
+///
+/// It also holds the jump list, walked with `Ctrl-O` and `Ctrl-I`, and the
+/// change list, walked with `g;` and `g,`. Every position is moved along
+/// with its text as lines are inserted or deleted before it.
+#[derive(Debug, Default)]
+pub struct Marks {
+    marks: HashMap<char, Vec2>,
+    jumps: Vec<Vec2>,
+    /// Entry of `jumps` reached with `Ctrl-O`, or its length when not
+    /// walking it.
+    jump_index: usize,
+    changes: Vec<Vec2>,
+    change_index: usize,
+}
+
+impl Marks {
+    pub fn get(&self, name: char) -> Option<Vec2> {
+        self.marks.get(&Self::canonical(name)).copied()
+    }
+
+    pub fn set(&mut self, name: char, pos: Vec2) {
+        self.marks.insert(Self::canonical(name), pos);
+    }
+
+    fn canonical(name: char) -> char {
+        if name == '`' {
+            '\''
+        } else {
+            name
+        }
+    }
+
+    /// Remembers `from` as the position a jump was made from. Like vim,
+    /// older jumps from the same line are forgotten.
+    pub fn push_jump(&mut self, from: Vec2) {
+        self.jumps.retain(|pos| pos.y != from.y);
+        self.jumps.push(from);
+        if self.jumps.len() > MAX_ENTRIES {
+            self.jumps.remove(0);
+        }
+
+        self.jump_index = self.jumps.len();
+        self.set('\'', from);
+    }
+
+    /// Goes `count` entries back in the jump list, as done by `Ctrl-O`. The
+    /// cursor position `from` is remembered first so that `Ctrl-I` can come
+    /// back to it.
+    pub fn older_jump(&mut self, from: Vec2, count: usize) -> Option<Vec2> {
+        if self.jump_index >= self.jumps.len() {
+            self.push_jump(from);
+            self.jump_index -= 1;
+        }
+
+        self.jump_index = self.jump_index.checked_sub(count)?;
+        Some(self.jumps[self.jump_index])
+    }
+
+    /// Goes `count` entries forward in the jump list, as done by `Ctrl-I`.
+    pub fn newer_jump(&mut self, count: usize) -> Option<Vec2> {
+        let index = self.jump_index.saturating_add(count);
+        if index >= self.jumps.len() {
+            return None;
+        }
+
+        self.jump_index = index;
+        Some(self.jumps[index])
+    }
+
+    /// Remembers where a change was made, replacing the last entry of the
+    /// change list when it is on the same line.
+    pub fn push_change(&mut self, pos: Vec2) {
+        if self.changes.last().is_some_and(|last| last.y == pos.y) {
+            self.changes.pop();
+        }
+        self.changes.push(pos);
+        if self.changes.len() > MAX_ENTRIES {
+            self.changes.remove(0);
+        }
+
+        self.change_index = self.changes.len();
+    }
+
+    /// Goes `count` entries back in the change list, as done by `g;`,
+    /// stopping at the oldest one.
+    pub fn older_change(&mut self, count: usize) -> Result<Vec2, String> {
+        if self.changes.is_empty() {
+            return Err("E664: Changelist is empty".to_string());
+        }
+        if self.change_index == 0 {
+            return Err("E662: At start of changelist".to_string());
+        }
+
+        self.change_index = self.change_index.saturating_sub(count);
+        Ok(self.changes[self.change_index])
+    }
+
+    /// Goes `count` entries forward in the change list, as done by `g,`,
+    /// stopping at the newest one.
+    pub fn newer_change(&mut self, count: usize) -> Result<Vec2, String> {
+        if self.changes.is_empty() {
+            return Err("E664: Changelist is empty".to_string());
+        }
+        if self.change_index + 1 >= self.changes.len() {
+            return Err("E663: At end of changelist".to_string());
+        }
+
+        self.change_index = self
+            .change_index
+            .saturating_add(count)
+            .min(self.changes.len() - 1);
+        Ok(self.changes[self.change_index])
+    }
+
+    /// Moves the positions after `pos` once text ending at `end` has been
+    /// inserted there. Like vim, only insertions of whole lines move them.
+    pub fn adjust_insert(&mut self, pos: Vec2, end: Vec2) {
+        if end.y == pos.y {
+            return;
+        }
+
+        self.adjust(|mark| {
+            if mark.y > pos.y {
+                Vec2::new(mark.x, mark.y + end.y - pos.y)
+            } else if mark.y == pos.y && mark.x >= pos.x {
+                Vec2::new(mark.x - pos.x + end.x, end.y)
+            } else {
+                mark
+            }
+        });
+    }
+
+    /// Forgets the marks `a` to `z` on `lines`, which are being deleted,
+    /// like vim, where jumping to them then fails.
+    pub fn delete_lines(&mut self, lines: Range<usize>) {
+        self.marks
+            .retain(|name, pos| !name.is_ascii_lowercase() || !lines.contains(&pos.y));
+    }
+
+    /// Moves the positions after `pos` once the text up to `end` has been
+    /// deleted. Positions in the deleted text end up at `pos`.
+    pub fn adjust_delete(&mut self, pos: Vec2, end: Vec2) {
+        if end.y == pos.y {
+            return;
+        }
+
+        self.adjust(|mark| {
+            if mark.y > end.y {
+                Vec2::new(mark.x, mark.y - (end.y - pos.y))
+            } else if mark >= end {
+                Vec2::new(mark.x - end.x + pos.x, pos.y)
+            } else if mark > pos {
+                pos
+            } else {
+                mark
+            }
+        });
+    }
+
+    fn adjust(&mut self, f: impl Fn(Vec2) -> Vec2) {
+        for pos in self
+            .marks
+            .values_mut()
+            .chain(self.jumps.iter_mut())
+            .chain(self.changes.iter_mut())
+        {
+            *pos = f(*pos);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backtick_is_previous_context() {
+        let mut marks = Marks::default();
+
+        marks.push_jump(Vec2::new(2, 3));
+
+        assert_eq!(marks.get('`'), Some(Vec2::new(2, 3)));
+        assert_eq!(marks.get('\''), Some(Vec2::new(2, 3)));
+    }
+
+    #[test]
+    fn walk_jumps() {
+        let mut marks = Marks::default();
+        marks.push_jump(Vec2::new(0, 1));
+        marks.push_jump(Vec2::new(0, 5));
+        marks.push_jump(Vec2::new(3, 1));
+
+        assert_eq!(marks.older_jump(Vec2::new(0, 9), 1), Some(Vec2::new(3, 1)));
+        assert_eq!(marks.older_jump(Vec2::new(3, 1), 1), Some(Vec2::new(0, 5)));
+        assert_eq!(marks.older_jump(Vec2::new(0, 5), 1), None);
+        assert_eq!(marks.newer_jump(2), Some(Vec2::new(0, 9)));
+        assert_eq!(marks.newer_jump(1), None);
+    }
+
+    #[test]
+    fn walk_changes() {
+        let mut marks = Marks::default();
+        assert!(marks.older_change(1).is_err());
+
+        marks.push_change(Vec2::new(0, 1));
+        marks.push_change(Vec2::new(4, 1));
+        marks.push_change(Vec2::new(2, 7));
+
+        assert_eq!(marks.older_change(1), Ok(Vec2::new(2, 7)));
+        assert_eq!(marks.older_change(5), Ok(Vec2::new(4, 1)));
+        assert!(marks.older_change(1).is_err());
+        assert_eq!(marks.newer_change(1), Ok(Vec2::new(2, 7)));
+        assert!(marks.newer_change(1).is_err());
+    }
+
+    #[test]
+    fn adjust_for_inserted_and_deleted_lines() {
+        let mut marks = Marks::default();
+        marks.set('a', Vec2::new(1, 2));
+        marks.set('b', Vec2::new(3, 5));
+        marks.set('c', Vec2::new(2, 0));
+
+        marks.adjust_insert(Vec2::new(0, 2), Vec2::new(0, 4));
+        assert_eq!(marks.get('a'), Some(Vec2::new(1, 4)));
+        assert_eq!(marks.get('b'), Some(Vec2::new(3, 7)));
+        assert_eq!(marks.get('c'), Some(Vec2::new(2, 0)));
+
+        marks.adjust_delete(Vec2::new(0, 3), Vec2::new(0, 5));
+        assert_eq!(marks.get('a'), Some(Vec2::new(0, 3)));
+        assert_eq!(marks.get('b'), Some(Vec2::new(3, 5)));
+
+        marks.adjust_delete(Vec2::new(0, 1), Vec2::new(0, 2));
+        assert_eq!(marks.get('b'), Some(Vec2::new(3, 4)));
+        assert_eq!(marks.get('c'), Some(Vec2::new(2, 0)));
+    }
+
+    #[test]
+    fn forget_marks_on_deleted_lines() {
+        let mut marks = Marks::default();
+        marks.set('a', Vec2::new(1, 2));
+        marks.set('b', Vec2::new(0, 3));
+        marks.set('A', Vec2::new(0, 2));
+        marks.set('<', Vec2::new(0, 2));
+
+        marks.delete_lines(2..3);
+        assert_eq!(marks.get('a'), None);
+        assert_eq!(marks.get('b'), Some(Vec2::new(0, 3)));
+        assert_eq!(marks.get('A'), Some(Vec2::new(0, 2)));
+        assert_eq!(marks.get('<'), Some(Vec2::new(0, 2)));
+    }
+}
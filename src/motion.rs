@@ -0,0 +1,477 @@
+use termion::event::Key;
+
+use crate::buffer::{Buffer, WindowPosition};
+use crate::Vec2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MotionKind {
+    /// Covers the text between both positions, without the last character.
+    Exclusive,
+    /// Covers the text between both positions, including the last character.
+    Inclusive,
+    /// Covers every line between both positions.
+    Linewise,
+    /// Covers the columns between both positions on every line between
+    /// them, where the `x` of the range are display columns rather than
+    /// byte offsets, as they are the same on every line.
+    Blockwise,
+}
+
+/// Text covered by a motion, with `start` never after `end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Range {
+    pub start: Vec2,
+    pub end: Vec2,
+    pub kind: MotionKind,
+}
+
+impl Range {
+    pub fn new(from: Vec2, to: Vec2, kind: MotionKind) -> Self {
+        Self {
+            start: from.min(to),
+            end: from.max(to),
+            kind,
+        }
+    }
+
+    /// Turns the range into the `[start, end)` bounds of the characters it
+    /// covers, or `None` when it covers whole lines.
+    ///
+    /// Like vim, an exclusive range ending at the start of a line stops at
+    /// the end of the previous one, and becomes linewise when it also starts
+    /// before the first non-blank character of its line.
+    pub fn normalize(&self, buffer: &Buffer) -> Option<(Vec2, Vec2)> {
+        match self.kind {
+            MotionKind::Linewise | MotionKind::Blockwise => None,
+            MotionKind::Inclusive => {
+                let end_x = buffer.next_grapheme(self.end);
+                Some((self.start, Vec2::new(end_x, self.end.y)))
+            }
+            MotionKind::Exclusive => {
+                if self.end.x == 0 && self.end.y > self.start.y {
+                    if self.start.x <= buffer.first_non_blank(self.start.y) {
+                        return None;
+                    }
+
+                    let y = self.end.y - 1;
+                    return Some((self.start, Vec2::new(buffer.line_len(y), y)));
+                }
+
+                Some((self.start, self.end))
+            }
+        }
+    }
+
+    /// Where the range starts, the top left corner of a block.
+    pub fn first(&self, buffer: &Buffer) -> Vec2 {
+        match self.kind {
+            MotionKind::Blockwise => {
+                Vec2::new(buffer.col_to_byte(self.start.y, self.start.x), self.start.y)
+            }
+            _ => self.start,
+        }
+    }
+
+    /// Where the range ends, the bottom right corner of a block.
+    pub fn last(&self, buffer: &Buffer) -> Vec2 {
+        match self.kind {
+            MotionKind::Blockwise => {
+                Vec2::new(buffer.col_to_byte(self.end.y, self.end.x), self.end.y)
+            }
+            _ => self.end,
+        }
+    }
+
+    /// The `[start, end)` bounds of the columns of line `y` covered by the
+    /// range, or `None` when it doesn't reach the line. A block covers the
+    /// characters its edges fall on, so that wide characters and tabs are
+    /// taken whole.
+    pub fn columns(&self, buffer: &Buffer, y: usize) -> Option<(usize, usize)> {
+        if y < self.start.y || y > self.end.y {
+            return None;
+        }
+
+        let len = buffer.line_len(y);
+        let columns = match self.kind {
+            MotionKind::Linewise => (0, len),
+            MotionKind::Blockwise => {
+                let end = Vec2::new(buffer.col_to_byte(y, self.end.x), y);
+                (
+                    buffer.col_to_byte(y, self.start.x),
+                    buffer.next_grapheme(end),
+                )
+            }
+            MotionKind::Inclusive | MotionKind::Exclusive => {
+                let start = if y == self.start.y { self.start.x } else { 0 };
+                let end = match self.kind {
+                    _ if y < self.end.y => len,
+                    MotionKind::Inclusive => buffer.next_grapheme(self.end),
+                    _ => self.end.x,
+                };
+                (start, end)
+            }
+        };
+        Some(columns)
+    }
+}
+
+/// In-line search for a character, as done by `f`, `F`, `t` and `T`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CharSearch {
+    pub c: char,
+    pub forward: bool,
+    /// Stops right before the character instead of on it.
+    pub till: bool,
+}
+
+impl CharSearch {
+    pub fn reversed(&self) -> Self {
+        Self {
+            forward: !self.forward,
+            ..*self
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    WordForward {
+        bigword: bool,
+    },
+    WordBackward {
+        bigword: bool,
+    },
+    WordEnd {
+        bigword: bool,
+    },
+    WordEndBackward {
+        bigword: bool,
+    },
+    LineStart,
+    LineEnd,
+    FirstNonBlank,
+    FileStart,
+    FileEnd,
+    /// `repeat` is set for `;` and `,`, so that `t` doesn't get stuck right
+    /// before the character it found last time.
+    FindChar {
+        search: CharSearch,
+        repeat: bool,
+    },
+    /// Next match of the last search, or previous one when `reverse` is set.
+    SearchNext {
+        reverse: bool,
+    },
+    /// Jump to a mark with `` ` ``, or to its line with `'` when `linewise`.
+    Mark {
+        name: char,
+        linewise: bool,
+    },
+    /// Bracket matching the one under or after the cursor, moved to by `%`.
+    MatchPair,
+    /// Line of the window moved to by `H`, `M` and `L`.
+    WindowLine {
+        position: WindowPosition,
+    },
+}
+
+impl Motion {
+    pub fn from_key(key: Key) -> Option<Motion> {
+        match key {
+            Key::Char('h') | Key::Left => Some(Motion::Left),
+            Key::Char('l') | Key::Right => Some(Motion::Right),
+            Key::Char('k') | Key::Up => Some(Motion::Up),
+            Key::Char('j') | Key::Down => Some(Motion::Down),
+            Key::Char('w') => Some(Motion::WordForward { bigword: false }),
+            Key::Char('W') => Some(Motion::WordForward { bigword: true }),
+            Key::Char('b') => Some(Motion::WordBackward { bigword: false }),
+            Key::Char('B') => Some(Motion::WordBackward { bigword: true }),
+            Key::Char('e') => Some(Motion::WordEnd { bigword: false }),
+            Key::Char('E') => Some(Motion::WordEnd { bigword: true }),
+            Key::Char('0') | Key::Home => Some(Motion::LineStart),
+            Key::Char('$') | Key::End => Some(Motion::LineEnd),
+            Key::Char('^') => Some(Motion::FirstNonBlank),
+            Key::Char('G') => Some(Motion::FileEnd),
+            Key::Char('n') => Some(Motion::SearchNext { reverse: false }),
+            Key::Char('N') => Some(Motion::SearchNext { reverse: true }),
+            Key::Char('%') => Some(Motion::MatchPair),
+            Key::Char('H') => Some(Motion::WindowLine {
+                position: WindowPosition::Top,
+            }),
+            Key::Char('M') => Some(Motion::WindowLine {
+                position: WindowPosition::Middle,
+            }),
+            Key::Char('L') => Some(Motion::WindowLine {
+                position: WindowPosition::Bottom,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Motion typed after a `g`.
+    pub fn from_g_key(key: Key) -> Option<Motion> {
+        match key {
+            Key::Char('g') => Some(Motion::FileStart),
+            Key::Char('e') => Some(Motion::WordEndBackward { bigword: false }),
+            Key::Char('E') => Some(Motion::WordEndBackward { bigword: true }),
+            _ => None,
+        }
+    }
+
+    pub fn kind(&self) -> MotionKind {
+        match self {
+            Motion::Up
+            | Motion::Down
+            | Motion::FileStart
+            | Motion::FileEnd
+            | Motion::WindowLine { .. } => MotionKind::Linewise,
+            Motion::WordEnd { .. }
+            | Motion::WordEndBackward { .. }
+            | Motion::LineEnd
+            | Motion::MatchPair => MotionKind::Inclusive,
+            Motion::FindChar { search, .. } if search.forward => MotionKind::Inclusive,
+            Motion::FindChar { .. } => MotionKind::Exclusive,
+            Motion::Mark { linewise: true, .. } => MotionKind::Linewise,
+            Motion::Mark { .. } => MotionKind::Exclusive,
+            Motion::Left
+            | Motion::Right
+            | Motion::WordForward { .. }
+            | Motion::WordBackward { .. }
+            | Motion::LineStart
+            | Motion::FirstNonBlank
+            | Motion::SearchNext { .. } => MotionKind::Exclusive,
+        }
+    }
+
+    /// Whether the motion fails when it can't move the cursor, as opposed to
+    /// motions to a fixed place, like `0` or `G`, that are already there.
+    pub fn can_fail(&self) -> bool {
+        !matches!(
+            self,
+            Motion::LineStart
+                | Motion::LineEnd
+                | Motion::FirstNonBlank
+                | Motion::FileStart
+                | Motion::FileEnd
+                | Motion::Mark { .. }
+                | Motion::WindowLine { .. }
+        )
+    }
+
+    /// Whether the position the motion moves from is added to the jump list.
+    pub fn is_jump(&self) -> bool {
+        matches!(
+            self,
+            Motion::FileStart
+                | Motion::FileEnd
+                | Motion::SearchNext { .. }
+                | Motion::Mark { .. }
+                | Motion::MatchPair
+                | Motion::WindowLine { .. }
+        )
+    }
+
+    /// Moves the buffer cursor, `count` times for most motions or until it
+    /// stops moving. An operator is allowed to move the cursor one past the
+    /// end of the line, so that `dl` deletes the last character.
+    pub fn apply(&self, buffer: &mut Buffer, count: Option<usize>, operator_pending: bool) {
+        match self {
+            Motion::LineEnd => {
+                for _ in 1..count.unwrap_or(1) {
+                    let y = buffer.cursor().y;
+                    buffer.move_cursor_down();
+                    if buffer.cursor().y == y {
+                        break;
+                    }
+                }
+                buffer.move_cursor_last_character();
+            }
+            Motion::FileStart => buffer.goto_line(count.unwrap_or(1).saturating_sub(1)),
+            Motion::FileEnd => match count {
+                Some(count) => buffer.goto_line(count.saturating_sub(1)),
+                None => buffer.goto_line(usize::MAX),
+            },
+            Motion::FindChar { search, repeat } => {
+                buffer.find_char(*search, count.unwrap_or(1), *repeat);
+            }
+            Motion::WindowLine { position } => {
+                buffer.goto_line(buffer.window_line(*position, count.unwrap_or(1)));
+            }
+            Motion::MatchPair => self.apply_once(buffer, operator_pending),
+            Motion::SearchNext { reverse } => {
+                // searches wrap around, so once the matches repeat only the
+                // last time around them is taken
+                let count = count.unwrap_or(1);
+                let mut first = None;
+                let mut moves = 0;
+                while moves < count && buffer.search_next(*reverse) {
+                    moves += 1;
+                    match first {
+                        None => first = Some(buffer.cursor()),
+                        Some(first) if first == buffer.cursor() => {
+                            moves = count - (count - moves) % (moves - 1);
+                        }
+                        Some(_) => {}
+                    }
+                }
+            }
+            _ => {
+                for _ in 0..count.unwrap_or(1) {
+                    let before = buffer.cursor();
+                    self.apply_once(buffer, operator_pending);
+                    if buffer.cursor() == before {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    fn apply_once(&self, buffer: &mut Buffer, operator_pending: bool) {
+        match *self {
+            Motion::Left => buffer.move_cursor_left(),
+            Motion::Right => buffer.move_cursor_right(operator_pending),
+            Motion::Up => buffer.move_cursor_up(),
+            Motion::Down => buffer.move_cursor_down(),
+            Motion::WordForward { bigword } => buffer.word_forward(bigword),
+            Motion::WordBackward { bigword } => buffer.word_backward(bigword),
+            Motion::WordEnd { bigword } => buffer.word_end(bigword),
+            Motion::WordEndBackward { bigword } => buffer.word_end_backward(bigword),
+            Motion::LineStart => buffer.move_cursor_first_character(),
+            Motion::LineEnd => buffer.move_cursor_last_character(),
+            Motion::FirstNonBlank => buffer.move_cursor_first_non_blank(),
+            Motion::FileStart => buffer.goto_line(0),
+            Motion::FileEnd => buffer.goto_line(usize::MAX),
+            Motion::FindChar { search, repeat } => {
+                buffer.find_char(search, 1, repeat);
+            }
+            Motion::SearchNext { reverse } => {
+                buffer.search_next(reverse);
+            }
+            Motion::Mark { name, linewise } => {
+                buffer.goto_mark(name, linewise);
+            }
+            Motion::MatchPair => {
+                if let Some(pos) = buffer.match_pair() {
+                    buffer.set_cursor(pos);
+                }
+            }
+            Motion::WindowLine { position } => buffer.goto_line(buffer.window_line(position, 1)),
+        }
+    }
+
+    /// Text covered by moving `count` times from the cursor, which is left
+    /// untouched. Returns `None` when the motion fails or covers nothing.
+    ///
+    /// A motion that stays where it is still covers the line, or the
+    /// character, under the cursor, like `$` on the last character. Like
+    /// vim, so do `w` and `e` at the end of the buffer.
+    pub fn range(&self, buffer: &mut Buffer, count: Option<usize>) -> Option<Range> {
+        let from = buffer.cursor();
+        self.apply(buffer, count, true);
+        let to = buffer.cursor();
+        buffer.set_cursor(from);
+
+        if from != to {
+            return Some(Range::new(from, to, self.kind()));
+        }
+
+        let kind = match self {
+            Motion::WordForward { .. } | Motion::WordEnd { .. } => MotionKind::Inclusive,
+            _ if self.can_fail() => return None,
+            _ => self.kind(),
+        };
+        let covered = match kind {
+            MotionKind::Linewise => true,
+            MotionKind::Inclusive => buffer.char_at(from).is_some(),
+            _ => false,
+        };
+        covered.then(|| Range::new(from, to, kind))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(lines: &[&str], cursor: Vec2) -> Buffer {
+        let lines = lines.iter().map(|line| line.to_string()).collect();
+        let mut buffer = Buffer::from_lines(lines, Vec2::default(), Vec2::new(100, 100), true);
+        buffer.set_cursor(cursor);
+        buffer
+    }
+
+    #[test]
+    fn range_leaves_cursor() {
+        let mut buffer = buffer(&["one two"], Vec2::new(1, 0));
+
+        let range = Motion::WordForward { bigword: false }.range(&mut buffer, None);
+
+        assert_eq!(
+            range,
+            Some(Range::new(
+                Vec2::new(1, 0),
+                Vec2::new(4, 0),
+                MotionKind::Exclusive
+            ))
+        );
+        assert_eq!(buffer.cursor(), Vec2::new(1, 0));
+    }
+
+    #[test]
+    fn range_is_ordered() {
+        let mut buffer = buffer(&["one", "two"], Vec2::new(2, 1));
+
+        let range = Motion::Up.range(&mut buffer, None).unwrap();
+
+        assert_eq!(range.start, Vec2::new(2, 0));
+        assert_eq!(range.end, Vec2::new(2, 1));
+        assert_eq!(range.kind, MotionKind::Linewise);
+    }
+
+    #[test]
+    fn range_right_reaches_end_of_line() {
+        let mut buffer = buffer(&["one"], Vec2::new(2, 0));
+
+        let range = Motion::Right.range(&mut buffer, None).unwrap();
+
+        assert_eq!(range.end, Vec2::new(3, 0));
+    }
+
+    #[test]
+    fn range_none_when_not_moving() {
+        let mut buffer = buffer(&["one"], Vec2::new(0, 0));
+
+        assert_eq!(Motion::Up.range(&mut buffer, None), None);
+        assert_eq!(Motion::LineStart.range(&mut buffer, None), None);
+    }
+
+    #[test]
+    fn normalize_exclusive_at_start_of_line() {
+        let buffer = buffer(&["one two", "three"], Vec2::default());
+
+        let range = Range::new(Vec2::new(4, 0), Vec2::new(0, 1), MotionKind::Exclusive);
+        assert_eq!(
+            range.normalize(&buffer),
+            Some((Vec2::new(4, 0), Vec2::new(7, 0)))
+        );
+
+        let range = Range::new(Vec2::new(0, 0), Vec2::new(0, 1), MotionKind::Exclusive);
+        assert_eq!(range.normalize(&buffer), None);
+    }
+
+    #[test]
+    fn normalize_inclusive() {
+        let buffer = buffer(&["one two"], Vec2::default());
+
+        let range = Range::new(Vec2::new(0, 0), Vec2::new(2, 0), MotionKind::Inclusive);
+        assert_eq!(
+            range.normalize(&buffer),
+            Some((Vec2::new(0, 0), Vec2::new(3, 0)))
+        );
+    }
+}
@@ -0,0 +1,336 @@
+use termion::event::Key;
+
+use crate::buffer::CharClass;
+use crate::motion::{Motion, MotionKind, Range};
+use crate::register::{Register, RegisterKind};
+use crate::{Mode, State, Vec2};
+
+/// Columns added or removed by `>` and `<`.
+pub const SHIFT_WIDTH: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Delete,
+    Change,
+    Yank,
+    ShiftRight,
+    ShiftLeft,
+    Lowercase,
+    Uppercase,
+    ToggleCase,
+}
+
+impl Operator {
+    pub fn from_key(key: Key) -> Option<Operator> {
+        match key {
+            Key::Char('d') => Some(Operator::Delete),
+            Key::Char('c') => Some(Operator::Change),
+            Key::Char('y') => Some(Operator::Yank),
+            Key::Char('>') => Some(Operator::ShiftRight),
+            Key::Char('<') => Some(Operator::ShiftLeft),
+            _ => None,
+        }
+    }
+
+    /// Operator typed after a `g`.
+    pub fn from_g_key(key: Key) -> Option<Operator> {
+        match key {
+            Key::Char('u') => Some(Operator::Lowercase),
+            Key::Char('U') => Some(Operator::Uppercase),
+            Key::Char('~') => Some(Operator::ToggleCase),
+            _ => None,
+        }
+    }
+
+    /// Last key of the operator, which applies it to whole lines when typed
+    /// again, as in `dd` or `gUU`.
+    pub fn key(&self) -> char {
+        match self {
+            Operator::Delete => 'd',
+            Operator::Change => 'c',
+            Operator::Yank => 'y',
+            Operator::ShiftRight => '>',
+            Operator::ShiftLeft => '<',
+            Operator::Lowercase => 'u',
+            Operator::Uppercase => 'U',
+            Operator::ToggleCase => '~',
+        }
+    }
+}
+
+impl State {
+    /// Applies `operator` to the text covered by `motion`.
+    pub fn operate(&mut self, operator: Operator, motion: Motion, count: Option<usize>) {
+        let mut range = match motion.range(&mut self.buffer, count) {
+            Some(range) => range,
+            None => {
+                self.failed = true;
+                return;
+            }
+        };
+
+        if let Motion::WordForward { bigword } = motion {
+            // like vim, "cw" on a word only changes up to the end of the word
+            let on_blank = self
+                .buffer
+                .char_at(range.start)
+                .is_none_or(char::is_whitespace);
+            if operator == Operator::Change && !on_blank {
+                range.end = self.skip_blanks_backward(range.start, range.end);
+            }
+
+            // "w" stops on the last character when there is no next word,
+            // which still has to be covered
+            if !self.is_word_start(range.end, bigword) {
+                range.kind = MotionKind::Inclusive;
+            }
+        }
+
+        self.apply_operator(operator, range);
+    }
+
+    /// Applies `operator` to `count` lines from the cursor, as when it is
+    /// doubled.
+    pub fn operate_lines(&mut self, operator: Operator, count: Option<usize>) {
+        let from = self.buffer.cursor();
+        let last = from
+            .y
+            .saturating_add(count.unwrap_or(1) - 1)
+            .min(self.buffer.line_count() - 1);
+        let to = Vec2::new(from.x, last);
+
+        self.apply_operator(operator, Range::new(from, to, MotionKind::Linewise));
+    }
+
+    fn is_word_start(&self, pos: Vec2, bigword: bool) -> bool {
+        let class = self.buffer.class_at(pos, bigword);
+        if pos.x == 0 || class == CharClass::Blank {
+            return true;
+        }
+
+        let before = self.buffer.prev_grapheme(pos);
+        class != self.buffer.class_at(Vec2::new(before, pos.y), bigword)
+    }
+
+    fn skip_blanks_backward(&self, start: Vec2, mut end: Vec2) -> Vec2 {
+        while end > start {
+            if end.x == 0 {
+                end = Vec2::new(self.buffer.line_len(end.y - 1), end.y - 1);
+                continue;
+            }
+
+            let before = Vec2::new(self.buffer.prev_grapheme(end), end.y);
+            if !self.buffer.char_at(before).is_some_and(char::is_whitespace) {
+                break;
+            }
+            end = before;
+        }
+
+        end
+    }
+
+    pub fn apply_operator(&mut self, operator: Operator, range: Range) {
+        match operator {
+            Operator::ShiftRight | Operator::ShiftLeft => {
+                let right = operator == Operator::ShiftRight;
+                self.shift_lines(range.start.y, range.end.y, right, 1);
+            }
+            Operator::Lowercase => self.map_chars(range, |c, text| text.extend(c.to_lowercase())),
+            Operator::Uppercase => self.map_chars(range, |c, text| text.extend(c.to_uppercase())),
+            Operator::ToggleCase => self.map_chars(range, |c, text| {
+                if c.is_lowercase() {
+                    text.extend(c.to_uppercase())
+                } else {
+                    text.extend(c.to_lowercase())
+                }
+            }),
+            Operator::Yank | Operator::Delete | Operator::Change => {
+                if operator == Operator::Yank {
+                    let (first, last) = (range.first(&self.buffer), range.last(&self.buffer));
+                    self.buffer.marks_mut().set('[', first);
+                    self.buffer.marks_mut().set(']', last);
+                }
+
+                if range.kind == MotionKind::Blockwise {
+                    return self.cut_block(operator, range);
+                }
+
+                match range.normalize(&self.buffer) {
+                    Some((start, end)) => self.cut_chars(operator, start, end),
+                    None => self.cut_lines(operator, range.start.y, range.end.y),
+                }
+            }
+        }
+    }
+
+    /// Yanks, deletes or changes the text from `start` up to `end`.
+    fn cut_chars(&mut self, operator: Operator, start: Vec2, end: Vec2) {
+        let text = if operator == Operator::Yank {
+            self.buffer.text_between(start, end)
+        } else {
+            self.buffer.delete_text(start, end)
+        };
+        self.store(operator, Register::new(text, RegisterKind::Charwise));
+
+        if operator == Operator::Change {
+            self.buffer.set_insert_cursor(start);
+            self.mode = Mode::Insert;
+        } else {
+            self.buffer.set_cursor(start);
+        }
+    }
+
+    /// Yanks, deletes or changes the lines from `first` to `last`.
+    fn cut_lines(&mut self, operator: Operator, first: usize, last: usize) {
+        let count = last - first + 1;
+        let text = self.buffer.lines_text(first, count);
+        let register = Register::new(text, RegisterKind::Linewise);
+
+        match operator {
+            Operator::Yank => {
+                self.store(operator, register);
+                let x = self.buffer.cursor().x;
+                self.buffer.set_cursor(Vec2::new(x, first));
+            }
+            Operator::Change => {
+                let end = Vec2::new(self.buffer.line_len(last), last);
+                self.buffer.delete_text(Vec2::new(0, first), end);
+                self.store(operator, register);
+
+                self.buffer.set_insert_cursor(Vec2::new(0, first));
+                self.mode = Mode::Insert;
+            }
+            _ => {
+                self.buffer.delete_lines(first, count);
+                self.store(operator, register);
+            }
+        }
+    }
+
+    /// Yanks, deletes or changes a block, where a change inserts the typed
+    /// text on every line of the block.
+    fn cut_block(&mut self, operator: Operator, range: Range) {
+        let segments = self.segments(range);
+        let text = segments
+            .iter()
+            .map(|(start, end)| self.buffer.text_between(*start, *end))
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.store(operator, Register::new(text, RegisterKind::Blockwise));
+
+        if operator != Operator::Yank {
+            for (start, end) in segments.into_iter().rev() {
+                if start != end {
+                    self.buffer.delete_text(start, end);
+                }
+            }
+        }
+
+        if operator == Operator::Change {
+            self.start_block_insert(range.start.x, range.start.y, range.end.y, false);
+        } else {
+            self.buffer.set_cursor(range.first(&self.buffer));
+        }
+    }
+
+    fn store(&mut self, operator: Operator, register: Register) {
+        if operator == Operator::Yank {
+            self.registers.yank(self.register, register);
+        } else {
+            self.registers.delete(self.register, register);
+        }
+    }
+
+    /// Bounds of the text covered by `range` on each line it spans, or a
+    /// single pair for characterwise and linewise ranges.
+    fn segments(&self, range: Range) -> Vec<(Vec2, Vec2)> {
+        if range.kind == MotionKind::Blockwise {
+            return (range.start.y..=range.end.y)
+                .filter_map(|y| {
+                    let (start, end) = range.columns(&self.buffer, y)?;
+                    Some((Vec2::new(start, y), Vec2::new(end, y)))
+                })
+                .collect();
+        }
+
+        match range.normalize(&self.buffer) {
+            Some(bounds) => vec![bounds],
+            None => {
+                let last = range.end.y;
+                vec![(
+                    Vec2::new(0, range.start.y),
+                    Vec2::new(self.buffer.line_len(last), last),
+                )]
+            }
+        }
+    }
+
+    /// Replaces every character covered by `range`, except line breaks,
+    /// with what `f` adds to the text for it, which may be several
+    /// characters, as when `ß` is uppercased to `SS`.
+    pub fn map_chars(&mut self, range: Range, f: impl Fn(char, &mut String)) {
+        for (start, end) in self.segments(range) {
+            let text = self.buffer.text_between(start, end);
+            let mut mapped = String::with_capacity(text.len());
+            for c in text.chars() {
+                if c == '\n' {
+                    mapped.push(c);
+                } else {
+                    f(c, &mut mapped);
+                }
+            }
+
+            if mapped != text {
+                self.buffer.delete_text(start, end);
+                self.buffer.insert_text(start, &mapped);
+            }
+        }
+
+        let start = match range.kind {
+            MotionKind::Linewise => Vec2::new(self.buffer.cursor().x, range.start.y),
+            _ => range.first(&self.buffer),
+        };
+        self.buffer.set_cursor(start);
+    }
+
+    /// Indents or unindents the lines from `first` to `last` by `times`
+    /// shift widths, leaving the cursor on the first non-blank of `first`.
+    pub fn shift_lines(&mut self, first: usize, last: usize, right: bool, times: usize) {
+        if !self.check_repeated_len(SHIFT_WIDTH, times) {
+            return;
+        }
+        let width = SHIFT_WIDTH * times;
+
+        for y in first..=last {
+            let line = self.buffer.lines_text(y, 1);
+            if right {
+                if !line.is_empty() {
+                    self.buffer.insert_text(Vec2::new(0, y), &" ".repeat(width));
+                }
+                continue;
+            }
+
+            let mut removed = 0;
+            let mut columns = 0;
+            for c in line.chars() {
+                if columns >= width {
+                    break;
+                }
+                columns += match c {
+                    ' ' => 1,
+                    '\t' => SHIFT_WIDTH,
+                    _ => break,
+                };
+                removed += 1;
+            }
+
+            if removed > 0 {
+                self.buffer
+                    .delete_text(Vec2::new(0, y), Vec2::new(removed, y));
+            }
+        }
+
+        let x = self.buffer.first_non_blank(first);
+        self.buffer.set_cursor(Vec2::new(x, first));
+    }
+}
@@ -0,0 +1,225 @@
+use std::ops::RangeInclusive;
+
+use crate::buffer::MAX_TABSTOP;
+use crate::encoding::Encoding;
+use crate::fileformat::FileFormat;
+use crate::State;
+
+/// An option set with `:set`, like `scrolloff`.
+struct OptionSpec {
+    name: &'static str,
+    /// Abbreviation accepted too, like `so`.
+    short: &'static str,
+    access: Access,
+}
+
+/// How an option is read and set, depending on the kind of its value.
+enum Access {
+    /// Set with `:set name` and reset with `:set noname`.
+    Bool {
+        get: fn(&State) -> bool,
+        set: fn(&mut State, bool),
+    },
+    /// Only takes `values`, like vim, which tells that a value must be
+    /// positive when it is below them.
+    Number {
+        get: fn(&State) -> usize,
+        set: fn(&mut State, usize),
+        values: RangeInclusive<usize>,
+    },
+    /// Returns `false` from `set` for a value the option doesn't take.
+    Text {
+        get: fn(&State) -> String,
+        set: fn(&mut State, &str) -> bool,
+    },
+}
+
+const OPTIONS: &[OptionSpec] = &[
+    OptionSpec {
+        name: "endofline",
+        short: "eol",
+        access: Access::Bool {
+            get: |state| state.buffer.eol(),
+            set: |state, eol| state.buffer.set_eol(eol),
+        },
+    },
+    OptionSpec {
+        name: "fileencoding",
+        short: "fenc",
+        access: Access::Text {
+            get: |state| state.buffer.encoding().name().to_string(),
+            set: |state, name| match Encoding::from_name(name) {
+                Some(encoding) => {
+                    state.buffer.set_encoding(encoding);
+                    true
+                }
+                None => false,
+            },
+        },
+    },
+    OptionSpec {
+        name: "fileformat",
+        short: "ff",
+        access: Access::Text {
+            get: |state| state.buffer.file_format().name().to_string(),
+            set: |state, name| match FileFormat::from_name(name) {
+                Some(format) => {
+                    state.buffer.set_file_format(format);
+                    true
+                }
+                None => false,
+            },
+        },
+    },
+    OptionSpec {
+        name: "fixendofline",
+        short: "fixeol",
+        access: Access::Bool {
+            get: |state| state.buffer.fixeol(),
+            set: |state, fixeol| state.buffer.set_fixeol(fixeol),
+        },
+    },
+    OptionSpec {
+        name: "scroll",
+        short: "scr",
+        access: Access::Number {
+            get: |state| state.buffer.scroll(),
+            set: |state, lines| state.buffer.set_scroll(lines),
+            values: 0..=usize::MAX,
+        },
+    },
+    OptionSpec {
+        name: "scrolloff",
+        short: "so",
+        access: Access::Number {
+            get: |state| state.buffer.scrolloff(),
+            set: |state, lines| state.buffer.set_scrolloff(lines),
+            values: 0..=usize::MAX,
+        },
+    },
+    OptionSpec {
+        name: "tabstop",
+        short: "ts",
+        access: Access::Number {
+            get: |state| state.buffer.tabstop(),
+            set: |state, columns| state.buffer.set_tabstop(columns),
+            values: 1..=MAX_TABSTOP,
+        },
+    },
+];
+
+fn lookup(name: &str) -> Option<&'static OptionSpec> {
+    OPTIONS
+        .iter()
+        .find(|option| option.name == name || option.short == name)
+}
+
+/// What an argument of `:set` does to its option.
+#[derive(Debug, PartialEq)]
+enum Action<'a> {
+    /// Shows the value, or sets a boolean option, as in `so` or `eol`.
+    Name,
+    /// Only shows the value, as in `eol?`.
+    Show,
+    /// Sets the value given with `=` or `:`, as in `so=5`.
+    Set(&'a str),
+    /// Resets a boolean option, as in `noeol`.
+    Reset,
+    /// Inverts a boolean option, as in `inveol` or `eol!`.
+    Invert,
+}
+
+/// Splits an argument of `:set` into the option name and what to do with
+/// it. Names starting with `no` or `inv` are only taken as such when there
+/// is no option of that name.
+fn parse(arg: &str) -> (&str, Action<'_>) {
+    if let Some(i) = arg.find(['=', ':']) {
+        return (&arg[..i], Action::Set(&arg[i + 1..]));
+    }
+    if let Some(name) = arg.strip_suffix('?') {
+        return (name, Action::Show);
+    }
+    if let Some(name) = arg.strip_suffix('!') {
+        return (name, Action::Invert);
+    }
+
+    match (arg.strip_prefix("no"), arg.strip_prefix("inv")) {
+        _ if lookup(arg).is_some() => (arg, Action::Name),
+        (Some(name), _) => (name, Action::Reset),
+        (_, Some(name)) => (name, Action::Invert),
+        _ => (arg, Action::Name),
+    }
+}
+
+impl OptionSpec {
+    fn show(&self, state: &State) -> String {
+        match self.access {
+            Access::Bool { get, .. } if get(state) => self.name.to_string(),
+            Access::Bool { .. } => format!("no{}", self.name),
+            Access::Number { get, .. } => format!("{}={}", self.name, get(state)),
+            Access::Text { get, .. } => format!("{}={}", self.name, get(state)),
+        }
+    }
+
+    /// Does `action` to the option, returning what to show, if anything.
+    fn apply(
+        &self,
+        state: &mut State,
+        arg: &str,
+        action: Action<'_>,
+    ) -> Result<Option<String>, String> {
+        let invalid = || format!("E474: Invalid argument: {}", arg);
+
+        match (&self.access, action) {
+            (_, Action::Show)
+            | (Access::Number { .. }, Action::Name)
+            | (Access::Text { .. }, Action::Name) => return Ok(Some(self.show(state))),
+            (Access::Bool { set, .. }, Action::Name) => set(state, true),
+            (Access::Bool { set, .. }, Action::Reset) => set(state, false),
+            (Access::Bool { get, set }, Action::Invert) => set(state, !get(state)),
+            (Access::Number { set, values, .. }, Action::Set(value)) => {
+                let value = value
+                    .parse()
+                    .map_err(|_| format!("E521: Number required after =: {}", arg))?;
+                if value < *values.start() {
+                    return Err(format!("E487: Argument must be positive: {}", arg));
+                } else if value > *values.end() {
+                    return Err(invalid());
+                }
+                set(state, value);
+            }
+            (Access::Text { set, .. }, Action::Set(value)) => {
+                if !set(state, value) {
+                    return Err(invalid());
+                }
+            }
+            _ => return Err(invalid()),
+        }
+        Ok(None)
+    }
+}
+
+impl State {
+    /// Sets the options given to `:set`, and shows the ones given without a
+    /// value, or every option without arguments, in the message area.
+    pub fn set_options(&mut self, args: &str) -> Result<(), String> {
+        let mut shown = Vec::new();
+
+        if args.is_empty() {
+            for option in OPTIONS {
+                shown.push(option.show(self));
+            }
+        }
+
+        for arg in args.split_whitespace() {
+            let (name, action) = parse(arg);
+            let option = lookup(name).ok_or_else(|| format!("E518: Unknown option: {}", arg))?;
+            shown.extend(option.apply(self, arg, action)?);
+        }
+
+        if !shown.is_empty() {
+            self.message = Some(shown.join(" "));
+        }
+        Ok(())
+    }
+}
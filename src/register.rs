@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterKind {
+    Charwise,
+    Linewise,
+    /// A block from Visual mode, with one line of text per line of the
+    /// block.
+    Blockwise,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Register {
+    pub text: String,
+    pub kind: RegisterKind,
+}
+
+impl Register {
+    pub fn new(text: String, kind: RegisterKind) -> Self {
+        Self { text, kind }
+    }
+
+    fn append(&mut self, other: Register) {
+        if self.kind == RegisterKind::Linewise || other.kind == RegisterKind::Linewise {
+            self.text.push('\n');
+            self.kind = RegisterKind::Linewise;
+        }
+
+        self.text.push_str(&other.text);
+    }
+}
+
+/// Registers shared by every buffer, following vim's naming:
+///
+/// - `"` the unnamed register, holding the last yanked or deleted text
+/// - `0` the last yanked text
+/// - `1` to `9` a ring of the last big (linewise or multi-line) deletes
+/// - `-` the last small delete
+/// - `a` to `z` named registers, appended to when written as `A` to `Z`
+/// - `_` the black hole register, which discards whatever is written to it
+#[derive(Debug, Default)]
+pub struct Registers {
+    unnamed: Option<Register>,
+    named: HashMap<char, Register>,
+    numbered: [Option<Register>; 10],
+    small_delete: Option<Register>,
+}
+
+pub fn is_valid(name: char) -> bool {
+    name.is_ascii_alphanumeric() || matches!(name, '"' | '-' | '_')
+}
+
+impl Registers {
+    pub fn get(&self, name: Option<char>) -> Option<&Register> {
+        match name {
+            None | Some('"') => self.unnamed.as_ref(),
+            Some('-') => self.small_delete.as_ref(),
+            Some(c) if c.is_ascii_digit() => self.numbered[c as usize - '0' as usize].as_ref(),
+            Some(c) if c.is_ascii_alphabetic() => self.named.get(&c.to_ascii_lowercase()),
+            Some(_) => None,
+        }
+    }
+
+    /// Stores yanked text, which also goes to register `0` unless another
+    /// register was given.
+    pub fn yank(&mut self, name: Option<char>, register: Register) {
+        match name {
+            None | Some('"') => {
+                self.numbered[0] = Some(register.clone());
+                self.unnamed = Some(register);
+            }
+            Some(c) => self.write(c, register),
+        }
+    }
+
+    /// Stores deleted text, which also goes to either the small delete
+    /// register or the numbered ring unless another register was given.
+    pub fn delete(&mut self, name: Option<char>, register: Register) {
+        match name {
+            None | Some('"') => {
+                if register.kind == RegisterKind::Linewise || register.text.contains('\n') {
+                    self.numbered[1..].rotate_right(1);
+                    self.numbered[1] = Some(register.clone());
+                } else {
+                    self.small_delete = Some(register.clone());
+                }
+                self.unnamed = Some(register);
+            }
+            Some(c) => self.write(c, register),
+        }
+    }
+
+    fn write(&mut self, name: char, register: Register) {
+        if name != '_' {
+            self.store(name, register);
+            self.unnamed = self.get(Some(name)).cloned();
+        }
+    }
+
+    /// Stores text in a register without making the unnamed register point
+    /// to it, as done when recording a macro.
+    pub fn store(&mut self, name: char, register: Register) {
+        match name {
+            '_' => {}
+            '"' => self.unnamed = Some(register),
+            '-' => self.small_delete = Some(register),
+            c if c.is_ascii_digit() => {
+                self.numbered[c as usize - '0' as usize] = Some(register);
+            }
+            c if c.is_ascii_uppercase() => {
+                let lowercase = c.to_ascii_lowercase();
+                match self.named.get_mut(&lowercase) {
+                    Some(existing) => existing.append(register),
+                    None => {
+                        self.named.insert(lowercase, register);
+                    }
+                }
+            }
+            c => {
+                self.named.insert(c, register);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn charwise(text: &str) -> Register {
+        Register::new(text.to_string(), RegisterKind::Charwise)
+    }
+
+    fn linewise(text: &str) -> Register {
+        Register::new(text.to_string(), RegisterKind::Linewise)
+    }
+
+    #[test]
+    fn yank_unnamed() {
+        let mut registers = Registers::default();
+
+        registers.yank(None, charwise("word"));
+
+        assert_eq!(registers.get(None), Some(&charwise("word")));
+        assert_eq!(registers.get(Some('0')), Some(&charwise("word")));
+    }
+
+    #[test]
+    fn yank_named() {
+        let mut registers = Registers::default();
+
+        registers.yank(Some('a'), charwise("word"));
+
+        assert_eq!(registers.get(Some('a')), Some(&charwise("word")));
+        assert_eq!(registers.get(None), Some(&charwise("word")));
+        assert_eq!(registers.get(Some('0')), None);
+    }
+
+    #[test]
+    fn uppercase_appends() {
+        let mut registers = Registers::default();
+
+        registers.yank(Some('a'), charwise("one"));
+        registers.yank(Some('A'), charwise("two"));
+        assert_eq!(registers.get(Some('a')), Some(&charwise("onetwo")));
+
+        registers.yank(Some('A'), linewise("three"));
+        assert_eq!(registers.get(Some('a')), Some(&linewise("onetwo\nthree")));
+        assert_eq!(registers.get(Some('A')), Some(&linewise("onetwo\nthree")));
+    }
+
+    #[test]
+    fn delete_small() {
+        let mut registers = Registers::default();
+
+        registers.delete(None, charwise("x"));
+
+        assert_eq!(registers.get(Some('-')), Some(&charwise("x")));
+        assert_eq!(registers.get(Some('1')), None);
+        assert_eq!(registers.get(None), Some(&charwise("x")));
+    }
+
+    #[test]
+    fn delete_shifts_numbered() {
+        let mut registers = Registers::default();
+
+        for i in 1..=10 {
+            registers.delete(None, linewise(&format!("line{}", i)));
+        }
+
+        assert_eq!(registers.get(Some('1')), Some(&linewise("line10")));
+        assert_eq!(registers.get(Some('2')), Some(&linewise("line9")));
+        assert_eq!(registers.get(Some('9')), Some(&linewise("line2")));
+        assert_eq!(registers.get(Some('-')), None);
+    }
+
+    #[test]
+    fn black_hole() {
+        let mut registers = Registers::default();
+
+        registers.yank(None, charwise("kept"));
+        registers.delete(Some('_'), charwise("gone"));
+
+        assert_eq!(registers.get(None), Some(&charwise("kept")));
+        assert_eq!(registers.get(Some('_')), None);
+    }
+
+    #[test]
+    fn store_keeps_unnamed() {
+        let mut registers = Registers::default();
+
+        registers.yank(None, charwise("kept"));
+        registers.store('q', charwise("macro"));
+        registers.store('Q', charwise("!"));
+
+        assert_eq!(registers.get(Some('q')), Some(&charwise("macro!")));
+        assert_eq!(registers.get(None), Some(&charwise("kept")));
+    }
+}
@@ -0,0 +1,80 @@
+use std::mem;
+
+use termion::event::{Event, Key};
+
+use crate::State;
+
+/// Keys of the last command that changed the buffer, including the text
+/// typed in the Insert mode session it started, replayed by `.`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    keys: Vec<Key>,
+    count: Option<usize>,
+}
+
+/// Keys of the command being typed, kept as the last change once the
+/// command completes if it changed the buffer.
+#[derive(Debug, Default)]
+pub struct Recorder {
+    keys: Vec<Key>,
+    count: Option<usize>,
+    last_change: Option<Change>,
+    replaying: bool,
+}
+
+impl State {
+    /// Records a key of the current command, except for its count, which
+    /// is recorded on its own with `record_count`.
+    pub fn record_key(&mut self, key: Key) {
+        if !self.recorder.replaying {
+            self.recorder.keys.push(key);
+        }
+    }
+
+    pub fn record_count(&mut self, count: Option<usize>) {
+        if !self.recorder.replaying {
+            self.recorder.count = count;
+        }
+    }
+
+    /// Ends the current command, which becomes the last change when it
+    /// `changed` the buffer.
+    pub fn finish_recording(&mut self, changed: bool) {
+        let keys = mem::take(&mut self.recorder.keys);
+        if changed && !keys.is_empty() && !self.recorder.replaying {
+            self.recorder.last_change = Some(Change {
+                keys,
+                count: self.recorder.count,
+            });
+        }
+    }
+
+    /// Drops the keys of the current command, so that it can't be repeated.
+    pub fn discard_recording(&mut self) {
+        self.recorder.keys.clear();
+    }
+
+    /// Replays the last change, where `count` replaces the count it was
+    /// typed with.
+    pub fn repeat_change(&mut self, count: Option<usize>) {
+        let change = match self.recorder.last_change.clone() {
+            Some(change) => change,
+            None => return,
+        };
+
+        self.count = None;
+        self.recorder.replaying = true;
+
+        if let Some(count) = count.or(change.count) {
+            for c in count.to_string().chars() {
+                self.handle(Event::Key(Key::Char(c)));
+            }
+        }
+        for key in change.keys {
+            self.handle(Event::Key(key));
+        }
+
+        self.recorder.replaying = false;
+        self.discard_recording();
+    }
+}
@@ -0,0 +1,56 @@
+use regex::Regex;
+
+use crate::buffer::CharClass;
+
+/// Pattern searched with `/`, `?`, `*` or `#`, repeated by `n` and `N`.
+#[derive(Debug, Clone)]
+pub struct Search {
+    pub regex: Regex,
+    pub forward: bool,
+}
+
+impl Search {
+    pub fn new(pattern: &str, forward: bool) -> Result<Self, regex::Error> {
+        Ok(Self {
+            regex: Regex::new(pattern)?,
+            forward,
+        })
+    }
+
+    /// Search for `word` as a whole word, as done by `*` and `#`. Keywords
+    /// only match when they aren't part of a longer keyword.
+    pub fn word(word: &str, forward: bool) -> Self {
+        let escaped = regex::escape(word);
+        let pattern = if word
+            .chars()
+            .all(|c| CharClass::of(c, false) == CharClass::Word)
+        {
+            format!(r"\b{}\b", escaped)
+        } else {
+            escaped
+        };
+
+        Self::new(&pattern, forward).expect("escaped pattern is valid")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn word_matches_whole_keyword() {
+        let search = Search::word("foo", true);
+
+        assert!(search.regex.is_match("a foo b"));
+        assert!(!search.regex.is_match("foobar"));
+    }
+
+    #[test]
+    fn word_escapes_punctuation() {
+        let search = Search::word("+=", true);
+
+        assert!(search.regex.is_match("a+=b"));
+        assert!(!search.regex.is_match("a+b"));
+    }
+}
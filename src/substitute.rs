@@ -0,0 +1,417 @@
+use std::collections::VecDeque;
+use std::ops::Range;
+
+use regex::{Regex, RegexBuilder};
+
+use crate::buffer::Buffer;
+use crate::ex::LineRange;
+use crate::Vec2;
+
+/// A `:s/pattern/replacement/flags` command, replacing matches one at a
+/// time so that each one can be confirmed with the `c` flag.
+#[derive(Debug)]
+pub struct Substitute {
+    pub regex: Regex,
+    /// Replacement as typed, shown when asking for confirmation.
+    typed_replacement: String,
+    /// Replacement in the syntax of `Captures::expand`.
+    replacement: String,
+    global: bool,
+    pub confirm: bool,
+    count_only: bool,
+    /// Next line to look for matches in.
+    next_line: usize,
+    /// Last line of the range, which moves down as replacements add lines.
+    end_line: usize,
+    /// Matches left in the current line, as ranges of its text before any
+    /// substitution, along with their replacement.
+    matches: VecDeque<(Range<usize>, String)>,
+    /// Where the previous match of the current line ended, in the original
+    /// text of the line and in the buffer.
+    shift: (usize, Vec2),
+    line_changed: bool,
+    substitutions: usize,
+    lines: usize,
+    /// Line of the last substitution, where the cursor ends up.
+    pub last_line: Option<usize>,
+}
+
+impl Substitute {
+    /// Parses the arguments following `:s`. An empty pattern reuses the
+    /// last search.
+    pub fn parse(
+        args: &str,
+        range: LineRange,
+        last_search: Option<&Regex>,
+    ) -> Result<Self, String> {
+        let delimiter = match args.chars().next() {
+            Some(c)
+                if !c.is_alphanumeric() && !c.is_whitespace() && !matches!(c, '\\' | '"' | '|') =>
+            {
+                c
+            }
+            _ => return Err("E146: Regular expressions can't be delimited by letters".to_string()),
+        };
+
+        let (pattern, rest) = split_at_delimiter(&args[delimiter.len_utf8()..], delimiter);
+        let (typed_replacement, flags) = split_at_delimiter(rest.unwrap_or(""), delimiter);
+
+        let mut global = false;
+        let mut confirm = false;
+        let mut count_only = false;
+        let mut ignore_case = false;
+        for flag in flags.unwrap_or("").chars() {
+            match flag {
+                'g' => global = true,
+                'c' => confirm = true,
+                'n' => count_only = true,
+                'i' => ignore_case = true,
+                'I' => ignore_case = false,
+                _ => {
+                    return Err(format!(
+                        "E488: Trailing characters: {}",
+                        flags.unwrap_or("")
+                    ))
+                }
+            }
+        }
+
+        let pattern = match (pattern.is_empty(), last_search) {
+            (false, _) => pattern,
+            (true, Some(regex)) => regex.as_str().to_string(),
+            (true, None) => return Err("E35: No previous regular expression".to_string()),
+        };
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(ignore_case)
+            .build()
+            .map_err(|_| format!("E383: Invalid search string: {}", pattern))?;
+
+        Ok(Self {
+            regex,
+            replacement: expand_template(&typed_replacement),
+            typed_replacement,
+            global,
+            confirm,
+            count_only,
+            next_line: range.start,
+            end_line: range.end,
+            matches: VecDeque::new(),
+            shift: (0, Vec2::default()),
+            line_changed: false,
+            substitutions: 0,
+            lines: 0,
+            last_line: None,
+        })
+    }
+
+    /// Position of the next match in the buffer, if any is left.
+    pub fn next(&mut self, buffer: &Buffer) -> Option<Vec2> {
+        loop {
+            if let Some((range, _)) = self.matches.front() {
+                let (end, pos) = self.shift;
+                return Some(Vec2::new(pos.x + range.start - end, pos.y));
+            }
+
+            if self.next_line > self.end_line || self.next_line >= buffer.line_count() {
+                return None;
+            }
+
+            let y = self.next_line;
+            let line = buffer.lines_text(y, 1);
+            let matches = self.regex.captures_iter(&line).map(|captures| {
+                let mut replacement = String::new();
+                captures.expand(&self.replacement, &mut replacement);
+                (captures.get(0).unwrap().range(), replacement)
+            });
+
+            self.matches = if self.global {
+                matches.collect()
+            } else {
+                matches.take(1).collect()
+            };
+            self.shift = (0, Vec2::new(0, y));
+            self.line_changed = false;
+            self.next_line = y + 1;
+        }
+    }
+
+    /// Replaces the match returned by `next`.
+    pub fn accept(&mut self, buffer: &mut Buffer) {
+        let pos = match self.next(buffer) {
+            Some(pos) => pos,
+            None => return,
+        };
+        let (range, replacement) = self.matches.pop_front().unwrap();
+
+        if self.count_only {
+            self.shift = (range.end, Vec2::new(pos.x + range.len(), pos.y));
+        } else {
+            if !range.is_empty() {
+                buffer.delete_text(pos, Vec2::new(pos.x + range.len(), pos.y));
+            }
+            let end = if replacement.is_empty() {
+                pos
+            } else {
+                buffer.insert_text(pos, &replacement)
+            };
+
+            let added_lines = end.y - pos.y;
+            self.next_line += added_lines;
+            self.end_line += added_lines;
+            self.shift = (range.end, end);
+            self.last_line = Some(end.y);
+        }
+
+        self.substitutions += 1;
+        if !self.line_changed {
+            self.line_changed = true;
+            self.lines += 1;
+        }
+    }
+
+    /// Leaves the match returned by `next` as it is.
+    pub fn skip(&mut self, buffer: &Buffer) {
+        if let Some(pos) = self.next(buffer) {
+            let (range, _) = self.matches.pop_front().unwrap();
+            self.shift = (range.end, Vec2::new(pos.x + range.len(), pos.y));
+        }
+    }
+
+    /// Replaces every match left.
+    pub fn run(&mut self, buffer: &mut Buffer) {
+        while self.next(buffer).is_some() {
+            self.accept(buffer);
+        }
+    }
+
+    pub fn prompt(&self) -> String {
+        format!("replace with {} (y/n/a/q/l)?", self.typed_replacement)
+    }
+
+    /// Reports how many substitutions were made, or matches found with the
+    /// `n` flag.
+    pub fn message(&self) -> String {
+        if self.substitutions == 0 && !self.confirm {
+            return format!("E486: Pattern not found: {}", self.regex.as_str());
+        }
+
+        let (singular, plural) = if self.count_only {
+            ("match", "matches")
+        } else {
+            ("substitution", "substitutions")
+        };
+        let noun = if self.substitutions == 1 {
+            singular
+        } else {
+            plural
+        };
+        let lines = if self.lines == 1 { "line" } else { "lines" };
+
+        format!(
+            "{} {} on {} {}",
+            self.substitutions, noun, self.lines, lines
+        )
+    }
+}
+
+/// Splits `input` at the first `delimiter` not escaped by a backslash,
+/// unescaping the delimiter in the first part.
+fn split_at_delimiter(input: &str, delimiter: char) -> (String, Option<&str>) {
+    let mut part = String::new();
+    let mut chars = input.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some((_, c)) if c == delimiter => part.push(c),
+                Some((_, c)) => {
+                    part.push('\\');
+                    part.push(c);
+                }
+                None => part.push('\\'),
+            },
+            c if c == delimiter => return (part, Some(&input[i + c.len_utf8()..])),
+            c => part.push(c),
+        }
+    }
+
+    (part, None)
+}
+
+/// Turns a vim replacement, where `&` and `\0` to `\9` refer to the match
+/// and its groups and `\r` breaks the line, into a `Captures::expand`
+/// template.
+fn expand_template(replacement: &str) -> String {
+    let mut template = String::new();
+    let mut chars = replacement.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => template.push_str("${0}"),
+            '$' => template.push_str("$$"),
+            '\\' => match chars.next() {
+                Some(d) if d.is_ascii_digit() => template.push_str(&format!("${{{}}}", d)),
+                Some('r') | Some('n') => template.push('\n'),
+                Some('t') => template.push('\t'),
+                Some('$') => template.push_str("$$"),
+                Some(c) => template.push(c),
+                None => template.push('\\'),
+            },
+            c => template.push(c),
+        }
+    }
+
+    template
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(lines: &[&str]) -> Buffer {
+        let lines = lines.iter().map(|line| line.to_string()).collect();
+        Buffer::from_lines(lines, Vec2::default(), Vec2::new(100, 100), true)
+    }
+
+    fn substitute(buffer: &mut Buffer, args: &str, range: LineRange) -> Substitute {
+        let mut substitute = Substitute::parse(args, range, None).unwrap();
+        substitute.run(buffer);
+        substitute
+    }
+
+    fn all(buffer: &Buffer) -> LineRange {
+        LineRange {
+            start: 0,
+            end: buffer.line_count() - 1,
+        }
+    }
+
+    #[test]
+    fn first_match_of_each_line() {
+        let mut buffer = buffer(&["a a", "b", "a a"]);
+
+        let range = all(&buffer);
+
+        let substitute = substitute(&mut buffer, "/a/x/", range);
+
+        assert_eq!(buffer.lines_text(0, 3), "x a\nb\nx a");
+        assert_eq!(substitute.message(), "2 substitutions on 2 lines");
+        assert_eq!(substitute.last_line, Some(2));
+    }
+
+    #[test]
+    fn global() {
+        let mut buffer = buffer(&["a a", "b", "a a"]);
+
+        let substitute = substitute(&mut buffer, "/a/xy/g", LineRange::line(0));
+
+        assert_eq!(buffer.lines_text(0, 3), "xy xy\nb\na a");
+        assert_eq!(substitute.message(), "2 substitutions on 1 line");
+    }
+
+    #[test]
+    fn ignore_case() {
+        let mut buffer = buffer(&["A a"]);
+
+        let range = all(&buffer);
+
+        substitute(&mut buffer, "/a/x/gi", range);
+
+        assert_eq!(buffer.lines_text(0, 1), "x x");
+    }
+
+    #[test]
+    fn capture_groups() {
+        let mut buffer = buffer(&["key = value"]);
+
+        let range = all(&buffer);
+
+        substitute(&mut buffer, r"/(\w+) = (\w+)/\2 = \1 (&)/", range);
+
+        assert_eq!(buffer.lines_text(0, 1), "value = key (key = value)");
+    }
+
+    #[test]
+    fn count_only() {
+        let mut buffer = buffer(&["a a", "a"]);
+
+        let range = all(&buffer);
+
+        let substitute = substitute(&mut buffer, "/a//gn", range);
+
+        assert_eq!(buffer.lines_text(0, 2), "a a\na");
+        assert_eq!(substitute.message(), "3 matches on 2 lines");
+    }
+
+    #[test]
+    fn line_breaks() {
+        let mut buffer = buffer(&["a,b", "c,d"]);
+
+        let range = all(&buffer);
+
+        substitute(&mut buffer, r"/,/\r/g", range);
+
+        assert_eq!(buffer.lines_text(0, 4), "a\nb\nc\nd");
+    }
+
+    #[test]
+    fn empty_matches() {
+        let mut buffer = buffer(&["abc"]);
+
+        let range = all(&buffer);
+
+        substitute(&mut buffer, "/b*/-/g", range);
+
+        assert_eq!(buffer.lines_text(0, 1), "-a-c-");
+    }
+
+    #[test]
+    fn other_delimiter() {
+        let mut buffer = buffer(&["a/b"]);
+
+        let range = all(&buffer);
+
+        substitute(&mut buffer, r"#/#\##", range);
+
+        assert_eq!(buffer.lines_text(0, 1), "a#b");
+    }
+
+    #[test]
+    fn confirm_each_match() {
+        let mut buffer = buffer(&["a a a"]);
+        let mut substitute = Substitute::parse("/a/x/gc", LineRange::line(0), None).unwrap();
+
+        assert_eq!(substitute.next(&buffer), Some(Vec2::new(0, 0)));
+        substitute.skip(&buffer);
+        assert_eq!(substitute.next(&buffer), Some(Vec2::new(2, 0)));
+        substitute.accept(&mut buffer);
+        assert_eq!(substitute.next(&buffer), Some(Vec2::new(4, 0)));
+        substitute.skip(&buffer);
+        assert_eq!(substitute.next(&buffer), None);
+
+        assert_eq!(buffer.lines_text(0, 1), "a x a");
+        assert_eq!(substitute.message(), "1 substitution on 1 line");
+    }
+
+    #[test]
+    fn parse_errors() {
+        let range = LineRange::line(0);
+
+        assert!(Substitute::parse("a/b/", range, None).is_err());
+        assert!(Substitute::parse("//b/", range, None).is_err());
+        assert!(Substitute::parse("/(/b/", range, None).is_err());
+        assert!(Substitute::parse("/a/b/x", range, None).is_err());
+        assert!(Substitute::parse("/a/b/", range, None).is_ok());
+    }
+
+    #[test]
+    fn pattern_not_found() {
+        let mut buffer = buffer(&["a"]);
+
+        let range = all(&buffer);
+
+        let substitute = substitute(&mut buffer, "/b/c/", range);
+
+        assert_eq!(substitute.message(), "E486: Pattern not found: b");
+    }
+}
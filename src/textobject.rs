@@ -0,0 +1,693 @@
+use std::sync::OnceLock;
+
+use regex::Regex;
+use termion::event::Key;
+
+use crate::buffer::{Buffer, CharClass};
+use crate::motion::{MotionKind, Range};
+use crate::visual::{Selection, VisualKind};
+use crate::{Mode, State, Vec2};
+
+/// Text around the cursor selected after `i` or `a`, by an operator or in
+/// Visual mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextObject {
+    Word {
+        bigword: bool,
+    },
+    Sentence,
+    Paragraph,
+    /// Quoted text within the line.
+    Quote(char),
+    Bracket {
+        open: char,
+        close: char,
+    },
+    /// Contents of an XML or HTML element.
+    Tag,
+}
+
+impl TextObject {
+    pub fn from_key(key: Key) -> Option<TextObject> {
+        let bracket = |open, close| Some(TextObject::Bracket { open, close });
+
+        match key {
+            Key::Char('w') => Some(TextObject::Word { bigword: false }),
+            Key::Char('W') => Some(TextObject::Word { bigword: true }),
+            Key::Char('s') => Some(TextObject::Sentence),
+            Key::Char('p') => Some(TextObject::Paragraph),
+            Key::Char(c @ ('"' | '\'' | '`')) => Some(TextObject::Quote(c)),
+            Key::Char('(' | ')' | 'b') => bracket('(', ')'),
+            Key::Char('[' | ']') => bracket('[', ']'),
+            Key::Char('{' | '}' | 'B') => bracket('{', '}'),
+            Key::Char('<' | '>') => bracket('<', '>'),
+            Key::Char('t') => Some(TextObject::Tag),
+            _ => None,
+        }
+    }
+
+    /// Text covered by the object around the cursor, as an exclusive or
+    /// linewise range. `inner` leaves out the white space, quotes, brackets
+    /// or tags around the object. Returns `None` when there is no such
+    /// object around the cursor.
+    pub fn range(&self, buffer: &Buffer, inner: bool, count: usize) -> Option<Range> {
+        match *self {
+            TextObject::Word { bigword } => word(buffer, bigword, inner, count),
+            TextObject::Sentence => sentence(buffer, inner, count),
+            TextObject::Paragraph => paragraph(buffer, inner, count),
+            TextObject::Quote(quote) => self::quote(buffer, quote, inner),
+            TextObject::Bracket { open, close } => bracket(buffer, open, close, inner, count),
+            TextObject::Tag => tag(buffer, inner, count),
+        }
+    }
+}
+
+/// Lines `first..last` joined with `'\n'`, for objects that can span lines.
+struct Text {
+    text: String,
+    first: usize,
+    line_starts: Vec<usize>,
+}
+
+impl Text {
+    fn new(buffer: &Buffer, first: usize, last: usize) -> Self {
+        let text = buffer.lines_text(first, last - first);
+        let mut line_starts = vec![0];
+        line_starts.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+
+        Self {
+            text,
+            first,
+            line_starts,
+        }
+    }
+
+    fn offset(&self, pos: Vec2) -> usize {
+        (self.line_starts[pos.y - self.first] + pos.x).min(self.text.len())
+    }
+
+    fn pos(&self, offset: usize) -> Vec2 {
+        let y = self.line_starts.partition_point(|start| *start <= offset) - 1;
+        Vec2::new(offset - self.line_starts[y], self.first + y)
+    }
+}
+
+/// Bounds of `count` runs of items starting with the one at `at`, where
+/// `same` tells whether two items belong to the same run, as done by `iw`
+/// and `ip`. Outside of `inner` objects, every run also takes the blank run
+/// after it, or the run after it when it is blank itself, and the blank run
+/// before the object is taken when nothing blank ends it.
+fn runs(
+    len: usize,
+    at: usize,
+    blank: impl Fn(usize) -> bool,
+    same: impl Fn(usize, usize) -> bool,
+    inner: bool,
+    count: usize,
+) -> (usize, usize) {
+    let run_end = |from: usize| {
+        let mut i = from + 1;
+        while i < len && same(from, i) {
+            i += 1;
+        }
+        i
+    };
+
+    let mut start = at;
+    while start > 0 && same(at, start - 1) {
+        start -= 1;
+    }
+
+    let mut end = at;
+    let mut ends_blank = false;
+    for _ in 0..count {
+        if end >= len {
+            break;
+        }
+
+        ends_blank = blank(end);
+        end = run_end(end);
+        if !inner && end < len && (ends_blank || blank(end)) {
+            ends_blank = blank(end);
+            end = run_end(end);
+        }
+    }
+
+    if !inner && !ends_blank && !blank(at) {
+        while start > 0 && blank(start - 1) {
+            start -= 1;
+        }
+    }
+
+    (start, end)
+}
+
+/// Lines around `y` that are all blank or all non-blank, as `first..last`.
+fn line_run(buffer: &Buffer, y: usize) -> (usize, usize) {
+    let blank = |y| buffer.line(y).trim().is_empty();
+    runs(
+        buffer.line_count(),
+        y,
+        blank,
+        |a, b| blank(a) == blank(b),
+        true,
+        1,
+    )
+}
+
+fn word(buffer: &Buffer, bigword: bool, inner: bool, count: usize) -> Option<Range> {
+    let cursor = buffer.cursor();
+    let line = buffer.line(cursor.y);
+    let chars: Vec<(usize, char)> = line.char_indices().collect();
+    if chars.is_empty() {
+        return None;
+    }
+
+    let class = |i: usize| CharClass::of(chars[i].1, bigword);
+    let at = chars
+        .iter()
+        .position(|(x, _)| *x >= cursor.x)
+        .unwrap_or(chars.len() - 1);
+    let (start, end) = runs(
+        chars.len(),
+        at,
+        |i| class(i) == CharClass::Blank,
+        |a, b| class(a) == class(b),
+        inner,
+        count,
+    );
+
+    let end_x = chars.get(end).map_or(line.len(), |(x, _)| *x);
+    Some(Range::new(
+        Vec2::new(chars[start].0, cursor.y),
+        Vec2::new(end_x, cursor.y),
+        MotionKind::Exclusive,
+    ))
+}
+
+/// Sentences end with `.`, `!` or `?`, optionally followed by closing
+/// brackets or quotes, and then by white space. They never cross a
+/// paragraph boundary.
+fn sentence(buffer: &Buffer, inner: bool, count: usize) -> Option<Range> {
+    let cursor = buffer.cursor();
+    let (first, last) = line_run(buffer, cursor.y);
+    let text = Text::new(buffer, first, last);
+    let chars: Vec<(usize, char)> = text.text.char_indices().collect();
+    let byte = |i: usize| chars.get(i).map_or(text.text.len(), |(x, _)| *x);
+
+    // (start, end, blank) of every sentence and of the white space between
+    let mut items = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let blank = chars[i].1.is_whitespace();
+        let mut j = i;
+        if blank {
+            while j < chars.len() && chars[j].1.is_whitespace() {
+                j += 1;
+            }
+        } else {
+            while j < chars.len() {
+                let c = chars[j].1;
+                j += 1;
+
+                if ".!?".contains(c) {
+                    while j < chars.len() && ")]\"'".contains(chars[j].1) {
+                        j += 1;
+                    }
+                    if chars.get(j).is_none_or(|(_, c)| c.is_whitespace()) {
+                        break;
+                    }
+                }
+            }
+        }
+
+        items.push((byte(i), byte(j), blank));
+        i = j;
+    }
+    if items.is_empty() {
+        return None;
+    }
+
+    let offset = text.offset(cursor);
+    let at = items
+        .iter()
+        .position(|(_, end, _)| *end > offset)
+        .unwrap_or(items.len() - 1);
+    let (start, end) = runs(items.len(), at, |i| items[i].2, |a, b| a == b, inner, count);
+
+    Some(Range::new(
+        text.pos(items[start].0),
+        text.pos(items[end - 1].1),
+        MotionKind::Exclusive,
+    ))
+}
+
+fn paragraph(buffer: &Buffer, inner: bool, count: usize) -> Option<Range> {
+    let blank = |y| buffer.line(y).trim().is_empty();
+    let (start, end) = runs(
+        buffer.line_count(),
+        buffer.cursor().y,
+        blank,
+        |a, b| blank(a) == blank(b),
+        inner,
+        count,
+    );
+
+    Some(Range::new(
+        Vec2::new(0, start),
+        Vec2::new(0, end - 1),
+        MotionKind::Linewise,
+    ))
+}
+
+/// Like vim, the quotes are paired from the start of the line when the
+/// cursor is on one of them. Otherwise the closest quotes around the cursor
+/// are used, or the first quoted text after it.
+fn quote(buffer: &Buffer, quote: char, inner: bool) -> Option<Range> {
+    let cursor = buffer.cursor();
+    let line = buffer.line(cursor.y);
+
+    let mut quotes = Vec::new();
+    let mut escaped = false;
+    for (x, c) in line.char_indices() {
+        if c == quote && !escaped {
+            quotes.push(x);
+        }
+        escaped = c == '\\' && !escaped;
+    }
+
+    let (open, close) = match quotes.iter().position(|x| *x == cursor.x) {
+        Some(i) if i % 2 == 0 => (*quotes.get(i)?, *quotes.get(i + 1)?),
+        Some(i) => (quotes[i - 1], quotes[i]),
+        None => {
+            let after = quotes.iter().position(|x| *x > cursor.x)?;
+            if after > 0 {
+                (quotes[after - 1], quotes[after])
+            } else {
+                (quotes[after], *quotes.get(after + 1)?)
+            }
+        }
+    };
+
+    let (start, end) = if inner {
+        (open + 1, close)
+    } else {
+        let is_blank = |c: char| c == ' ' || c == '\t';
+        let trailing =
+            line[close + 1..].len() - line[close + 1..].trim_start_matches(is_blank).len();
+        if trailing > 0 {
+            (open, close + 1 + trailing)
+        } else {
+            (line[..open].trim_end_matches(is_blank).len(), close + 1)
+        }
+    };
+
+    Some(Range::new(
+        Vec2::new(start, cursor.y),
+        Vec2::new(end, cursor.y),
+        MotionKind::Exclusive,
+    ))
+}
+
+/// Like vim, the inner text of a block whose brackets are on lines of their
+/// own covers the lines between them.
+fn bracket(buffer: &Buffer, open: char, close: char, inner: bool, count: usize) -> Option<Range> {
+    let cursor = buffer.cursor();
+    let lines = 0..=buffer.line_count() - 1;
+
+    // the bracket under the cursor only counts when it opens the block
+    let under = buffer.char_at(cursor).filter(|c| *c == open);
+    let mut depth = 0;
+    let mut remaining = count;
+    let (start, _) = under
+        .map(|c| (cursor, c))
+        .into_iter()
+        .chain(buffer.chars_around(cursor, false, lines.clone()))
+        .find(|&(_, c)| {
+            if c == close {
+                depth += 1;
+            } else if c == open {
+                if depth == 0 {
+                    remaining -= 1;
+                    return remaining == 0;
+                }
+                depth -= 1;
+            }
+            false
+        })?;
+
+    let mut depth = 0;
+    let (end, _) = buffer.chars_around(start, true, lines).find(|&(_, c)| {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            if depth == 0 {
+                return true;
+            }
+            depth -= 1;
+        }
+        false
+    })?;
+
+    if !inner {
+        return Some(Range::new(
+            start,
+            Vec2::new(end.x + close.len_utf8(), end.y),
+            MotionKind::Exclusive,
+        ));
+    }
+
+    let open_ends_line = start.x + open.len_utf8() == buffer.line_len(start.y);
+    let close_starts_line = end.y > start.y && buffer.line(end.y)[..end.x].trim().is_empty();
+
+    if open_ends_line && close_starts_line && end.y > start.y + 1 {
+        return Some(Range::new(
+            Vec2::new(0, start.y + 1),
+            Vec2::new(0, end.y - 1),
+            MotionKind::Linewise,
+        ));
+    }
+
+    let inner_start = if open_ends_line {
+        Vec2::new(0, start.y + 1)
+    } else {
+        Vec2::new(start.x + open.len_utf8(), start.y)
+    };
+    let inner_end = if close_starts_line {
+        Vec2::new(buffer.line_len(end.y - 1), end.y - 1)
+    } else {
+        end
+    };
+
+    Some(Range::new(
+        inner_start,
+        inner_end.max(inner_start),
+        MotionKind::Exclusive,
+    ))
+}
+
+/// Lines looked at on each side of the cursor for the elements around it,
+/// doubled until enough of them are found or the whole buffer is looked at.
+const TAG_LINES: usize = 64;
+
+fn tag(buffer: &Buffer, inner: bool, count: usize) -> Option<Range> {
+    let y = buffer.cursor().y;
+    let mut lines = TAG_LINES;
+    loop {
+        let first = y.saturating_sub(lines);
+        let last = y.saturating_add(lines + 1).min(buffer.line_count());
+        let range = tag_within(buffer, first, last, inner, count);
+        if range.is_some() || (first == 0 && last == buffer.line_count()) {
+            return range;
+        }
+        lines = lines.saturating_mul(2);
+    }
+}
+
+/// Like `tag`, looking only at lines `first..last`.
+fn tag_within(
+    buffer: &Buffer,
+    first: usize,
+    last: usize,
+    inner: bool,
+    count: usize,
+) -> Option<Range> {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    let regex = REGEX.get_or_init(|| {
+        Regex::new(r"<(/?)([A-Za-z][^\s/>]*)[^>]*>").expect("tag pattern is valid")
+    });
+
+    let text = Text::new(buffer, first, last);
+    let offset = text.offset(buffer.cursor());
+
+    // Elements as (start, inner start, inner end, end), with unclosed tags
+    // ignored.
+    let mut open: Vec<(&str, usize, usize)> = Vec::new();
+    let mut elements = Vec::new();
+    for captures in regex.captures_iter(&text.text) {
+        let tag = captures.get(0).expect("whole match");
+        let name = captures.get(2).expect("tag name").as_str();
+        if tag.as_str().ends_with("/>") {
+            continue;
+        }
+
+        if captures[1].is_empty() {
+            open.push((name, tag.start(), tag.end()));
+        } else if let Some(i) = open.iter().rposition(|(open, ..)| *open == name) {
+            let (_, start, inner_start) = open[i];
+            open.truncate(i);
+            elements.push((start, inner_start, tag.start(), tag.end()));
+        }
+    }
+
+    let mut around: Vec<_> = elements
+        .into_iter()
+        .filter(|(start, .., end)| *start <= offset && offset < *end)
+        .collect();
+    around.sort_by_key(|(start, .., end)| end - start);
+
+    let (start, inner_start, inner_end, end) = *around.get(count - 1)?;
+    let (start, end) = if inner {
+        (inner_start, inner_end)
+    } else {
+        (start, end)
+    };
+
+    Some(Range::new(
+        text.pos(start),
+        text.pos(end),
+        MotionKind::Exclusive,
+    ))
+}
+
+impl State {
+    /// Applies the pending operator to a text object, or selects it in
+    /// Visual mode.
+    pub fn text_object(&mut self, object: TextObject, inner: bool) {
+        let count = self.count().unwrap_or(1);
+        let range = object.range(&self.buffer, inner, count);
+
+        match (self.mode, range) {
+            (Mode::OperatorPending(operator), Some(range)) => {
+                self.mode = Mode::Normal;
+                self.apply_operator(operator, range);
+            }
+            (Mode::OperatorPending(_), None) => {
+                self.mode = Mode::Normal;
+                self.failed = true;
+            }
+            (Mode::Visual(_), Some(range)) => self.select(range),
+            _ => self.failed = true,
+        }
+    }
+
+    /// Selects an exclusive or linewise range in Visual mode.
+    fn select(&mut self, range: Range) {
+        let (kind, anchor, cursor) = if range.kind == MotionKind::Linewise {
+            (VisualKind::Line, range.start, range.end)
+        } else {
+            let mut end = range.end;
+            if end > range.start {
+                if end.x == 0 {
+                    end.y -= 1;
+                    end.x = self.buffer.line_len(end.y);
+                }
+                end.x = self.buffer.line(end.y)[..end.x]
+                    .char_indices()
+                    .next_back()
+                    .map_or(0, |(x, _)| x);
+            }
+            (VisualKind::Char, range.start, end)
+        };
+
+        self.buffer.set_selection(Selection { anchor, kind });
+        self.buffer.set_cursor(cursor);
+        self.mode = Mode::Visual(kind);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(lines: &[&str], cursor: Vec2) -> Buffer {
+        let lines = lines.iter().map(|line| line.to_string()).collect();
+        let mut buffer = Buffer::from_lines(lines, Vec2::default(), Vec2::new(100, 100), true);
+        buffer.set_cursor(cursor);
+        buffer
+    }
+
+    fn text(buffer: &Buffer, object: TextObject, inner: bool, count: usize) -> Option<String> {
+        let range = object.range(buffer, inner, count)?;
+        Some(match range.kind {
+            MotionKind::Linewise => {
+                buffer.lines_text(range.start.y, range.end.y - range.start.y + 1)
+            }
+            _ => buffer.text_between(range.start, range.end),
+        })
+    }
+
+    const WORD: TextObject = TextObject::Word { bigword: false };
+    const PARENS: TextObject = TextObject::Bracket {
+        open: '(',
+        close: ')',
+    };
+
+    #[test]
+    fn word() {
+        let buffer = buffer(&["one two.three  four"], Vec2::new(5, 0));
+
+        assert_eq!(text(&buffer, WORD, true, 1).unwrap(), "two");
+        assert_eq!(text(&buffer, WORD, true, 3).unwrap(), "two.three");
+        assert_eq!(text(&buffer, WORD, false, 1).unwrap(), " two");
+        let bigword = TextObject::Word { bigword: true };
+        assert_eq!(text(&buffer, bigword, false, 1).unwrap(), "two.three  ");
+    }
+
+    #[test]
+    fn word_on_blank() {
+        let buffer = buffer(&["one   two three"], Vec2::new(4, 0));
+
+        assert_eq!(text(&buffer, WORD, true, 1).unwrap(), "   ");
+        assert_eq!(text(&buffer, WORD, false, 1).unwrap(), "   two");
+    }
+
+    #[test]
+    fn sentence() {
+        let buffer = buffer(
+            &[
+                "First one. Second with parens",
+                "lines.  Third?",
+                "",
+                "Next.",
+            ],
+            Vec2::new(3, 1),
+        );
+
+        assert_eq!(
+            text(&buffer, TextObject::Sentence, true, 1).unwrap(),
+            "Second with parens\nlines."
+        );
+        assert_eq!(
+            text(&buffer, TextObject::Sentence, false, 1).unwrap(),
+            "Second with parens\nlines.  "
+        );
+        assert_eq!(
+            text(&buffer, TextObject::Sentence, false, 2).unwrap(),
+            " Second with parens\nlines.  Third?"
+        );
+    }
+
+    #[test]
+    fn paragraph() {
+        let buffer = buffer(&["a", "b", "", "  ", "c", "", "d"], Vec2::new(0, 1));
+
+        assert_eq!(
+            text(&buffer, TextObject::Paragraph, true, 1).unwrap(),
+            "a\nb"
+        );
+        assert_eq!(
+            text(&buffer, TextObject::Paragraph, false, 1).unwrap(),
+            "a\nb\n\n  "
+        );
+        assert_eq!(
+            text(&buffer, TextObject::Paragraph, true, 3).unwrap(),
+            "a\nb\n\n  \nc"
+        );
+    }
+
+    #[test]
+    fn paragraph_at_end_takes_blank_lines_before() {
+        let buffer = buffer(&["a", "", "b"], Vec2::new(0, 2));
+
+        assert_eq!(
+            text(&buffer, TextObject::Paragraph, false, 1).unwrap(),
+            "\nb"
+        );
+    }
+
+    #[test]
+    fn quote() {
+        let object = TextObject::Quote('"');
+        let escaped = buffer(&[r#"say "a \"b\"" and "c""#], Vec2::new(6, 0));
+
+        assert_eq!(text(&escaped, object, true, 1).unwrap(), r#"a \"b\""#);
+        assert_eq!(text(&escaped, object, false, 1).unwrap(), r#""a \"b\"" "#);
+
+        let after = buffer(&[r#"x = "c""#], Vec2::new(0, 0));
+        assert_eq!(text(&after, object, true, 1).unwrap(), "c");
+        assert_eq!(text(&after, object, false, 1).unwrap(), r#" "c""#);
+
+        let none = buffer(&["no quotes"], Vec2::new(0, 0));
+        assert_eq!(text(&none, object, true, 1), None);
+    }
+
+    #[test]
+    fn bracket() {
+        let nested = buffer(&["f(a, (b), c)"], Vec2::new(6, 0));
+
+        assert_eq!(text(&nested, PARENS, true, 1).unwrap(), "b");
+        assert_eq!(text(&nested, PARENS, false, 1).unwrap(), "(b)");
+        assert_eq!(text(&nested, PARENS, true, 2).unwrap(), "a, (b), c");
+        assert_eq!(text(&nested, PARENS, true, 3), None);
+
+        let on_open = buffer(&["f(a, (b), c)"], Vec2::new(1, 0));
+        assert_eq!(text(&on_open, PARENS, false, 1).unwrap(), "(a, (b), c)");
+        let on_close = buffer(&["f(a, (b), c)"], Vec2::new(7, 0));
+        assert_eq!(text(&on_close, PARENS, false, 1).unwrap(), "(b)");
+    }
+
+    #[test]
+    fn bracket_multi_line() {
+        let braces = TextObject::Bracket {
+            open: '{',
+            close: '}',
+        };
+        let block = buffer(&["fn f() {", "    a;", "    b;", "}"], Vec2::new(4, 1));
+
+        let range = braces.range(&block, true, 1).unwrap();
+        assert_eq!(range.kind, MotionKind::Linewise);
+        assert_eq!(text(&block, braces, true, 1).unwrap(), "    a;\n    b;");
+        assert_eq!(
+            text(&block, braces, false, 1).unwrap(),
+            "{\n    a;\n    b;\n}"
+        );
+
+        let parens = buffer(&["(a", "b)"], Vec2::new(0, 1));
+        assert_eq!(text(&parens, PARENS, true, 1).unwrap(), "a\nb");
+    }
+
+    #[test]
+    fn tag() {
+        let buffer = buffer(
+            &["<div class=\"x\"><p>one <br/>", "two</p></div>"],
+            Vec2::new(20, 0),
+        );
+
+        assert_eq!(
+            text(&buffer, TextObject::Tag, true, 1).unwrap(),
+            "one <br/>\ntwo"
+        );
+        assert_eq!(
+            text(&buffer, TextObject::Tag, false, 1).unwrap(),
+            "<p>one <br/>\ntwo</p>"
+        );
+        assert_eq!(
+            text(&buffer, TextObject::Tag, true, 2).unwrap(),
+            "<p>one <br/>\ntwo</p>"
+        );
+        assert_eq!(text(&buffer, TextObject::Tag, true, 3), None);
+    }
+
+    #[test]
+    fn tag_far_from_cursor() {
+        let mut lines = vec!["<a>"];
+        lines.extend(["x"; 300]);
+        lines.push("</a>");
+        let buffer = buffer(&lines, Vec2::new(0, 250));
+
+        let range = TextObject::Tag.range(&buffer, false, 1).unwrap();
+        assert_eq!(range.start, Vec2::new(0, 0));
+        assert_eq!(range.end, Vec2::new(4, 301));
+    }
+}
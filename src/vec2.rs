@@ -1,4 +1,6 @@
-#[derive(Default, Debug, PartialEq, Eq)]
+use std::cmp::Ordering;
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Vec2 {
     pub x: usize,
     pub y: usize,
@@ -15,3 +17,16 @@ impl From<(u16, u16)> for Vec2 {
         Self::new(tuple.0 as usize, tuple.1 as usize)
     }
 }
+
+/// Positions are ordered as they appear in a buffer: by line, then column.
+impl PartialOrd for Vec2 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Vec2 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.y, self.x).cmp(&(other.y, other.x))
+    }
+}
@@ -0,0 +1,297 @@
+use termion::event::Key;
+
+use crate::buffer::Buffer;
+use crate::motion::{MotionKind, Range};
+use crate::operator::Operator;
+use crate::{Mode, Pending, State, Vec2};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VisualKind {
+    /// Started with `v`.
+    Char,
+    /// Started with `V`.
+    Line,
+    /// Started with `Ctrl-V`.
+    Block,
+}
+
+/// Text selected in Visual mode, between `anchor` and the cursor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Selection {
+    pub anchor: Vec2,
+    pub kind: VisualKind,
+}
+
+impl Selection {
+    /// Text selected up to the cursor of `buffer`. A block spans the display
+    /// columns of the characters under both ends, as the same byte offset
+    /// falls elsewhere on lines with multibyte or wide characters.
+    pub fn range(&self, buffer: &Buffer) -> Range {
+        let cursor = buffer.cursor();
+
+        match self.kind {
+            VisualKind::Char => Range::new(self.anchor, cursor, MotionKind::Inclusive),
+            VisualKind::Line => Range::new(self.anchor, cursor, MotionKind::Linewise),
+            VisualKind::Block => {
+                // the last column of an empty line is the one it starts at
+                let left = |pos| buffer.byte_to_col(pos);
+                let right = |pos: Vec2| {
+                    let next = Vec2::new(buffer.next_grapheme(pos), pos.y);
+                    buffer.byte_to_col(next).max(left(pos) + 1) - 1
+                };
+
+                Range {
+                    start: Vec2::new(
+                        left(self.anchor).min(left(cursor)),
+                        self.anchor.y.min(cursor.y),
+                    ),
+                    end: Vec2::new(
+                        right(self.anchor).max(right(cursor)),
+                        self.anchor.y.max(cursor.y),
+                    ),
+                    kind: MotionKind::Blockwise,
+                }
+            }
+        }
+    }
+}
+
+/// Text typed in Insert mode after `I`, `A` or `c` on a block, repeated on
+/// the other lines of the block once Insert mode is left.
+#[derive(Debug)]
+pub struct BlockInsert {
+    pos: Vec2,
+    /// Display column the text goes at on every line.
+    col: usize,
+    last_line: usize,
+    /// Length of the first line before anything was typed.
+    line_len: usize,
+    /// Whether lines shorter than the block are padded with spaces, as
+    /// done by `A`, instead of being skipped.
+    pad: bool,
+}
+
+impl State {
+    pub fn start_visual(&mut self, kind: VisualKind) {
+        self.buffer.start_selection(kind);
+        self.mode = Mode::Visual(kind);
+    }
+
+    /// Leaves Visual mode, remembering the selection in the `<` and `>`
+    /// marks.
+    pub fn exit_visual(&mut self) {
+        if let Some(selection) = self.buffer.selection() {
+            let range = selection.range(&self.buffer);
+            let (first, last) = (range.first(&self.buffer), range.last(&self.buffer));
+            self.buffer.marks_mut().set('<', first);
+            self.buffer.marks_mut().set('>', last);
+        }
+        self.buffer.clear_selection();
+        self.mode = Mode::Normal;
+    }
+
+    /// Handles a key typed in Visual mode that isn't a motion.
+    pub fn update_visual(&mut self, kind: VisualKind, key: Key, count: Option<usize>) -> bool {
+        let selection = match self.buffer.selection() {
+            Some(selection) => selection,
+            None => return true,
+        };
+        let range = selection.range(&self.buffer);
+
+        let operator = match key {
+            Key::Char('x') => Some(Operator::Delete),
+            Key::Char('s') => Some(Operator::Change),
+            Key::Char('~') => Some(Operator::ToggleCase),
+            Key::Char('u') => Some(Operator::Lowercase),
+            Key::Char('U') => Some(Operator::Uppercase),
+            key => Operator::from_key(key),
+        };
+        if let Some(operator) = operator {
+            self.visual_operate(operator, count);
+            return true;
+        }
+
+        match key {
+            Key::Esc => self.exit_visual(),
+            Key::Char('v') => self.switch_visual(kind, VisualKind::Char),
+            Key::Char('V') => self.switch_visual(kind, VisualKind::Line),
+            Key::Ctrl('v') => self.switch_visual(kind, VisualKind::Block),
+            Key::Char('o') => self.buffer.swap_selection_ends(false),
+            Key::Char('O') => self.buffer.swap_selection_ends(kind == VisualKind::Block),
+            Key::Char('J') => {
+                self.exit_visual();
+                self.buffer.set_cursor(range.first(&self.buffer));
+                for _ in 0..(range.end.y - range.start.y).max(1) {
+                    self.buffer.join_line();
+                }
+            }
+            Key::Char('r') => {
+                self.pending = Some(Pending::Replace);
+                return false;
+            }
+            Key::Char('"') => {
+                self.pending = Some(Pending::Register);
+                return false;
+            }
+            Key::Char('I') if kind == VisualKind::Block => {
+                self.exit_visual();
+                self.start_block_insert(range.start.x, range.start.y, range.end.y, false);
+            }
+            Key::Char('A') if kind == VisualKind::Block => {
+                self.exit_visual();
+                self.start_block_insert(range.end.x + 1, range.start.y, range.end.y, true);
+            }
+            _ => {}
+        }
+
+        true
+    }
+
+    /// Applies `operator` to the selection, where a count shifts lines
+    /// several times.
+    pub fn visual_operate(&mut self, operator: Operator, count: Option<usize>) {
+        let selection = match self.buffer.selection() {
+            Some(selection) => selection,
+            None => return,
+        };
+        let range = selection.range(&self.buffer);
+        self.exit_visual();
+
+        match operator {
+            Operator::ShiftRight | Operator::ShiftLeft => self.shift_lines(
+                range.start.y,
+                range.end.y,
+                operator == Operator::ShiftRight,
+                count.unwrap_or(1),
+            ),
+            _ => self.apply_operator(operator, range),
+        }
+    }
+
+    /// Replaces every selected character with `c`.
+    pub fn visual_replace(&mut self, c: char) {
+        let selection = match self.buffer.selection() {
+            Some(selection) => selection,
+            None => return,
+        };
+        let range = selection.range(&self.buffer);
+        self.exit_visual();
+
+        if c != '\n' {
+            self.map_chars(range, |_, text| text.push(c));
+        }
+    }
+
+    fn switch_visual(&mut self, from: VisualKind, to: VisualKind) {
+        if from == to {
+            self.exit_visual();
+        } else {
+            self.start_visual(to);
+        }
+    }
+
+    /// Starts Insert mode at display column `col` of line `y`, padding the
+    /// line when needed, to insert the same text on every line down to
+    /// `last_line`.
+    pub fn start_block_insert(&mut self, col: usize, y: usize, last_line: usize, pad: bool) {
+        let pos = Vec2::new(self.buffer.pad_to_col(y, col), y);
+
+        self.block_insert = Some(BlockInsert {
+            pos,
+            col,
+            last_line,
+            line_len: self.buffer.line_len(pos.y),
+            pad,
+        });
+        self.buffer.set_insert_cursor(pos);
+        self.mode = Mode::Insert;
+    }
+
+    /// Repeats the text typed on the first line of a block on its other
+    /// lines. Nothing is repeated when a line break was typed.
+    pub fn finish_block_insert(&mut self) {
+        let block = match self.block_insert.take() {
+            Some(block) => block,
+            None => return,
+        };
+
+        let len = self.buffer.line_len(block.pos.y);
+        if self.buffer.cursor().y != block.pos.y || len <= block.line_len {
+            return;
+        }
+
+        let end = Vec2::new(block.pos.x + len - block.line_len, block.pos.y);
+        let text = self.buffer.text_between(block.pos, end);
+
+        for y in block.pos.y + 1..=block.last_line {
+            let len = self.buffer.line_len(y);
+            if !block.pad && self.buffer.byte_to_col(Vec2::new(len, y)) < block.col {
+                continue;
+            }
+
+            let x = self.buffer.pad_to_col(y, block.col);
+            self.buffer.insert_text(Vec2::new(x, y), &text);
+        }
+
+        self.buffer.set_cursor(block.pos);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(lines: &[&str], cursor: Vec2) -> Buffer {
+        let lines = lines.iter().map(|line| line.to_string()).collect();
+        let mut buffer = Buffer::from_lines(lines, Vec2::default(), Vec2::new(80, 24), false);
+        buffer.set_cursor(cursor);
+        buffer
+    }
+
+    #[test]
+    fn block_range_uses_corners() {
+        let selection = Selection {
+            anchor: Vec2::new(1, 3),
+            kind: VisualKind::Block,
+        };
+
+        let range = selection.range(&buffer(&["0123456"; 4], Vec2::new(4, 1)));
+
+        assert_eq!(range.start, Vec2::new(1, 1));
+        assert_eq!(range.end, Vec2::new(4, 3));
+    }
+
+    #[test]
+    fn columns() {
+        let buffer = buffer(&["0123456"; 3], Vec2::new(1, 2));
+        let selection = |kind| Selection {
+            anchor: Vec2::new(3, 0),
+            kind,
+        };
+        let columns = |kind, y| selection(kind).range(&buffer).columns(&buffer, y);
+
+        assert_eq!(columns(VisualKind::Char, 0), Some((3, 7)));
+        assert_eq!(columns(VisualKind::Char, 2), Some((0, 2)));
+        assert_eq!(columns(VisualKind::Line, 0), Some((0, 7)));
+        assert_eq!(columns(VisualKind::Block, 1), Some((1, 4)));
+    }
+
+    #[test]
+    fn block_columns_on_multibyte_and_wide_characters() {
+        let buffer = buffer(&["aéb", "中文字", "\tx", "abc"], Vec2::new(2, 3));
+        let selection = Selection {
+            anchor: Vec2::new(1, 0),
+            kind: VisualKind::Block,
+        };
+        let range = selection.range(&buffer);
+
+        // from "é" to "c", at display columns 1 to 2
+        assert_eq!(range.start, Vec2::new(1, 0));
+        assert_eq!(range.end, Vec2::new(2, 3));
+        assert_eq!(range.columns(&buffer, 0), Some((1, 4)));
+        assert_eq!(range.columns(&buffer, 1), Some((0, 6)));
+        assert_eq!(range.columns(&buffer, 2), Some((0, 1)));
+        assert_eq!(range.columns(&buffer, 3), Some((1, 3)));
+        assert_eq!(range.last(&buffer), Vec2::new(2, 3));
+    }
+}